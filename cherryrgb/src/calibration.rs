@@ -0,0 +1,81 @@
+//! Color calibration of the LEDs
+//!
+//! Example (TOML):
+//! ```toml
+//! gamma = [1.0, 1.2, 1.6]
+//! white_point = [1.0, 0.9, 0.7]
+//! min_visible = 12
+//! ```
+
+use crate::{CherryRgbError, CustomKeyLeds, OwnRGB8};
+use rgb::RGB8;
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+
+/// Corrections applied to every color before it is sent to the keyboard
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Calibration {
+    /// Gamma of the red, green and blue channel, values above 1.0 darken low and mid values
+    pub gamma: [f32; 3],
+    /// Scale of the red, green and blue channel (0.0 - 1.0), to balance white
+    pub white_point: [f32; 3],
+    /// Lowest channel value the LEDs visibly render, lower values (except 0) are raised to it
+    pub min_visible: u8,
+}
+
+impl Default for Calibration {
+    fn default() -> Self {
+        Self {
+            gamma: [1.0; 3],
+            white_point: [1.0; 3],
+            min_visible: 0,
+        }
+    }
+}
+
+impl Calibration {
+    /// True, if colors are left unchanged
+    pub fn is_identity(&self) -> bool {
+        *self == Self::default()
+    }
+
+    /// Correct a single color
+    pub fn apply(&self, color: &OwnRGB8) -> OwnRGB8 {
+        let correct = |value: u8, channel: usize| {
+            if value == 0 {
+                return 0;
+            }
+            let linear = (value as f32 / 255.0).powf(self.gamma[channel].max(0.01));
+            let scaled = (linear * self.white_point[channel].clamp(0.0, 1.0) * 255.0).round() as u8;
+            scaled.max(self.min_visible)
+        };
+        let color: RGB8 = color.clone().into();
+        OwnRGB8::new(
+            correct(color.r, 0),
+            correct(color.g, 1),
+            correct(color.b, 2),
+        )
+    }
+
+    /// Correct all key colors
+    pub fn apply_leds(&self, key_leds: &CustomKeyLeds) -> CustomKeyLeds {
+        CustomKeyLeds::from_leds(key_leds.leds().iter().map(|c| self.apply(c)).collect()).unwrap()
+    }
+
+    pub fn from_toml(calibration: &str) -> Result<Self, CherryRgbError> {
+        toml::from_str(calibration)
+            .map_err(|err| CherryRgbError::ParseError(format!("parsing calibration, err={err}")))
+    }
+
+    pub fn to_toml(&self) -> String {
+        toml::to_string(self).unwrap()
+    }
+
+    /// Read calibration file
+    pub fn load(path: &Path) -> Result<Self, CherryRgbError> {
+        let toml = std::fs::read_to_string(path)
+            .map_err(|err| CherryRgbError::ParseError(format!("reading {:?}, err={err}", path)))?;
+        Self::from_toml(&toml)
+    }
+}
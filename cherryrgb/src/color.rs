@@ -0,0 +1,598 @@
+//! Color spaces and gradients
+//!
+//! Hue is given in degrees (0.0 - 360.0), all other components range from 0.0 to 1.0,
+//! except for OKLab's `a` and `b`, which are roughly within -0.4 - 0.4.
+
+use crate::OwnRGB8;
+use clap::ValueEnum;
+use rgb::RGB8;
+use serde::{Deserialize, Serialize};
+use std::f32::consts::PI;
+
+/// Hue, saturation, value
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Hsv {
+    pub h: f32,
+    pub s: f32,
+    pub v: f32,
+}
+
+/// Hue, saturation, lightness
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Hsl {
+    pub h: f32,
+    pub s: f32,
+    pub l: f32,
+}
+
+/// Perceptual color space by Björn Ottosson, see <https://bottosson.github.io/posts/oklab/>
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Oklab {
+    pub l: f32,
+    pub a: f32,
+    pub b: f32,
+}
+
+impl Hsv {
+    pub fn new(h: f32, s: f32, v: f32) -> Self {
+        Self { h, s, v }
+    }
+}
+
+impl Hsl {
+    pub fn new(h: f32, s: f32, l: f32) -> Self {
+        Self { h, s, l }
+    }
+}
+
+impl Oklab {
+    pub fn new(l: f32, a: f32, b: f32) -> Self {
+        Self { l, a, b }
+    }
+}
+
+/// Color components as 0.0 - 1.0
+fn to_unit(color: &OwnRGB8) -> [f32; 3] {
+    let color: RGB8 = color.clone().into();
+    [color.r, color.g, color.b].map(|c| c as f32 / 255.0)
+}
+
+fn from_unit([r, g, b]: [f32; 3]) -> OwnRGB8 {
+    let byte = |c: f32| (c.clamp(0.0, 1.0) * 255.0).round() as u8;
+    OwnRGB8::new(byte(r), byte(g), byte(b))
+}
+
+/// Hue (degrees) and chroma of a color
+fn hue_chroma([r, g, b]: [f32; 3]) -> (f32, f32, f32) {
+    let max = r.max(g).max(b);
+    let min = r.min(g).min(b);
+    let chroma = max - min;
+    let hue = if chroma == 0.0 {
+        0.0
+    } else if max == r {
+        60.0 * ((g - b) / chroma).rem_euclid(6.0)
+    } else if max == g {
+        60.0 * ((b - r) / chroma + 2.0)
+    } else {
+        60.0 * ((r - g) / chroma + 4.0)
+    };
+    (hue, chroma, max)
+}
+
+/// RGB components of hue and chroma, without the lightness offset
+fn from_hue_chroma(hue: f32, chroma: f32) -> [f32; 3] {
+    let h = hue.rem_euclid(360.0) / 60.0;
+    let x = chroma * (1.0 - (h.rem_euclid(2.0) - 1.0).abs());
+    match h as u32 {
+        0 => [chroma, x, 0.0],
+        1 => [x, chroma, 0.0],
+        2 => [0.0, chroma, x],
+        3 => [0.0, x, chroma],
+        4 => [x, 0.0, chroma],
+        _ => [chroma, 0.0, x],
+    }
+}
+
+impl From<&OwnRGB8> for Hsv {
+    fn from(color: &OwnRGB8) -> Self {
+        let (h, chroma, v) = hue_chroma(to_unit(color));
+        let s = if v == 0.0 { 0.0 } else { chroma / v };
+        Self { h, s, v }
+    }
+}
+
+impl From<Hsv> for OwnRGB8 {
+    fn from(hsv: Hsv) -> Self {
+        let (s, v) = (hsv.s.clamp(0.0, 1.0), hsv.v.clamp(0.0, 1.0));
+        let chroma = v * s;
+        from_unit(from_hue_chroma(hsv.h, chroma).map(|c| c + v - chroma))
+    }
+}
+
+impl From<&OwnRGB8> for Hsl {
+    fn from(color: &OwnRGB8) -> Self {
+        let (h, chroma, max) = hue_chroma(to_unit(color));
+        let l = max - chroma / 2.0;
+        let s = if l == 0.0 || l == 1.0 {
+            0.0
+        } else {
+            chroma / (1.0 - (2.0 * l - 1.0).abs())
+        };
+        Self { h, s, l }
+    }
+}
+
+impl From<Hsl> for OwnRGB8 {
+    fn from(hsl: Hsl) -> Self {
+        let (s, l) = (hsl.s.clamp(0.0, 1.0), hsl.l.clamp(0.0, 1.0));
+        let chroma = (1.0 - (2.0 * l - 1.0).abs()) * s;
+        from_unit(from_hue_chroma(hsl.h, chroma).map(|c| c + l - chroma / 2.0))
+    }
+}
+
+fn srgb_to_linear(c: f32) -> f32 {
+    if c <= 0.04045 {
+        c / 12.92
+    } else {
+        ((c + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+fn linear_to_srgb(c: f32) -> f32 {
+    if c <= 0.0031308 {
+        c * 12.92
+    } else {
+        1.055 * c.powf(1.0 / 2.4) - 0.055
+    }
+}
+
+impl From<&OwnRGB8> for Oklab {
+    fn from(color: &OwnRGB8) -> Self {
+        let [r, g, b] = to_unit(color).map(srgb_to_linear);
+        let l = (0.41222147 * r + 0.53633254 * g + 0.051445993 * b).cbrt();
+        let m = (0.2119035 * r + 0.6806995 * g + 0.10739696 * b).cbrt();
+        let s = (0.08830246 * r + 0.28171884 * g + 0.6299787 * b).cbrt();
+        Self {
+            l: 0.21045426 * l + 0.7936178 * m - 0.004072047 * s,
+            a: 1.9779985 * l - 2.4285922 * m + 0.4505937 * s,
+            b: 0.025904037 * l + 0.78277177 * m - 0.80867577 * s,
+        }
+    }
+}
+
+impl From<Oklab> for OwnRGB8 {
+    fn from(lab: Oklab) -> Self {
+        let l = (lab.l + 0.39633778 * lab.a + 0.21580376 * lab.b).powi(3);
+        let m = (lab.l - 0.105561346 * lab.a - 0.06385417 * lab.b).powi(3);
+        let s = (lab.l - 0.08948418 * lab.a - 1.2914855 * lab.b).powi(3);
+        let rgb = [
+            4.0767417 * l - 3.3077116 * m + 0.23096994 * s,
+            -1.268438 * l + 2.6097574 * m - 0.34131938 * s,
+            -0.0041960863 * l - 0.7034186 * m + 1.7076147 * s,
+        ];
+        from_unit(rgb.map(|c| linear_to_srgb(c.clamp(0.0, 1.0))))
+    }
+}
+
+/// Color space colors are interpolated in
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum, Serialize, Deserialize)]
+#[clap(rename_all = "snake_case")]
+#[serde(rename_all = "snake_case")]
+pub enum ColorSpace {
+    /// Straight line between the RGB values
+    #[default]
+    Rgb,
+    /// Along the shorter way around the hue circle
+    Hsv,
+    /// Along the shorter way around the hue circle
+    Hsl,
+    /// Perceptually even, avoids the dark and grey middle of RGB gradients
+    Oklab,
+}
+
+fn mix(a: f32, b: f32, t: f32) -> f32 {
+    a + (b - a) * t
+}
+
+/// Interpolate hue along the shorter way around the circle
+fn mix_hue(a: f32, b: f32, t: f32) -> f32 {
+    let delta = (b - a + 180.0).rem_euclid(360.0) - 180.0;
+    (a + delta * t).rem_euclid(360.0)
+}
+
+/// Interpolate between `from` and `to` in the given color space,
+/// `t` ranges from 0.0 (`from`) to 1.0 (`to`)
+pub fn interpolate(from: &OwnRGB8, to: &OwnRGB8, t: f32, space: ColorSpace) -> OwnRGB8 {
+    let t = t.clamp(0.0, 1.0);
+    match space {
+        ColorSpace::Rgb => from.lerp(to, t),
+        ColorSpace::Hsv => {
+            let (a, b) = (Hsv::from(from), Hsv::from(to));
+            // Keep the hue of colored ends, if the other end is grey
+            let (ha, hb) = match (a.s == 0.0, b.s == 0.0) {
+                (true, false) => (b.h, b.h),
+                (false, true) => (a.h, a.h),
+                _ => (a.h, b.h),
+            };
+            Hsv::new(mix_hue(ha, hb, t), mix(a.s, b.s, t), mix(a.v, b.v, t)).into()
+        }
+        ColorSpace::Hsl => {
+            let (a, b) = (Hsl::from(from), Hsl::from(to));
+            let (ha, hb) = match (a.s == 0.0, b.s == 0.0) {
+                (true, false) => (b.h, b.h),
+                (false, true) => (a.h, a.h),
+                _ => (a.h, b.h),
+            };
+            Hsl::new(mix_hue(ha, hb, t), mix(a.s, b.s, t), mix(a.l, b.l, t)).into()
+        }
+        ColorSpace::Oklab => {
+            let (a, b) = (Oklab::from(from), Oklab::from(to));
+            Oklab::new(mix(a.l, b.l, t), mix(a.a, b.a, t), mix(a.b, b.b, t)).into()
+        }
+    }
+}
+
+/// Color at position `t` (0.0 - 1.0) of evenly spaced gradient stops.
+/// Black, if there are no stops.
+pub fn gradient_at(stops: &[OwnRGB8], t: f32, space: ColorSpace) -> OwnRGB8 {
+    match stops {
+        [] => OwnRGB8::default(),
+        [color] => color.clone(),
+        stops => {
+            let position = t.clamp(0.0, 1.0) * (stops.len() - 1) as f32;
+            let index = (position.floor() as usize).min(stops.len() - 2);
+            interpolate(
+                &stops[index],
+                &stops[index + 1],
+                position - index as f32,
+                space,
+            )
+        }
+    }
+}
+
+/// `count` colors along the gradient, from the first to the last stop
+pub fn gradient(stops: &[OwnRGB8], count: usize, space: ColorSpace) -> Vec<OwnRGB8> {
+    (0..count)
+        .map(|i| {
+            let t = if count > 1 {
+                i as f32 / (count - 1) as f32
+            } else {
+                0.0
+            };
+            gradient_at(stops, t, space)
+        })
+        .collect()
+}
+
+/// `count` colors with evenly spaced hues around the color wheel, starting at red
+pub fn hue_wheel(count: usize, saturation: f32, value: f32) -> Vec<OwnRGB8> {
+    (0..count)
+        .map(|i| Hsv::new(360.0 * i as f32 / count as f32, saturation, value).into())
+        .collect()
+}
+
+/// Why a color couldn't be parsed, see `parse_color`
+#[derive(Clone, Debug, PartialEq, Eq, thiserror::Error)]
+pub enum ColorParseError {
+    #[error("Empty color")]
+    Empty,
+    #[error("Invalid hex color '{0}', expected 3 or 6 hex digits (e.g. ff8000)")]
+    InvalidHex(String),
+    #[error("Unknown color name '{0}'")]
+    UnknownName(String),
+    #[error("Unknown color function '{0}()'")]
+    UnknownFunction(String),
+    #[error("{function}() expects 3 components, got {found}")]
+    ComponentCount { function: String, found: usize },
+    #[error("Invalid component '{component}' in {function}()")]
+    InvalidComponent { function: String, component: String },
+    #[error("Transparent colors are not supported")]
+    Transparent,
+}
+
+/// Parse a color in CSS syntax:
+/// * Hex: `#f80`, `#ff8800`, also without `#`
+/// * Named: `orange`, see <https://www.w3.org/TR/css-color-4/#named-colors>
+/// * Functions: `rgb()`, `hsl()`, `hwb()`, `oklab()`, `oklch()` and, not part of CSS, `hsv()`,
+///   e.g. `rgb(255 128 0)` or `hsl(30deg, 100%, 50%)`
+///
+/// Unlike CSS, plain numbers for saturation, value, lightness, whiteness and blackness
+/// are fractions (0.0 - 1.0), percentages have to be marked with `%`.
+///
+/// The LEDs can't be transparent, so an alpha component has to be 1 (or 100%, `ff`).
+pub fn parse_color(color: &str) -> Result<OwnRGB8, ColorParseError> {
+    let color = color.trim();
+    if color.is_empty() {
+        return Err(ColorParseError::Empty);
+    }
+    if let Some((name, args)) = color.strip_suffix(')').and_then(|c| c.split_once('(')) {
+        return parse_function(&name.trim().to_ascii_lowercase(), args);
+    }
+
+    let lower = color.to_ascii_lowercase();
+    if let Some(hex) = lower.strip_prefix('#') {
+        return parse_hex(hex);
+    }
+    if let Ok(index) = NAMED_COLORS.binary_search_by_key(&lower.as_str(), |(name, _)| name) {
+        let [_, r, g, b] = NAMED_COLORS[index].1.to_be_bytes();
+        return Ok(OwnRGB8::new(r, g, b));
+    }
+    match lower.as_str() {
+        "transparent" => Err(ColorParseError::Transparent),
+        hex if hex.chars().all(|c| c.is_ascii_hexdigit()) => parse_hex(hex),
+        _ => Err(ColorParseError::UnknownName(color.to_string())),
+    }
+}
+
+fn parse_hex(hex: &str) -> Result<OwnRGB8, ColorParseError> {
+    let invalid = || ColorParseError::InvalidHex(hex.to_string());
+    let digits: Vec<u8> = hex
+        .chars()
+        .map(|c| c.to_digit(16).map(|d| d as u8))
+        .collect::<Option<_>>()
+        .ok_or_else(invalid)?;
+    // Short forms repeat each digit
+    let bytes: Vec<u8> = match digits.len() {
+        3 | 4 => digits.iter().map(|d| d * 0x11).collect(),
+        6 | 8 => digits.chunks(2).map(|d| d[0] * 0x10 + d[1]).collect(),
+        _ => return Err(invalid()),
+    };
+    if bytes.len() == 4 && bytes[3] != 0xff {
+        return Err(ColorParseError::Transparent);
+    }
+    Ok(OwnRGB8::new(bytes[0], bytes[1], bytes[2]))
+}
+
+const COLOR_FUNCTIONS: [&str; 8] = ["rgb", "rgba", "hsl", "hsla", "hsv", "hwb", "oklab", "oklch"];
+
+fn parse_function(function: &str, args: &str) -> Result<OwnRGB8, ColorParseError> {
+    if !COLOR_FUNCTIONS.contains(&function) {
+        return Err(ColorParseError::UnknownFunction(function.to_string()));
+    }
+    let invalid = |component: &str| ColorParseError::InvalidComponent {
+        function: function.to_string(),
+        component: component.to_string(),
+    };
+    // Modern syntax separates alpha by '/', the legacy syntax (commas) appends it
+    let (args, alpha) = match args.split_once('/') {
+        Some((args, alpha)) => (args, Some(alpha.trim())),
+        None => (args, None),
+    };
+    let mut components: Vec<&str> = args
+        .split(|c: char| c == ',' || c.is_whitespace())
+        .filter(|arg| !arg.is_empty())
+        .collect();
+    let alpha = match alpha {
+        Some(alpha) => Some(alpha),
+        None if components.len() == 4 => components.pop(),
+        None => None,
+    };
+    if let Some(alpha) = alpha {
+        let opaque = match alpha.strip_suffix('%') {
+            Some(percent) => number(percent).map(|a| a == 100.0),
+            None => number(alpha).map(|a| a == 1.0),
+        };
+        match opaque {
+            Some(true) => {}
+            Some(false) => return Err(ColorParseError::Transparent),
+            None => return Err(invalid(alpha)),
+        }
+    }
+    let [x, y, z] = components[..] else {
+        return Err(ColorParseError::ComponentCount {
+            function: function.to_string(),
+            found: components.len(),
+        });
+    };
+
+    // Percentages and plain numbers, `scale` is the value of 100%
+    let value = |arg: &str, scale: f32| match arg.strip_suffix('%') {
+        Some(percent) => number(percent).map(|v| v / 100.0 * scale),
+        None => number(arg),
+    };
+    let value = |arg: &str, scale: f32| value(arg, scale).ok_or_else(|| invalid(arg));
+    let hue = |arg: &str| hue(arg).ok_or_else(|| invalid(arg));
+    // Saturation, lightness etc. as 0.0 - 1.0 or in percent
+    let fraction = |arg: &str| value(arg, 1.0);
+
+    match function {
+        "rgb" | "rgba" => {
+            let channel = |arg| value(arg, 255.0).map(|c| c.clamp(0.0, 255.0).round() as u8);
+            Ok(OwnRGB8::new(channel(x)?, channel(y)?, channel(z)?))
+        }
+        "hsl" | "hsla" => Ok(Hsl::new(hue(x)?, fraction(y)?, fraction(z)?).into()),
+        "hsv" => Ok(Hsv::new(hue(x)?, fraction(y)?, fraction(z)?).into()),
+        "hwb" => {
+            let hue = hue(x)?;
+            let (white, black) = (fraction(y)?.clamp(0.0, 1.0), fraction(z)?.clamp(0.0, 1.0));
+            if white + black >= 1.0 {
+                let grey = white / (white + black);
+                return Ok(Hsv::new(0.0, 0.0, grey).into());
+            }
+            let v = 1.0 - black;
+            Ok(Hsv::new(hue, 1.0 - white / v, v).into())
+        }
+        "oklab" => Ok(Oklab::new(value(x, 1.0)?, value(y, 0.4)?, value(z, 0.4)?).into()),
+        "oklch" => {
+            let (chroma, hue) = (value(y, 0.4)?, hue(z)?.to_radians());
+            Ok(Oklab::new(value(x, 1.0)?, chroma * hue.cos(), chroma * hue.sin()).into())
+        }
+        _ => unreachable!("checked against COLOR_FUNCTIONS"),
+    }
+}
+
+/// Finite number, CSS' `none` counts as 0
+fn number(arg: &str) -> Option<f32> {
+    match arg {
+        "none" => Some(0.0),
+        arg => arg.parse::<f32>().ok().filter(|v| v.is_finite()),
+    }
+}
+
+/// Angle in degrees, plain numbers are degrees as well
+fn hue(arg: &str) -> Option<f32> {
+    let units = [
+        ("deg", 1.0),
+        ("grad", 0.9),
+        ("rad", 180.0 / PI),
+        ("turn", 360.0),
+    ];
+    // "grad" is checked before "rad", which it ends with
+    match units.iter().find(|(unit, _)| arg.ends_with(unit)) {
+        Some((unit, degrees)) => number(&arg[..arg.len() - unit.len()]).map(|v| v * degrees),
+        None => number(arg),
+    }
+}
+
+/// CSS named colors, sorted by name
+const NAMED_COLORS: [(&str, u32); 148] = [
+    ("aliceblue", 0xf0f8ff),
+    ("antiquewhite", 0xfaebd7),
+    ("aqua", 0x00ffff),
+    ("aquamarine", 0x7fffd4),
+    ("azure", 0xf0ffff),
+    ("beige", 0xf5f5dc),
+    ("bisque", 0xffe4c4),
+    ("black", 0x000000),
+    ("blanchedalmond", 0xffebcd),
+    ("blue", 0x0000ff),
+    ("blueviolet", 0x8a2be2),
+    ("brown", 0xa52a2a),
+    ("burlywood", 0xdeb887),
+    ("cadetblue", 0x5f9ea0),
+    ("chartreuse", 0x7fff00),
+    ("chocolate", 0xd2691e),
+    ("coral", 0xff7f50),
+    ("cornflowerblue", 0x6495ed),
+    ("cornsilk", 0xfff8dc),
+    ("crimson", 0xdc143c),
+    ("cyan", 0x00ffff),
+    ("darkblue", 0x00008b),
+    ("darkcyan", 0x008b8b),
+    ("darkgoldenrod", 0xb8860b),
+    ("darkgray", 0xa9a9a9),
+    ("darkgreen", 0x006400),
+    ("darkgrey", 0xa9a9a9),
+    ("darkkhaki", 0xbdb76b),
+    ("darkmagenta", 0x8b008b),
+    ("darkolivegreen", 0x556b2f),
+    ("darkorange", 0xff8c00),
+    ("darkorchid", 0x9932cc),
+    ("darkred", 0x8b0000),
+    ("darksalmon", 0xe9967a),
+    ("darkseagreen", 0x8fbc8f),
+    ("darkslateblue", 0x483d8b),
+    ("darkslategray", 0x2f4f4f),
+    ("darkslategrey", 0x2f4f4f),
+    ("darkturquoise", 0x00ced1),
+    ("darkviolet", 0x9400d3),
+    ("deeppink", 0xff1493),
+    ("deepskyblue", 0x00bfff),
+    ("dimgray", 0x696969),
+    ("dimgrey", 0x696969),
+    ("dodgerblue", 0x1e90ff),
+    ("firebrick", 0xb22222),
+    ("floralwhite", 0xfffaf0),
+    ("forestgreen", 0x228b22),
+    ("fuchsia", 0xff00ff),
+    ("gainsboro", 0xdcdcdc),
+    ("ghostwhite", 0xf8f8ff),
+    ("gold", 0xffd700),
+    ("goldenrod", 0xdaa520),
+    ("gray", 0x808080),
+    ("green", 0x008000),
+    ("greenyellow", 0xadff2f),
+    ("grey", 0x808080),
+    ("honeydew", 0xf0fff0),
+    ("hotpink", 0xff69b4),
+    ("indianred", 0xcd5c5c),
+    ("indigo", 0x4b0082),
+    ("ivory", 0xfffff0),
+    ("khaki", 0xf0e68c),
+    ("lavender", 0xe6e6fa),
+    ("lavenderblush", 0xfff0f5),
+    ("lawngreen", 0x7cfc00),
+    ("lemonchiffon", 0xfffacd),
+    ("lightblue", 0xadd8e6),
+    ("lightcoral", 0xf08080),
+    ("lightcyan", 0xe0ffff),
+    ("lightgoldenrodyellow", 0xfafad2),
+    ("lightgray", 0xd3d3d3),
+    ("lightgreen", 0x90ee90),
+    ("lightgrey", 0xd3d3d3),
+    ("lightpink", 0xffb6c1),
+    ("lightsalmon", 0xffa07a),
+    ("lightseagreen", 0x20b2aa),
+    ("lightskyblue", 0x87cefa),
+    ("lightslategray", 0x778899),
+    ("lightslategrey", 0x778899),
+    ("lightsteelblue", 0xb0c4de),
+    ("lightyellow", 0xffffe0),
+    ("lime", 0x00ff00),
+    ("limegreen", 0x32cd32),
+    ("linen", 0xfaf0e6),
+    ("magenta", 0xff00ff),
+    ("maroon", 0x800000),
+    ("mediumaquamarine", 0x66cdaa),
+    ("mediumblue", 0x0000cd),
+    ("mediumorchid", 0xba55d3),
+    ("mediumpurple", 0x9370db),
+    ("mediumseagreen", 0x3cb371),
+    ("mediumslateblue", 0x7b68ee),
+    ("mediumspringgreen", 0x00fa9a),
+    ("mediumturquoise", 0x48d1cc),
+    ("mediumvioletred", 0xc71585),
+    ("midnightblue", 0x191970),
+    ("mintcream", 0xf5fffa),
+    ("mistyrose", 0xffe4e1),
+    ("moccasin", 0xffe4b5),
+    ("navajowhite", 0xffdead),
+    ("navy", 0x000080),
+    ("oldlace", 0xfdf5e6),
+    ("olive", 0x808000),
+    ("olivedrab", 0x6b8e23),
+    ("orange", 0xffa500),
+    ("orangered", 0xff4500),
+    ("orchid", 0xda70d6),
+    ("palegoldenrod", 0xeee8aa),
+    ("palegreen", 0x98fb98),
+    ("paleturquoise", 0xafeeee),
+    ("palevioletred", 0xdb7093),
+    ("papayawhip", 0xffefd5),
+    ("peachpuff", 0xffdab9),
+    ("peru", 0xcd853f),
+    ("pink", 0xffc0cb),
+    ("plum", 0xdda0dd),
+    ("powderblue", 0xb0e0e6),
+    ("purple", 0x800080),
+    ("rebeccapurple", 0x663399),
+    ("red", 0xff0000),
+    ("rosybrown", 0xbc8f8f),
+    ("royalblue", 0x4169e1),
+    ("saddlebrown", 0x8b4513),
+    ("salmon", 0xfa8072),
+    ("sandybrown", 0xf4a460),
+    ("seagreen", 0x2e8b57),
+    ("seashell", 0xfff5ee),
+    ("sienna", 0xa0522d),
+    ("silver", 0xc0c0c0),
+    ("skyblue", 0x87ceeb),
+    ("slateblue", 0x6a5acd),
+    ("slategray", 0x708090),
+    ("slategrey", 0x708090),
+    ("snow", 0xfffafa),
+    ("springgreen", 0x00ff7f),
+    ("steelblue", 0x4682b4),
+    ("tan", 0xd2b48c),
+    ("teal", 0x008080),
+    ("thistle", 0xd8bfd8),
+    ("tomato", 0xff6347),
+    ("turquoise", 0x40e0d0),
+    ("violet", 0xee82ee),
+    ("wheat", 0xf5deb3),
+    ("white", 0xffffff),
+    ("whitesmoke", 0xf5f5f5),
+    ("yellow", 0xffff00),
+    ("yellowgreen", 0x9acd32),
+];
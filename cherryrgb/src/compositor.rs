@@ -0,0 +1,134 @@
+//! Compositing of lighting layers into a single set of custom colors
+
+use crate::{CustomKeyLeds, OwnRGB8, TOTAL_KEYS};
+use clap::ValueEnum;
+use rgb::RGB8;
+use serde::{Deserialize, Serialize};
+
+/// How the colors of a layer are combined with the layers below
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum, Serialize, Deserialize)]
+#[clap(rename_all = "snake_case")]
+#[serde(rename_all = "snake_case")]
+pub enum BlendMode {
+    /// Cover the layers below
+    #[default]
+    Replace,
+    /// Add color channels, saturating at 255
+    Add,
+    /// Multiply color channels, e.g. for darkening
+    Multiply,
+    /// Take the brighter value of each color channel
+    Max,
+}
+
+impl BlendMode {
+    /// Combine a layer color (`top`) with the color below (`bottom`)
+    pub fn blend(self, bottom: &OwnRGB8, top: &OwnRGB8) -> OwnRGB8 {
+        let channel = |b: u8, t: u8| match self {
+            BlendMode::Replace => t,
+            BlendMode::Add => b.saturating_add(t),
+            BlendMode::Multiply => ((b as u16 * t as u16 + 127) / 255) as u8,
+            BlendMode::Max => b.max(t),
+        };
+        let (bottom, top): (RGB8, RGB8) = (bottom.clone().into(), top.clone().into());
+        OwnRGB8::new(
+            channel(bottom.r, top.r),
+            channel(bottom.g, top.g),
+            channel(bottom.b, top.b),
+        )
+    }
+}
+
+/// Custom colors, which are composited on top of other layers
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Layer {
+    /// Unique name, setting a layer with the same name replaces it
+    pub name: String,
+    /// Layers with higher priority are drawn on top
+    #[serde(default)]
+    pub priority: i32,
+    #[serde(default)]
+    pub blend: BlendMode,
+    /// Opacity of each key (0 - 255), all keys are opaque if not set
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub alpha: Option<Vec<u8>>,
+    pub leds: CustomKeyLeds,
+}
+
+impl Layer {
+    /// Create an opaque layer
+    pub fn new(name: &str, priority: i32, blend: BlendMode, leds: CustomKeyLeds) -> Self {
+        Self {
+            name: name.to_owned(),
+            priority,
+            blend,
+            alpha: None,
+            leds,
+        }
+    }
+
+    /// Opacity of the given key, from 0.0 (transparent) to 1.0 (opaque)
+    pub fn alpha_at(&self, index: usize) -> f32 {
+        match &self.alpha {
+            Some(alpha) => alpha.get(index).map_or(0.0, |&a| a as f32 / 255.0),
+            None => 1.0,
+        }
+    }
+}
+
+/// Stack of layers
+#[derive(Clone, Debug, Default)]
+pub struct Compositor {
+    layers: Vec<Layer>,
+}
+
+impl Compositor {
+    /// Add layer or replace the existing one with the same name
+    pub fn set_layer(&mut self, layer: Layer) {
+        self.layers.retain(|l| l.name != layer.name);
+        // Keep order of insertion among layers of the same priority
+        let position = self
+            .layers
+            .iter()
+            .position(|l| l.priority > layer.priority)
+            .unwrap_or(self.layers.len());
+        self.layers.insert(position, layer);
+    }
+
+    /// Remove layer by name, returns false if there is no such layer
+    pub fn remove_layer(&mut self, name: &str) -> bool {
+        let len = self.layers.len();
+        self.layers.retain(|l| l.name != name);
+        self.layers.len() != len
+    }
+
+    /// Layers, from bottom to top
+    pub fn layers(&self) -> &[Layer] {
+        &self.layers
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.layers.is_empty()
+    }
+
+    /// Draw all layers on top of `base`
+    pub fn compose(&self, base: &CustomKeyLeds) -> CustomKeyLeds {
+        let black = OwnRGB8::default();
+        let leds = (0..TOTAL_KEYS)
+            .map(|index| {
+                let bottom = base.leds().get(index).unwrap_or(&black).clone();
+                self.layers.iter().fold(bottom, |bottom, layer| {
+                    let alpha = layer.alpha_at(index);
+                    if alpha <= 0.0 {
+                        return bottom;
+                    }
+                    let top = layer.leds.leds().get(index).unwrap_or(&black);
+                    let blended = layer.blend.blend(&bottom, top);
+                    bottom.lerp(&blended, alpha)
+                })
+            })
+            .collect();
+
+        CustomKeyLeds::from_leds(leds).unwrap()
+    }
+}
@@ -0,0 +1,16 @@
+use crate::{CherryRgbError, CustomKeyLeds};
+use std::time::Duration;
+
+/// Software lighting effect, rendered frame by frame and
+/// streamed to the keyboard via `CherryKeyboard::update_custom_colors`
+pub trait Effect: Send {
+    /// Name of the effect, used for logging
+    fn name(&self) -> &str;
+
+    /// Feed a keyboard input report, see `CherryKeyboard::forward_filtered_keys`
+    fn key_report(&mut self, _report: &[u8]) {}
+
+    /// Render the frame at `elapsed` time since the effect was started.
+    /// Returns `None` once the effect has finished.
+    fn render(&mut self, elapsed: Duration) -> Result<Option<CustomKeyLeds>, CherryRgbError>;
+}
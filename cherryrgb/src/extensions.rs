@@ -1,3 +1,4 @@
+use crate::color::{parse_color, ColorParseError};
 use binrw::{BinRead, BinReaderExt, BinResult, BinWrite, BinWriterExt, Endian};
 use rgb::RGB8;
 use serde::{Deserialize, Serialize};
@@ -33,6 +34,22 @@ impl OwnRGB8 {
     pub fn new(r: u8, g: u8, b: u8) -> Self {
         Self(RGB8 { r, g, b })
     }
+
+    /// Six hex digits, e.g. `ff8000`
+    pub fn to_hex(&self) -> String {
+        hex::encode([self.0.r, self.0.g, self.0.b])
+    }
+
+    /// Linear interpolation towards `other`, `t` ranges from 0.0 (self) to 1.0 (other)
+    pub fn lerp(&self, other: &OwnRGB8, t: f32) -> Self {
+        let t = t.clamp(0.0, 1.0);
+        let mix = |a: u8, b: u8| (a as f32 + (b as f32 - a as f32) * t).round() as u8;
+        Self::new(
+            mix(self.0.r, other.0.r),
+            mix(self.0.g, other.0.g),
+            mix(self.0.b, other.0.b),
+        )
+    }
 }
 
 impl From<RGB8> for OwnRGB8 {
@@ -41,6 +58,12 @@ impl From<RGB8> for OwnRGB8 {
     }
 }
 
+impl From<OwnRGB8> for RGB8 {
+    fn from(val: OwnRGB8) -> Self {
+        val.0
+    }
+}
+
 impl BinRead for OwnRGB8 {
     type Args<'a> = ();
 
@@ -76,24 +99,11 @@ impl BinWrite for OwnRGB8 {
 }
 
 impl FromStr for OwnRGB8 {
-    type Err = hex::FromHexError;
+    type Err = ColorParseError;
 
+    /// Parses CSS colors (e.g. `ff8000`, `#f80`, `orange`, `hsl(30 100% 50%)`),
+    /// see `color::parse_color`
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        let val = match s.len() {
-            6 => {
-                let bytes = hex::decode(s)?;
-                RGB8 {
-                    r: bytes[0],
-                    g: bytes[1],
-                    b: bytes[2],
-                }
-                .into()
-            }
-            _ => {
-                return Err(hex::FromHexError::InvalidStringLength);
-            }
-        };
-
-        Ok(val)
+        parse_color(s)
     }
 }
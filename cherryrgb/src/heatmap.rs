@@ -0,0 +1,122 @@
+//! Key press statistics, rendered as heatmap
+//!
+//! Only the number of presses per key is recorded, neither the order
+//! of key presses nor any timing.
+
+use crate::{
+    color::{gradient_at, ColorSpace},
+    layout, CustomKeyLeds, OwnRGB8, TOTAL_KEYS,
+};
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+use std::fmt::Write;
+
+/// How press counts are mapped onto the gradient
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum, Serialize, Deserialize)]
+#[clap(rename_all = "snake_case")]
+#[serde(rename_all = "snake_case")]
+pub enum HeatmapScale {
+    /// Proportional to the number of presses
+    Linear,
+    /// Logarithmic, makes rarely used keys distinguishable
+    #[default]
+    Log,
+}
+
+/// Press count of every key
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct KeyPressCounts {
+    counts: Vec<u64>,
+    /// Keys pressed in the last report, to count presses instead of repeats
+    #[serde(skip)]
+    pressed: Vec<usize>,
+}
+
+impl Default for KeyPressCounts {
+    fn default() -> Self {
+        Self {
+            counts: vec![0; TOTAL_KEYS],
+            pressed: vec![],
+        }
+    }
+}
+
+impl KeyPressCounts {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Count the keys, which are pressed in `report` but weren't pressed before.
+    /// Takes keyboard input reports as forwarded by `forward_filtered_keys`.
+    pub fn record(&mut self, report: &[u8]) {
+        // Reports with other report IDs (e.g. media keys) don't tell anything about the keyboard keys
+        if !matches!(report.first(), Some(1)) {
+            return;
+        }
+        let pressed = layout::pressed_keys(report);
+        for &key in &pressed {
+            if self.pressed.contains(&key) {
+                continue;
+            }
+            if let Some(count) = self.counts.get_mut(key) {
+                *count = count.saturating_add(1);
+            }
+        }
+        self.pressed = pressed;
+    }
+
+    /// Press counts by LED index
+    pub fn counts(&self) -> &[u64] {
+        &self.counts
+    }
+
+    /// Total number of key presses
+    pub fn total(&self) -> u64 {
+        self.counts.iter().sum()
+    }
+
+    /// Render counts as custom colors, the most used key gets the last color of `gradient`.
+    /// Falls back to blue, green, yellow, red if `gradient` is empty.
+    /// Colors between the gradient stops are interpolated in `space`.
+    pub fn render(
+        &self,
+        scale: HeatmapScale,
+        gradient: &[OwnRGB8],
+        space: ColorSpace,
+    ) -> CustomKeyLeds {
+        let default_gradient = [
+            OwnRGB8::new(0, 0, 255),
+            OwnRGB8::new(0, 255, 0),
+            OwnRGB8::new(255, 255, 0),
+            OwnRGB8::new(255, 0, 0),
+        ];
+        let gradient = match gradient {
+            [] => &default_gradient[..],
+            gradient => gradient,
+        };
+        let value = |count: u64| match scale {
+            HeatmapScale::Linear => count as f32,
+            HeatmapScale::Log => (count as f32).ln_1p(),
+        };
+        let max = self.counts.iter().copied().map(value).fold(0.0, f32::max);
+        let leds = (0..TOTAL_KEYS)
+            .map(|index| {
+                let count = self.counts.get(index).copied().unwrap_or(0);
+                let t = if max > 0.0 { value(count) / max } else { 0.0 };
+                gradient_at(gradient, t, space)
+            })
+            .collect();
+
+        CustomKeyLeds::from_leds(leds).unwrap()
+    }
+
+    /// Export as CSV with the columns index, key name (if known) and count
+    pub fn to_csv(&self) -> String {
+        let mut csv = String::from("index,key,count\n");
+        for (index, count) in self.counts.iter().enumerate() {
+            let name = layout::key_name(index).unwrap_or_default();
+            let _ = writeln!(csv, "{},{},{}", index, name, count);
+        }
+        csv
+    }
+}
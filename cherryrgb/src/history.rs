@@ -0,0 +1,88 @@
+//! Undo/redo history of applied lighting
+
+use crate::{write_file_atomically, CherryRgbError, SavedLighting};
+use serde::{Deserialize, Serialize};
+use std::{collections::VecDeque, fmt, path::Path};
+
+/// Maximum number of entries kept, older entries are dropped
+pub const HISTORY_LENGTH: usize = 50;
+
+/// Applied lighting, oldest first. Undone entries are kept for redo,
+/// until another lighting is applied.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct History {
+    entries: VecDeque<SavedLighting>,
+    /// Number of entries up to and including the current one
+    position: usize,
+}
+
+impl History {
+    /// Add newly applied lighting, dropping undone entries
+    pub fn push(&mut self, lighting: SavedLighting) {
+        self.entries.truncate(self.position);
+        self.entries.push_back(lighting);
+        if self.entries.len() > HISTORY_LENGTH {
+            self.entries.pop_front();
+        }
+        self.position = self.entries.len();
+    }
+
+    /// Step back, returns the lighting to apply
+    pub fn undo(&mut self) -> Option<&SavedLighting> {
+        if self.position < 2 {
+            return None;
+        }
+        self.position -= 1;
+        self.current()
+    }
+
+    /// Step forward again after `undo`, returns the lighting to apply
+    pub fn redo(&mut self) -> Option<&SavedLighting> {
+        if self.position >= self.entries.len() {
+            return None;
+        }
+        self.position += 1;
+        self.current()
+    }
+
+    /// Lighting applied last, or stepped to by `undo`/`redo`
+    pub fn current(&self) -> Option<&SavedLighting> {
+        self.entries.get(self.position.checked_sub(1)?)
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Read history (JSON)
+    pub fn load(path: &Path) -> Result<Self, CherryRgbError> {
+        let json = std::fs::read_to_string(path)
+            .map_err(|err| CherryRgbError::ParseError(format!("reading {:?}, err={err}", path)))?;
+        let mut history: Self = serde_json::from_str(&json)?;
+        history.position = history.position.min(history.entries.len());
+        Ok(history)
+    }
+
+    /// Write history (JSON), the file is replaced atomically
+    pub fn save(&self, path: &Path) -> Result<(), CherryRgbError> {
+        write_file_atomically(path, &serde_json::to_string(self)?)
+    }
+}
+
+/// One line per entry, newest first, the current entry is marked with `*`
+impl fmt::Display for History {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.entries.is_empty() {
+            return writeln!(f, "No lighting applied yet");
+        }
+        for (index, lighting) in self.entries.iter().enumerate().rev() {
+            let marker = if index + 1 == self.position { '*' } else { ' ' };
+            writeln!(f, "{marker} {:2} {lighting}", index + 1)?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,248 @@
+//! Declarative keyframe animations
+//!
+//! Example (TOML):
+//! ```toml
+//! repeat = "ping_pong"
+//! easing = "ease_in_out"
+//!
+//! [groups]
+//! wasd = [14, 9, 15, 21]
+//!
+//! [[keyframes]]
+//! time = 0
+//! keys = { all = "000020", wasd = "ff0000" }
+//!
+//! [[keyframes]]
+//! time = 1500
+//! keys = { all = "0000ff", wasd = "ffff00", "0" = "ffffff" }
+//! ```
+
+use crate::{effect::Effect, CherryRgbError, CustomKeyLeds, OwnRGB8, TOTAL_KEYS};
+use serde::{Deserialize, Serialize};
+use std::{collections::BTreeMap, str::FromStr, time::Duration};
+
+/// Name of the implicit group containing all keys
+pub(crate) const GROUP_ALL: &str = "all";
+
+/// Interpolation curve between two keyframes
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Easing {
+    #[default]
+    Linear,
+    EaseIn,
+    EaseOut,
+    EaseInOut,
+    /// Hold the color until the next keyframe is reached
+    Step,
+}
+
+impl Easing {
+    /// Map linear progress (0.0 - 1.0) onto the easing curve
+    pub fn apply(self, t: f32) -> f32 {
+        let t = t.clamp(0.0, 1.0);
+        match self {
+            Easing::Linear => t,
+            Easing::EaseIn => t * t,
+            Easing::EaseOut => 1.0 - (1.0 - t) * (1.0 - t),
+            Easing::EaseInOut => {
+                if t < 0.5 {
+                    2.0 * t * t
+                } else {
+                    1.0 - (-2.0 * t + 2.0).powi(2) / 2.0
+                }
+            }
+            Easing::Step => 0.0,
+        }
+    }
+}
+
+/// What happens after the last keyframe
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RepeatMode {
+    /// Play once and keep the last frame
+    #[default]
+    Once,
+    /// Start over from the first keyframe
+    Loop,
+    /// Play forward, then backward
+    PingPong,
+}
+
+/// Colors of a set of keys at a point in time
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Keyframe {
+    /// Time offset in milliseconds
+    pub time: u64,
+    /// Easing used for the transition towards this keyframe, overrides the animation default
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub easing: Option<Easing>,
+    /// Key index or group name -> color (e.g. ff0000, red)
+    pub keys: BTreeMap<String, String>,
+}
+
+/// Keyframe animation file contents
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct KeyframeAnimation {
+    /// Length of one cycle in milliseconds, defaults to the time of the last keyframe
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub duration: Option<u64>,
+    #[serde(default)]
+    pub repeat: RepeatMode,
+    #[serde(default)]
+    pub easing: Easing,
+    /// Named groups of key indexes, `all` is predefined
+    #[serde(default)]
+    pub groups: BTreeMap<String, Vec<usize>>,
+    pub keyframes: Vec<Keyframe>,
+}
+
+impl KeyframeAnimation {
+    /// Resolve key index or group name
+    fn resolve_keys(&self, name: &str) -> Result<Vec<usize>, CherryRgbError> {
+        if name == GROUP_ALL {
+            return Ok((0..TOTAL_KEYS).collect());
+        }
+        let keys = match self.groups.get(name) {
+            Some(keys) => keys.clone(),
+            None => vec![name.parse::<usize>().map_err(|_| {
+                CherryRgbError::ParseError(format!("Unknown key or group '{name}'"))
+            })?],
+        };
+        match keys.iter().find(|&&index| index >= TOTAL_KEYS) {
+            Some(index) => Err(CherryRgbError::InvalidArgument(
+                "Key index out of bounds".into(),
+                index.to_string(),
+            )),
+            None => Ok(keys),
+        }
+    }
+}
+
+/// A keyframe of a single key
+#[derive(Clone, Debug)]
+struct KeyPoint {
+    time: u64,
+    color: OwnRGB8,
+    easing: Easing,
+}
+
+/// Plays a `KeyframeAnimation` by interpolating the frames in between keyframes
+pub struct KeyframePlayer {
+    name: String,
+    duration: u64,
+    repeat: RepeatMode,
+    tracks: Vec<Vec<KeyPoint>>,
+    finished: bool,
+}
+
+impl KeyframePlayer {
+    /// Validate the animation and prepare per-key tracks
+    pub fn new(name: &str, animation: &KeyframeAnimation) -> Result<Self, CherryRgbError> {
+        let mut keyframes = animation.keyframes.clone();
+        keyframes.sort_by_key(|kf| kf.time);
+
+        let mut tracks: Vec<Vec<KeyPoint>> = vec![vec![]; TOTAL_KEYS];
+        for keyframe in &keyframes {
+            let easing = keyframe.easing.unwrap_or(animation.easing);
+            // Apply `all` and groups first, so single keys can override them
+            let mut entries: Vec<(&String, &String)> = keyframe.keys.iter().collect();
+            entries.sort_by_key(|(name, _)| {
+                (name.as_str() != GROUP_ALL, name.parse::<usize>().is_ok())
+            });
+
+            let mut colors: BTreeMap<usize, OwnRGB8> = BTreeMap::new();
+            for (name, color) in entries {
+                let color = OwnRGB8::from_str(color).map_err(|e| {
+                    CherryRgbError::ParseError(format!("Failed parsing color '{color}', err: {e}"))
+                })?;
+                for index in animation.resolve_keys(name)? {
+                    colors.insert(index, color.clone());
+                }
+            }
+            for (index, color) in colors {
+                tracks[index].push(KeyPoint {
+                    time: keyframe.time,
+                    color,
+                    easing,
+                });
+            }
+        }
+
+        let duration = animation
+            .duration
+            .or_else(|| keyframes.last().map(|kf| kf.time))
+            .unwrap_or(0);
+
+        Ok(Self {
+            name: name.to_owned(),
+            duration,
+            repeat: animation.repeat,
+            tracks,
+            finished: false,
+        })
+    }
+
+    /// Length of one cycle
+    pub fn duration(&self) -> Duration {
+        Duration::from_millis(self.duration)
+    }
+
+    /// Key colors at the given time offset within a cycle
+    pub fn frame_at(&self, time: u64) -> CustomKeyLeds {
+        let leds: Vec<OwnRGB8> = self
+            .tracks
+            .iter()
+            .map(|track| {
+                let next = track.iter().position(|p| p.time > time);
+                match next {
+                    // Before first keyframe of this key, or no keyframes at all
+                    Some(0) => track[0].color.clone(),
+                    None => track.last().map_or(OwnRGB8::default(), |p| p.color.clone()),
+                    Some(next) => {
+                        let (from, to) = (&track[next - 1], &track[next]);
+                        let t = (time - from.time) as f32 / (to.time - from.time) as f32;
+                        from.color.lerp(&to.color, to.easing.apply(t))
+                    }
+                }
+            })
+            .collect();
+
+        CustomKeyLeds::from_leds(leds).unwrap()
+    }
+}
+
+impl Effect for KeyframePlayer {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn render(&mut self, elapsed: Duration) -> Result<Option<CustomKeyLeds>, CherryRgbError> {
+        let elapsed = elapsed.as_millis() as u64;
+        let time = match self.repeat {
+            RepeatMode::Once => {
+                if elapsed >= self.duration {
+                    // Make sure the last frame gets sent exactly once
+                    if self.finished {
+                        return Ok(None);
+                    }
+                    self.finished = true;
+                }
+                elapsed.min(self.duration)
+            }
+            _ if self.duration == 0 => 0,
+            RepeatMode::Loop => elapsed % self.duration,
+            RepeatMode::PingPong => {
+                let t = elapsed % (2 * self.duration);
+                if t > self.duration {
+                    2 * self.duration - t
+                } else {
+                    t
+                }
+            }
+        };
+
+        Ok(Some(self.frame_at(time)))
+    }
+}
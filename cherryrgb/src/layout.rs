@@ -0,0 +1,173 @@
+//! Physical key layout of the supported keyboards
+//!
+//! LED indexes are assigned column by column, 6 rows per column,
+//! starting at the top left (ESC). See `examples/static_rainbow.json`.
+
+use crate::TOTAL_KEYS;
+use std::convert::TryFrom;
+
+/// Number of key rows
+pub const ROWS: usize = 6;
+/// Number of key columns
+pub const COLUMNS: usize = TOTAL_KEYS / ROWS;
+
+/// HID report ID of the (NKRO) keyboard input report
+const KEYBOARD_REPORT_ID: u8 = 1;
+/// First HID usage in the keyboard report bitmap, see report descriptor in vkbd.rs
+const KEYBOARD_REPORT_USAGE_MIN: u8 = 0x04;
+/// Bits of the LED output report, see report descriptor in vkbd.rs
+const LED_NUM_LOCK: u8 = 1 << 0;
+const LED_CAPS_LOCK: u8 = 1 << 1;
+const LED_SCROLL_LOCK: u8 = 1 << 2;
+
+/// State of the lock indicators, as set by the host
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct LockState {
+    pub num_lock: bool,
+    pub caps_lock: bool,
+    pub scroll_lock: bool,
+}
+
+impl LockState {
+    /// Decode a LED output report (report ID 1) sent by the host to the virtual keyboard
+    pub fn from_output_report(report: &[u8]) -> Option<Self> {
+        match report {
+            [KEYBOARD_REPORT_ID, leds, ..] => Some(Self {
+                num_lock: leds & LED_NUM_LOCK != 0,
+                caps_lock: leds & LED_CAPS_LOCK != 0,
+                scroll_lock: leds & LED_SCROLL_LOCK != 0,
+            }),
+            _ => None,
+        }
+    }
+
+    /// Encode as LED output report of a boot keyboard
+    pub fn leds(&self) -> u8 {
+        let mut leds = 0;
+        if self.num_lock {
+            leds |= LED_NUM_LOCK;
+        }
+        if self.caps_lock {
+            leds |= LED_CAPS_LOCK;
+        }
+        if self.scroll_lock {
+            leds |= LED_SCROLL_LOCK;
+        }
+        leds
+    }
+}
+
+/// LED index -> HID usage (keyboard page), ISO layout.
+/// Indexes not listed here are either unused or have no keyboard page usage (FN, media keys).
+#[rustfmt::skip]
+const USAGES: &[(usize, u8)] = &[
+    (0, 0x29), (1, 0x35), (2, 0x2B), (3, 0x39), (4, 0xE1), (5, 0xE0),
+    (7, 0x1E), (8, 0x14), (9, 0x04), (10, 0x64), (11, 0xE3),
+    (13, 0x1F), (14, 0x1A), (15, 0x16), (16, 0x1D), (17, 0xE2),
+    (18, 0x3A), (19, 0x20), (20, 0x08), (21, 0x07), (22, 0x1B),
+    (24, 0x3B), (25, 0x21), (26, 0x15), (27, 0x09), (28, 0x06),
+    (30, 0x3C), (31, 0x22), (32, 0x17), (33, 0x0A), (34, 0x19),
+    (36, 0x3D), (37, 0x23), (38, 0x1C), (39, 0x0B), (40, 0x05), (41, 0x2C),
+    (42, 0x3E), (43, 0x24), (44, 0x18), (45, 0x0D), (46, 0x11),
+    (48, 0x3F), (49, 0x25), (50, 0x0C), (51, 0x0E), (52, 0x10),
+    (54, 0x40), (55, 0x26), (56, 0x12), (57, 0x0F), (58, 0x36),
+    (60, 0x41), (61, 0x27), (62, 0x13), (63, 0x33), (64, 0x37), (65, 0xE6),
+    (66, 0x42), (67, 0x2D), (68, 0x2F), (69, 0x34), (70, 0x38),
+    (72, 0x43), (73, 0x2E), (74, 0x30), (75, 0x32), (77, 0x65),
+    (78, 0x44), (79, 0x2A), (81, 0x28), (82, 0xE5), (83, 0xE4),
+    (84, 0x45), (85, 0x49), (86, 0x4C), (89, 0x50),
+    (90, 0x46), (91, 0x4A), (92, 0x4D), (94, 0x52), (95, 0x51),
+    (96, 0x47), (97, 0x4B), (98, 0x4E), (101, 0x4F),
+    (103, 0x53), (104, 0x5F), (105, 0x5C), (106, 0x59),
+    (109, 0x54), (110, 0x60), (111, 0x5D), (112, 0x5A), (113, 0x62),
+    (115, 0x55), (116, 0x61), (117, 0x5E), (118, 0x5B), (119, 0x63),
+    (121, 0x56), (122, 0x57), (124, 0x58),
+];
+
+/// Key name -> HID usage (keyboard page), names follow the Linux input event codes.
+/// Digit keys are prefixed, because plain numbers denote LED indexes.
+#[rustfmt::skip]
+const KEY_NAMES: &[(&str, u8)] = &[
+    ("a", 0x04), ("b", 0x05), ("c", 0x06), ("d", 0x07), ("e", 0x08), ("f", 0x09),
+    ("g", 0x0A), ("h", 0x0B), ("i", 0x0C), ("j", 0x0D), ("k", 0x0E), ("l", 0x0F),
+    ("m", 0x10), ("n", 0x11), ("o", 0x12), ("p", 0x13), ("q", 0x14), ("r", 0x15),
+    ("s", 0x16), ("t", 0x17), ("u", 0x18), ("v", 0x19), ("w", 0x1A), ("x", 0x1B),
+    ("y", 0x1C), ("z", 0x1D),
+    ("digit1", 0x1E), ("digit2", 0x1F), ("digit3", 0x20), ("digit4", 0x21), ("digit5", 0x22),
+    ("digit6", 0x23), ("digit7", 0x24), ("digit8", 0x25), ("digit9", 0x26), ("digit0", 0x27),
+    ("enter", 0x28), ("esc", 0x29), ("backspace", 0x2A), ("tab", 0x2B), ("space", 0x2C),
+    ("minus", 0x2D), ("equal", 0x2E), ("leftbrace", 0x2F), ("rightbrace", 0x30),
+    ("backslash", 0x32), ("semicolon", 0x33), ("apostrophe", 0x34), ("grave", 0x35),
+    ("comma", 0x36), ("dot", 0x37), ("slash", 0x38), ("capslock", 0x39),
+    ("f1", 0x3A), ("f2", 0x3B), ("f3", 0x3C), ("f4", 0x3D), ("f5", 0x3E), ("f6", 0x3F),
+    ("f7", 0x40), ("f8", 0x41), ("f9", 0x42), ("f10", 0x43), ("f11", 0x44), ("f12", 0x45),
+    ("sysrq", 0x46), ("scrolllock", 0x47), ("pause", 0x48), ("insert", 0x49),
+    ("home", 0x4A), ("pageup", 0x4B), ("delete", 0x4C), ("end", 0x4D), ("pagedown", 0x4E),
+    ("right", 0x4F), ("left", 0x50), ("down", 0x51), ("up", 0x52),
+    ("numlock", 0x53), ("kpslash", 0x54), ("kpasterisk", 0x55), ("kpminus", 0x56),
+    ("kpplus", 0x57), ("kpenter", 0x58), ("kp1", 0x59), ("kp2", 0x5A), ("kp3", 0x5B),
+    ("kp4", 0x5C), ("kp5", 0x5D), ("kp6", 0x5E), ("kp7", 0x5F), ("kp8", 0x60),
+    ("kp9", 0x61), ("kp0", 0x62), ("kpdot", 0x63), ("102nd", 0x64), ("compose", 0x65),
+    ("leftctrl", 0xE0), ("leftshift", 0xE1), ("leftalt", 0xE2), ("leftmeta", 0xE3),
+    ("rightctrl", 0xE4), ("rightshift", 0xE5), ("rightalt", 0xE6), ("rightmeta", 0xE7),
+];
+
+/// Return (column, row) of the key with the given LED index
+pub fn key_position(key_index: usize) -> Option<(usize, usize)> {
+    if key_index >= TOTAL_KEYS {
+        return None;
+    }
+    Some((key_index / ROWS, key_index % ROWS))
+}
+
+/// Return the LED index of the key producing the given HID usage
+pub fn key_index_for_usage(usage: u8) -> Option<usize> {
+    USAGES
+        .iter()
+        .find(|(_, u)| *u == usage)
+        .map(|(index, _)| *index)
+}
+
+/// Decode a keyboard input report (as forwarded by `forward_filtered_keys`)
+/// into the LED indexes of all currently pressed keys.
+/// Reports with other report IDs yield an empty collection.
+pub fn pressed_keys(report: &[u8]) -> Vec<usize> {
+    match report.split_first() {
+        Some((&KEYBOARD_REPORT_ID, bitmap)) => bitmap
+            .iter()
+            .enumerate()
+            .flat_map(|(byte_index, &byte)| {
+                (0..8)
+                    .filter(move |bit| byte & (1 << bit) != 0)
+                    .map(move |bit| byte_index * 8 + bit)
+            })
+            .filter_map(|bit| u8::try_from(bit + KEYBOARD_REPORT_USAGE_MIN as usize).ok())
+            .filter_map(key_index_for_usage)
+            .collect(),
+        _ => vec![],
+    }
+}
+
+/// Return the LED index of a key, given by name (e.g. `esc`, `f1`, `digit1`) or LED index
+pub fn key_index(name: &str) -> Option<usize> {
+    let name = name.trim().to_ascii_lowercase();
+    if let Ok(index) = name.parse::<usize>() {
+        return Some(index).filter(|&index| index < TOTAL_KEYS);
+    }
+    KEY_NAMES
+        .iter()
+        .find(|(n, _)| *n == name)
+        .and_then(|(_, usage)| key_index_for_usage(*usage))
+}
+
+/// Return the name of the key with the given LED index
+pub fn key_name(key_index: usize) -> Option<&'static str> {
+    let usage = USAGES
+        .iter()
+        .find(|(index, _)| *index == key_index)
+        .map(|(_, usage)| *usage)?;
+    KEY_NAMES
+        .iter()
+        .find(|(_, u)| *u == usage)
+        .map(|(name, _)| *name)
+}
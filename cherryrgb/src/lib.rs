@@ -52,27 +52,66 @@
 //! keyboard.set_custom_colors(keys).unwrap();
 //! ```
 
+mod calibration;
+pub mod color;
+mod compositor;
+mod effect;
 mod extensions;
+mod heatmap;
+mod history;
+mod keyframes;
+pub mod layout;
 mod models;
+pub mod nightlight;
+mod notification;
+mod profile;
+pub mod protocol;
+pub mod schedule;
+#[cfg(feature = "scripting")]
+mod script;
+mod transition;
 #[cfg(all(target_os = "linux", feature = "uhid"))]
 mod vkbd;
 
 use binrw::BinReaderExt;
-use models::{Keymap, ProfileKey};
-use rgb::RGB8;
+use models::{Keymap, PayloadReadWrite, ProfileKey};
+use protocol::{
+    GetCustomLED, GetKeyIndexes, GetKeymap, Read, ReadConfig, TransactionEnd, TransactionStart,
+    Unknown5, WriteConfig,
+};
 use rusb::UsbContext;
-use serde_json::{self, Value};
-use std::{str::FromStr, time::Duration};
+use std::{
+    path::Path,
+    sync::atomic::{AtomicUsize, Ordering},
+    time::Duration,
+};
 use thiserror::Error;
 
 // Re-exports
+pub use calibration::Calibration;
+pub use color::ColorParseError;
+pub use compositor::{BlendMode, Compositor, Layer};
+pub use effect::Effect;
 pub use extensions::{OwnRGB8, ToVec};
+pub use heatmap::{HeatmapScale, KeyPressCounts};
 pub use hex;
+pub use history::{History, HISTORY_LENGTH};
+pub use keyframes::{Easing, Keyframe, KeyframeAnimation, KeyframePlayer, RepeatMode};
+pub use models::{
+    AnimationConfig, AnimationParams, Brightness, CustomKeyLeds, DeviceConfig, LightingMode,
+    LightingState, Packet, Payload, RawResponse, RpcEffect, RpcEffectSource, SavedLighting, Scene,
+    Speed, DEVICE_CONFIG_SIZE,
+};
 #[cfg(all(target_os = "linux", feature = "uhid"))]
-pub use models::RpcAnimation;
-pub use models::{Brightness, CustomKeyLeds, LightingMode, Packet, Payload, Speed};
+pub use models::{RpcAnimation, RpcHeatmap, RpcLighting, RpcTransition};
+pub use notification::Notification;
+pub use profile::{KeyRef, Profile, PROFILE_VERSION};
+pub use protocol::{Request, ResponseFor};
 pub use rgb;
 pub use rusb;
+#[cfg(feature = "scripting")]
+pub use script::ScriptEffect;
+pub use transition::{Transition, TransitionStep};
 #[cfg(all(target_os = "linux", feature = "uhid"))]
 pub use vkbd::VirtKbd;
 
@@ -81,14 +120,19 @@ pub use vkbd::VirtKbd;
 pub const CHERRY_USB_VID: u16 = 0x046a;
 
 const INTERFACE_NUM: u8 = 1;
+/// Boot keyboard interface, left to the kernel driver, carries the lock LEDs
+const BOOT_INTERFACE_NUM: u8 = 0;
 const INTERRUPT_EP: u8 = 0x82;
 static TIMEOUT: Duration = Duration::from_millis(1000);
 #[cfg(all(target_os = "linux", feature = "uhid"))]
 static SHORT_TIMEOUT: Duration = Duration::from_millis(100);
 
+/// Size of packets sent to the keyboard
+const PACKET_SIZE: usize = 64;
 /// (64 byte packet - 4 byte packet header - 4 byte payload header)
 const CHUNK_SIZE: usize = 56;
-const TOTAL_KEYS: usize = 126;
+/// Number of key LEDs
+pub const TOTAL_KEYS: usize = 126;
 
 #[derive(Debug, Error)]
 pub enum CherryRgbError {
@@ -112,6 +156,8 @@ pub enum CherryRgbError {
     JsonParseError(#[from] serde_json::Error),
     #[error("Protocol error")]
     ProtocolError(String),
+    #[error("Script error: {0}")]
+    ScriptError(String),
 }
 
 /// Calculate packet checksum (index 1 in payload)
@@ -127,6 +173,22 @@ fn calc_checksum(payload_type: u8, data: &[u8]) -> u16 {
     sum
 }
 
+/// Serialize packet and pad it to `PACKET_SIZE`, fails if it doesn't fit
+fn padded_packet_bytes<T>(packet: &Packet<T>) -> Result<Vec<u8>, CherryRgbError>
+where
+    T: PayloadReadWrite + Clone,
+{
+    let mut packet_bytes = packet.clone().to_vec();
+    if packet_bytes.len() > PACKET_SIZE {
+        return Err(CherryRgbError::InvalidArgument(
+            format!("Packet exceeds {PACKET_SIZE} bytes"),
+            packet_bytes.len().to_string(),
+        ));
+    }
+    packet_bytes.resize(PACKET_SIZE, 0x00);
+    Ok(packet_bytes)
+}
+
 /// Return true, if supplied product id is not blacklisted
 fn is_supported(product_id: u16) -> bool {
     let blacklist: Vec<u16> = vec![
@@ -159,43 +221,107 @@ pub fn find_devices(product_id: Option<u16>) -> Result<Vec<(u16, u16)>, CherryRg
     Ok(usb_ids)
 }
 
-/// Reads the given color profile and returns a vector of `ProfileKey`.
+/// Reads the given color profile (JSON, any version) and returns a vector of `ProfileKey`.
 /// # Arguments
 /// * `color_profile` - Color profile content.
 pub fn read_color_profile(color_profile: &str) -> Result<Vec<ProfileKey>, CherryRgbError> {
-    let v: Value = serde_json::from_str(color_profile)?;
-
-    v.as_object().map_or(
-        Err(CherryRgbError::ParseError(
-            "No valid colors found in color profile.".into(),
-        )),
-        |root| {
-            root.iter()
-                .map(|(key, value)| {
-                    let key_index = key.parse::<usize>().map_err(|err| {
-                        CherryRgbError::ParseError(format!("parsing key index {}, err={err}", key))
-                    })?;
-                    let color = value.as_str().map_or(
-                        Err(CherryRgbError::ParseError(format!(
-                            "Invalid color for key with index {key_index}"
-                        ))),
-                        |hex| match OwnRGB8::from_str(hex) {
-                            Ok(color) => Ok(color),
-                            Err(e) => Err(CherryRgbError::ParseError(format!(
-                                "Failed parsing hex color '{hex}', err: {e}"
-                            ))),
-                        },
-                    )?;
-                    Ok(ProfileKey::new(key_index, color))
-                })
-                .collect()
-        },
-    )
+    Profile::parse(color_profile, FileFormat::Json)?.profile_keys()
+}
+
+/// Replace a file atomically by writing a temporary file first,
+/// so a crash never leaves a partially written file behind.
+/// The temporary file name is unique, so concurrent writers don't clobber each other.
+pub(crate) fn write_file_atomically(path: &Path, content: &str) -> Result<(), CherryRgbError> {
+    static TEMP_COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+    let write_error =
+        |err: std::io::Error| CherryRgbError::ParseError(format!("writing {:?}, err={err}", path));
+    let mut temp_path = path.as_os_str().to_owned();
+    temp_path.push(format!(
+        ".{}.{}.tmp",
+        std::process::id(),
+        TEMP_COUNTER.fetch_add(1, Ordering::Relaxed)
+    ));
+    let result = std::fs::File::create(&temp_path)
+        .and_then(|mut file| {
+            std::io::Write::write_all(&mut file, content.as_bytes())?;
+            file.sync_all()
+        })
+        .and_then(|_| std::fs::rename(&temp_path, path));
+    if result.is_err() {
+        let _ = std::fs::remove_file(&temp_path);
+    }
+    result.map_err(write_error)
+}
+
+/// Format of color profile and animation files
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FileFormat {
+    Json,
+    Toml,
+    Yaml,
+}
+
+impl FileFormat {
+    /// Determine format by file extension, defaults to JSON
+    pub fn from_path(path: &Path) -> Self {
+        match path.extension().and_then(|ext| ext.to_str()) {
+            Some("toml") => FileFormat::Toml,
+            Some("yaml" | "yml") => FileFormat::Yaml,
+            _ => FileFormat::Json,
+        }
+    }
+}
+
+/// Reads the given keyframe animation.
+/// # Arguments
+/// * `animation` - Animation file content.
+/// * `format` - Format of the content.
+pub fn read_keyframe_animation(
+    animation: &str,
+    format: FileFormat,
+) -> Result<KeyframeAnimation, CherryRgbError> {
+    match format {
+        FileFormat::Json => Ok(serde_json::from_str(animation)?),
+        FileFormat::Toml => toml::from_str(animation)
+            .map_err(|err| CherryRgbError::ParseError(format!("parsing animation, err={err}"))),
+        FileFormat::Yaml => serde_yaml::from_str(animation)
+            .map_err(|err| CherryRgbError::ParseError(format!("parsing animation, err={err}"))),
+    }
+}
+
+/// Reads the given scene.
+/// # Arguments
+/// * `scene` - Scene file content.
+/// * `format` - Format of the content.
+pub fn read_scene(scene: &str, format: FileFormat) -> Result<Scene, CherryRgbError> {
+    let scene: Scene = match format {
+        FileFormat::Json => serde_json::from_str(scene)?,
+        FileFormat::Toml => toml::from_str(scene)
+            .map_err(|err| CherryRgbError::ParseError(format!("parsing scene, err={err}")))?,
+        FileFormat::Yaml => serde_yaml::from_str(scene)
+            .map_err(|err| CherryRgbError::ParseError(format!("parsing scene, err={err}")))?,
+    };
+    scene.validate()?;
+    Ok(scene)
+}
+
+/// Serializes the given scene, see `read_scene`
+pub fn write_scene(scene: &Scene, format: FileFormat) -> Result<String, CherryRgbError> {
+    match format {
+        FileFormat::Json => Ok(serde_json::to_string_pretty(scene)?),
+        FileFormat::Toml => toml::to_string(scene)
+            .map_err(|err| CherryRgbError::ParseError(format!("writing scene, err={err}"))),
+        FileFormat::Yaml => serde_yaml::to_string(scene)
+            .map_err(|err| CherryRgbError::ParseError(format!("writing scene, err={err}"))),
+    }
 }
 
 /// Holds a handle to the USB keyboard device
 pub struct CherryKeyboard {
     device_handle: rusb::DeviceHandle<rusb::Context>,
+    calibration: Calibration,
+    device_id: String,
 }
 
 impl CherryKeyboard {
@@ -259,16 +385,93 @@ impl CherryKeyboard {
             .claim_interface(INTERFACE_NUM)
             .map_err(|e| CherryRgbError::UsbError("Failed to claim interface".into(), e))?;
 
-        Ok(Self { device_handle })
+        // Keyboards without serial number are told apart by the port they are plugged into
+        let serial = device_handle
+            .read_serial_number_string_ascii(&device_desc)
+            .ok()
+            .map(|serial| serial.trim().to_string())
+            .filter(|serial| !serial.is_empty());
+        let device_id = match serial {
+            Some(serial) => format!("{product_id:04x}_{serial}"),
+            None => {
+                let ports: Vec<String> = device
+                    .port_numbers()
+                    .unwrap_or_default()
+                    .iter()
+                    .map(u8::to_string)
+                    .collect();
+                format!(
+                    "{product_id:04x}_usb{}-{}",
+                    device.bus_number(),
+                    ports.join(".")
+                )
+            }
+        };
+        log::debug!("Device id: {device_id}");
+
+        Ok(Self {
+            device_handle,
+            calibration: Calibration::default(),
+            device_id,
+        })
+    }
+
+    /// Identity of the keyboard: product id and serial number, or USB port if there is no serial number
+    pub fn device_id(&self) -> &str {
+        &self.device_id
     }
 
-    /// Writes a control packet first, then reads interrupt packet
-    fn send_payload(&self, payload: Payload) -> Result<Option<Packet<Payload>>, CherryRgbError> {
-        let packet = Packet::new(payload);
+    /// Correct all colors sent to the keyboard from now on
+    pub fn set_calibration(&mut self, calibration: Calibration) {
+        self.calibration = calibration;
+    }
+
+    pub fn calibration(&self) -> &Calibration {
+        &self.calibration
+    }
 
-        // Serialize and pad to 64 bytes
-        let mut packet_bytes = packet.clone().to_vec();
-        packet_bytes.resize(64, 0x00);
+    /// Writes a control packet first, then reads interrupt packet and parses the response
+    fn send_payload<R: Request>(&self, request: R) -> Result<ResponseFor<R>, CherryRgbError> {
+        let response = self.send_packet(Packet::new(request))?;
+        std::io::Cursor::new(response.data)
+            .read_ne::<Packet<ResponseFor<R>>>()
+            .map(Packet::into_payload)
+            .map_err(|err| CherryRgbError::ProtocolError(format!("Unexpected response, err={err}")))
+    }
+
+    /// Send a payload of any type as is, for protocol research.
+    /// The checksum is calculated, optionally the payload is wrapped in a transaction.
+    pub fn send_raw(
+        &self,
+        payload_type: u8,
+        data: &[u8],
+        transaction: bool,
+    ) -> Result<RawResponse, CherryRgbError> {
+        let packet = Packet::new(Payload::Unhandled {
+            payload_type,
+            data: data.to_vec(),
+        });
+        // Reject oversized data before starting a transaction
+        padded_packet_bytes(&packet)?;
+
+        if transaction {
+            self.start_transaction()?;
+        }
+        let response = self.send_packet(packet);
+        if transaction {
+            // Don't discard a response that was already received
+            if let Err(err) = self.end_transaction() {
+                log::warn!("Failed to end transaction, err={err:?}");
+            }
+        }
+        response
+    }
+
+    fn send_packet<T>(&self, packet: Packet<T>) -> Result<RawResponse, CherryRgbError>
+    where
+        T: PayloadReadWrite + Clone + std::fmt::Debug,
+    {
+        let packet_bytes = padded_packet_bytes(&packet)?;
 
         let mut response = [0u8; 64];
         self.device_handle
@@ -312,131 +515,114 @@ impl CherryKeyboard {
             detail_info
         );
 
-        Ok(resp_payload.ok())
+        Ok(RawResponse {
+            data: response.to_vec(),
+            packet: resp_payload.ok(),
+        })
+    }
+
+    /// Show `state` on the physical lock LEDs.
+    /// Lock changes made through the virtual keyboard don't reach them by themselves.
+    pub fn set_lock_leds(&self, state: layout::LockState) -> Result<(), CherryRgbError> {
+        self.device_handle
+            .write_control(
+                rusb::request_type(
+                    rusb::Direction::Out,
+                    rusb::RequestType::Class,
+                    rusb::Recipient::Interface,
+                ),
+                0x09,                      // Request - SET_REPORT
+                0x0200,                    // Value - ReportId: 0, ReportType: Output
+                BOOT_INTERFACE_NUM as u16, // Index
+                &[state.leds()],           // Data
+                TIMEOUT,
+            )
+            .map_err(|err| CherryRgbError::UsbError("Setting lock LEDs failed".into(), err))?;
+
+        Ok(())
     }
 
     /// Start RGB setting transaction
     fn start_transaction(&self) -> Result<(), CherryRgbError> {
-        self.send_payload(Payload::TransactionStart)?;
+        self.send_payload(TransactionStart)?;
 
         Ok(())
     }
 
     /// End RGB setting transaction
     fn end_transaction(&self) -> Result<(), CherryRgbError> {
-        self.send_payload(Payload::TransactionEnd)?;
+        self.send_payload(TransactionEnd)?;
 
         Ok(())
     }
 
-    fn get_keymap(&self) -> Result<Vec<Option<Keymap>>, CherryRgbError> {
-        // 3 bytes per key are returned to reflect the keymap
-        let total_size = TOTAL_KEYS * 3;
-
-        // Send requests and gather payloads
-        let packets: Vec<Result<Option<Packet<Payload>>, CherryRgbError>> = (0..total_size)
-            .step_by(CHUNK_SIZE)
-            .map(|offset| {
-                let len = std::cmp::min(total_size - offset, CHUNK_SIZE);
-
-                self.send_payload(Payload::GetKeymap {
-                    data_len: len as u8,
-                    data_offset: offset as u16,
-                    padding: 0,
-                    keymap: vec![],
-                })
-            })
-            .collect();
-
-        // Check if any of the requests errored out
-        if packets.iter().any(|res| res.is_err()) {
-            return Err(CherryRgbError::ProtocolError(
-                "GetKeymap received unexpected response(s)".into(),
-            ));
+    /// Send read requests and gather the data read
+    fn read_chunked<const PAYLOAD_TYPE: u8>(
+        &self,
+        requests: impl Iterator<Item = Read<PAYLOAD_TYPE>>,
+    ) -> Result<Vec<u8>, CherryRgbError> {
+        let mut data = vec![];
+        for request in requests {
+            let len = request.data_len as usize;
+            let response = self.send_payload(request)?;
+            if response.data.len() != len {
+                return Err(CherryRgbError::ProtocolError(format!(
+                    "Read of payload type {PAYLOAD_TYPE:#x} returned {} instead of {len} bytes",
+                    response.data.len()
+                )));
+            }
+            data.extend(response.data);
         }
+        Ok(data)
+    }
 
-        // Unwrap the data
-        let all_keys: Vec<Option<Keymap>> = packets
-            .into_iter()
-            .filter_map(|res| {
-                res.ok().unwrap_or(None).and_then(|inner| {
-                    if let Payload::GetKeymap { keymap, .. } = inner.payload() {
-                        Some(keymap.to_owned())
-                    } else {
-                        None
-                    }
-                })
-            })
-            .collect::<Vec<Vec<u8>>>()
-            .concat()
-            .chunks(3)
-            .map(|x| {
-                if x.len() != 3 {
-                    return None;
-                }
+    fn get_keymap(&self) -> Result<Vec<Keymap>, CherryRgbError> {
+        // 3 bytes per key are returned to reflect the keymap
+        let data = self.read_chunked(GetKeymap::chunked(0x00, TOTAL_KEYS * 3))?;
 
-                Some(Keymap {
-                    modifier: x[0],
-                    unk: x[1],
-                    keycode: x[2],
-                })
+        Ok(data
+            .chunks(3)
+            .map(|x| Keymap {
+                modifier: x[0],
+                unk: x[1],
+                keycode: x[2],
             })
-            .collect();
-
-        Ok(all_keys)
+            .collect())
     }
 
     fn get_key_indexes(&self) -> Result<Vec<u8>, CherryRgbError> {
-        // Send requests and gather payloads
-        let packets: Vec<Result<Option<Packet<Payload>>, CherryRgbError>> = (0..TOTAL_KEYS)
-            .step_by(CHUNK_SIZE)
-            .map(|offset| {
-                let len = std::cmp::min(TOTAL_KEYS - offset, CHUNK_SIZE);
-
-                self.send_payload(Payload::GetKeyIndexes {
-                    data_len: len as u8,
-                    data_offset: offset as u16,
-                    padding: 0,
-                    key_data: vec![],
-                })
-            })
-            .collect();
-
-        // Check if any of the requests errored out
-        if packets.iter().any(|res| res.is_err()) {
-            return Err(CherryRgbError::ProtocolError(
-                "GetKeyIndexes received unexpected response(s)".into(),
-            ));
-        }
-
-        // Unwrap the data
-        let all_keys: Vec<u8> = packets
-            .into_iter()
-            .filter_map(|res| {
-                res.ok().unwrap_or(None).and_then(|inner| {
-                    if let Payload::GetKeyIndexes { key_data, .. } = inner.payload() {
-                        Some(key_data.to_owned())
-                    } else {
-                        None
-                    }
-                })
-            })
-            .collect::<Vec<Vec<u8>>>()
-            .concat();
+        self.read_chunked(GetKeyIndexes::chunked(0x00, TOTAL_KEYS))
+    }
 
-        if all_keys.len() != TOTAL_KEYS {
-            return Err(CherryRgbError::ProtocolError(
-                "Gathering of key indexes failed".into(),
-            ));
+    /// Read custom colors as stored on the keyboard, i.e. after calibration and scaling.
+    ///
+    /// Experimental: the request is a raw `SetCustomLED` packet without color data, i.e.
+    /// it sends the bytes of writing black. Use the saved lighting to modify colors instead.
+    pub fn get_custom_colors(&self) -> Result<CustomKeyLeds, CherryRgbError> {
+        // 3 bytes per key, as written by `SetCustomLED`
+        self.start_transaction()?;
+        let data = self.read_chunked(GetCustomLED::chunked(0x00, TOTAL_KEYS * 3));
+        // Don't discard colors that were already received
+        if let Err(err) = self.end_transaction() {
+            log::warn!("Failed to end transaction, err={err:?}");
         }
 
-        Ok(all_keys)
+        CustomKeyLeds::from_leds(
+            data?
+                .chunks(3)
+                .map(|rgb| OwnRGB8::new(rgb[0], rgb[1], rgb[2]))
+                .collect(),
+        )
     }
 
     /// forward a key event from our usb device to the virtual UHID keyboard,
     /// filter out any bogus events while doing so.
+    /// Returns the forwarded report, if any.
     #[cfg(all(target_os = "linux", feature = "uhid"))]
-    pub fn forward_filtered_keys(&self, vdevice: &mut VirtKbd) -> Result<(), CherryRgbError> {
+    pub fn forward_filtered_keys(
+        &self,
+        vdevice: &mut VirtKbd,
+    ) -> Result<Option<Vec<u8>>, CherryRgbError> {
         let mut buf = [0; 64];
         match self
             .device_handle
@@ -446,26 +632,32 @@ impl CherryKeyboard {
                 // Bogus event data has bit 3 set in the 3rd byte
                 if (len >= 3 && buf[2] >= 8) || (len == 9 && buf[0] == 5) {
                     log::debug!(" - BOGUS read {} bytes: {:?} filtered", len, &buf[..len]);
-                    return Ok(());
+                    return Ok(None);
                 }
                 log::debug!(" - read {} bytes: {:?}", len, &buf[..len]);
                 vdevice.forward(&buf[..len]);
+                Ok(Some(buf[..len].to_vec()))
             }
             Err(err) => {
                 if err == rusb::Error::Timeout {
-                    return Ok(());
+                    return Ok(None);
                 }
-                return Err(CherryRgbError::GeneralUsbError(err));
+                Err(CherryRgbError::GeneralUsbError(err))
             }
         }
-        Ok(())
     }
 
     /// Just taken 1:1 from usb capture
     pub fn fetch_device_state(&self) -> Result<(), CherryRgbError> {
         log::trace!("Fetching device state - START");
         self.start_transaction()?;
-        self.send_payload(Payload::Unknown3 { unk: 0x22 })?;
+        let config = self
+            .read_config(0x00, DEVICE_CONFIG_SIZE)
+            .and_then(|data| DeviceConfig::from_bytes(&data));
+        match config {
+            Ok(res) => log::debug!("Device config: {res:?}"),
+            Err(err) => log::warn!("Fetching device config failed, err={}", err),
+        }
 
         match self.get_keymap() {
             Ok(res) => log::debug!("Key indexes: {res:#?}"),
@@ -482,6 +674,30 @@ impl CherryKeyboard {
         Ok(())
     }
 
+    /// Read `len` bytes of the device configuration from `offset`
+    pub fn read_config(&self, offset: u16, len: usize) -> Result<Vec<u8>, CherryRgbError> {
+        self.read_chunked(ReadConfig::chunked(offset, len))
+    }
+
+    /// Write `data` into the device configuration at `offset`
+    pub fn write_config(&self, offset: u16, data: &[u8]) -> Result<(), CherryRgbError> {
+        for (index, chunk) in data.chunks(CHUNK_SIZE).enumerate() {
+            self.send_payload(WriteConfig {
+                offset: offset + (index * CHUNK_SIZE) as u16,
+                data: chunk.to_vec(),
+            })?;
+        }
+        Ok(())
+    }
+
+    /// Read the device configuration, e.g. the current LED animation
+    pub fn get_device_config(&self) -> Result<DeviceConfig, CherryRgbError> {
+        self.start_transaction()?;
+        let data = self.read_config(0x00, DEVICE_CONFIG_SIZE);
+        self.end_transaction()?;
+        DeviceConfig::from_bytes(&data?)
+    }
+
     /// Set LED animation from different modes
     pub fn set_led_animation<C: Into<OwnRGB8>>(
         &self,
@@ -492,29 +708,17 @@ impl CherryKeyboard {
         rainbow: bool,
     ) -> Result<(), CherryRgbError> {
         log::trace!("Set LED animation - START");
-        self.start_transaction()?;
-        // Send main payload
-        self.send_payload(Payload::SetAnimation {
-            unknown: [0x09, 0x00, 0x00, 0x55, 0x00],
+        let animation = AnimationConfig::from(AnimationParams {
             mode,
             brightness,
             speed,
-            pad: 0x0,
-            rainbow: if rainbow { 1 } else { 0 },
-            color: color.into(),
-        })?;
-        // Send unknown / ?static? bytes
-        self.send_payload(Payload::SetAnimation {
-            unknown: [0x01, 0x18, 0x00, 0x55, 0x01],
-            // Everything after unknown is nulled
-            mode: LightingMode::Wave,
-            brightness: Brightness::Off,
-            speed: Speed::VeryFast,
-            pad: 0x0,
-            rainbow: 0x0,
-            color: RGB8::new(0, 0, 0).into(),
-        })?;
-
+            color: self.calibration.apply(&color.into()),
+            rainbow,
+        });
+        self.start_transaction()?;
+        self.write_config(AnimationConfig::OFFSET, &animation.to_vec())?;
+        // Unknown, but sent by the official software as well
+        self.write_config(DeviceConfig::OFFSET_UNKNOWN_18, &[0x01])?;
         self.end_transaction()?;
         log::trace!("Set LED animation - END");
         Ok(())
@@ -522,20 +726,55 @@ impl CherryKeyboard {
 
     /// Set custom color for each individual key
     pub fn set_custom_colors(&self, key_leds: CustomKeyLeds) -> Result<(), CherryRgbError> {
+        self.set_custom_colors_with_brightness(key_leds, Brightness::Full)
+    }
+
+    /// Set custom color for each individual key at the given hardware brightness
+    pub fn set_custom_colors_with_brightness(
+        &self,
+        key_leds: CustomKeyLeds,
+        brightness: Brightness,
+    ) -> Result<(), CherryRgbError> {
         log::trace!("Set custom colors - START");
         // Set custom led mode
         self.set_led_animation(
             LightingMode::Custom,
-            Brightness::Full,
+            brightness,
             Speed::Slow,
             OwnRGB8::default(),
             false,
         )?;
 
+        self.update_custom_colors(key_leds)?;
+        log::trace!("Set custom colors - END");
+        Ok(())
+    }
+
+    /// Apply scene: set the lighting mode, then send the custom colors, if any
+    pub fn apply_scene(&self, scene: &Scene) -> Result<(), CherryRgbError> {
+        self.set_led_animation(
+            scene.mode.clone(),
+            scene.brightness.clone(),
+            scene.speed.clone(),
+            scene.color.clone(),
+            scene.rainbow,
+        )?;
+        if let Some(keys) = &scene.keys {
+            self.update_custom_colors(keys.clone())?;
+        }
+        Ok(())
+    }
+
+    /// Send custom colors without switching the lighting mode first.
+    /// Useful for streaming frames, requires a preceding `set_custom_colors`.
+    pub fn update_custom_colors(&self, key_leds: CustomKeyLeds) -> Result<(), CherryRgbError> {
+        let key_leds = match self.calibration.is_identity() {
+            true => key_leds,
+            false => self.calibration.apply_leds(&key_leds),
+        };
         for payload in key_leds.get_payloads()? {
             self.send_payload(payload)?;
         }
-        log::trace!("Set custom colors - END");
         Ok(())
     }
 
@@ -546,11 +785,61 @@ impl CherryKeyboard {
         self.set_custom_colors(CustomKeyLeds::new())?;
 
         // Payloads, type: 0x5
-        self.send_payload(Payload::Unknown5 { unk: 0x01 })?;
-        self.send_payload(Payload::Unknown5 { unk: 0x19 })?;
+        self.send_payload(Unknown5 { unk: 0x01 })?;
+        self.send_payload(Unknown5 { unk: 0x19 })?;
         log::trace!("Reset custom colors - END");
         Ok(())
     }
+
+    /// Apply a single step of a `Transition`
+    pub fn apply_transition_step(&self, step: &TransitionStep) -> Result<(), CherryRgbError> {
+        match step {
+            TransitionStep::Animation(params) => self.set_led_animation(
+                params.mode.clone(),
+                params.brightness.clone(),
+                params.speed.clone(),
+                params.color.clone(),
+                params.rainbow,
+            ),
+            TransitionStep::CustomMode(key_leds) => self.set_custom_colors(key_leds.clone()),
+            TransitionStep::CustomColors(key_leds) => self.update_custom_colors(key_leds.clone()),
+        }
+    }
+
+    /// Smoothly change the lighting from `from` to `to`, blocks for `duration`
+    pub fn transition(
+        &self,
+        from: Option<&LightingState>,
+        to: &LightingState,
+        duration: Duration,
+    ) -> Result<(), CherryRgbError> {
+        self.transition_with_brightness(from, to, duration, Brightness::Full)
+    }
+
+    /// Like `transition`, custom colors are shown at the given hardware brightness
+    pub fn transition_with_brightness(
+        &self,
+        from: Option<&LightingState>,
+        to: &LightingState,
+        duration: Duration,
+        brightness: Brightness,
+    ) -> Result<(), CherryRgbError> {
+        log::trace!("Transition - START");
+        let started = std::time::Instant::now();
+        for (at, step) in Transition::new(from, to, duration).steps() {
+            if let Some(wait) = at.checked_sub(started.elapsed()) {
+                std::thread::sleep(wait);
+            }
+            match step {
+                TransitionStep::CustomMode(key_leds) => {
+                    self.set_custom_colors_with_brightness(key_leds.clone(), brightness.clone())?
+                }
+                step => self.apply_transition_step(step)?,
+            }
+        }
+        log::trace!("Transition - END");
+        Ok(())
+    }
 }
 
 #[cfg(test)]
@@ -558,9 +847,12 @@ mod tests {
     use crate::models::PayloadType;
 
     use super::*;
+    use crate::color::{ColorSpace, Hsl, Hsv, Oklab};
     use binrw::BinReaderExt;
     use rgb::{ComponentSlice, RGB8};
+    use std::convert::TryFrom;
     use std::io::Cursor;
+    use std::str::FromStr;
 
     /// Some captures packets
     fn packets() -> Vec<&'static str> {
@@ -604,7 +896,7 @@ mod tests {
             "04 53 00 1b 38 00 00",
             "04 8b 00 1b 38 38 00",
             "04 99 00 1b 0e 70 00",
-            // Unknown
+            // read custom colors packets
             "04 43 00 0b 38 00 00",
             "04 7b 00 0b 38 38 00",
             "04 b3 00 0b 38 70 00",
@@ -646,75 +938,54 @@ mod tests {
 
     #[test]
     fn serialize_led_animation_payload() {
-        let buf: Vec<u8> = Payload::SetAnimation {
-            unknown: [0x09, 0x00, 0x00, 0x55, 0x00],
-            mode: LightingMode::Vortex,
-            brightness: Brightness::Full,
-            speed: Speed::VerySlow,
-            pad: 0x0,
-            rainbow: 0,
-            color: OwnRGB8::new(244, 255, 100),
-        }
-        .to_vec();
+        let payload = |mode, brightness, speed, rainbow| {
+            let animation = AnimationConfig::from(AnimationParams {
+                mode,
+                brightness,
+                speed,
+                color: OwnRGB8::new(244, 255, 100),
+                rainbow,
+            });
+            Payload::WriteConfig {
+                offset: AnimationConfig::OFFSET,
+                data: animation.to_vec(),
+            }
+            .to_vec()
+        };
         assert_eq!(
             vec![0x09, 0x00, 0x00, 0x55, 0x00, 0x05, 0x04, 0x04, 0x00, 0x00, 0xF4, 0xFF, 0x64],
-            buf
-        );
-        let buf: Vec<u8> = Payload::SetAnimation {
-            unknown: [0x09, 0x00, 0x00, 0x55, 0x00],
-            mode: LightingMode::Vortex,
-            brightness: Brightness::Full,
-            speed: Speed::VerySlow,
-            pad: 0x0,
-            rainbow: 1,
-            color: OwnRGB8::new(244, 255, 100),
-        }
-        .to_vec();
+            payload(
+                LightingMode::Vortex,
+                Brightness::Full,
+                Speed::VerySlow,
+                false
+            )
+        );
         assert_eq!(
             vec![0x09, 0x00, 0x00, 0x55, 0x00, 0x05, 0x04, 0x04, 0x00, 0x01, 0xF4, 0xFF, 0x64],
-            buf
-        );
-        let buf: Vec<u8> = Payload::SetAnimation {
-            unknown: [0x09, 0x00, 0x00, 0x55, 0x00],
-            mode: LightingMode::Rolling,
-            brightness: Brightness::Full,
-            speed: Speed::VerySlow,
-            pad: 0x0,
-            rainbow: 0,
-            color: OwnRGB8::new(244, 255, 100),
-        }
-        .to_vec();
+            payload(
+                LightingMode::Vortex,
+                Brightness::Full,
+                Speed::VerySlow,
+                true
+            )
+        );
         assert_eq!(
             vec![0x09, 0x00, 0x00, 0x55, 0x00, 0x0A, 0x04, 0x04, 0x00, 0x00, 0xF4, 0xFF, 0x64],
-            buf
-        );
-        let buf: Vec<u8> = Payload::SetAnimation {
-            unknown: [0x09, 0x00, 0x00, 0x55, 0x00],
-            mode: LightingMode::Vortex,
-            brightness: Brightness::Full,
-            speed: Speed::Medium,
-            pad: 0x0,
-            rainbow: 0,
-            color: OwnRGB8::new(244, 255, 100),
-        }
-        .to_vec();
+            payload(
+                LightingMode::Rolling,
+                Brightness::Full,
+                Speed::VerySlow,
+                false
+            )
+        );
         assert_eq!(
             vec![0x09, 0x00, 0x00, 0x55, 0x00, 0x05, 0x04, 0x02, 0x00, 0x00, 0xF4, 0xFF, 0x64],
-            buf
-        );
-        let buf: Vec<u8> = Payload::SetAnimation {
-            unknown: [0x09, 0x00, 0x00, 0x55, 0x00],
-            mode: LightingMode::Vortex,
-            brightness: Brightness::Low,
-            speed: Speed::Medium,
-            pad: 0x0,
-            rainbow: 0,
-            color: OwnRGB8::new(244, 255, 100),
-        }
-        .to_vec();
+            payload(LightingMode::Vortex, Brightness::Full, Speed::Medium, false)
+        );
         assert_eq!(
             vec![0x09, 0x00, 0x00, 0x55, 0x00, 0x05, 0x01, 0x02, 0x00, 0x00, 0xF4, 0xFF, 0x64],
-            buf
+            payload(LightingMode::Vortex, Brightness::Low, Speed::Medium, false)
         );
     }
 
@@ -723,27 +994,46 @@ mod tests {
         let packet = Packet::new(Payload::TransactionStart).to_vec();
         assert_eq!(packet[..4], vec![0x04, 0x01, 0x00, 0x01]);
 
-        let packet = Packet::new(Payload::SetAnimation {
-            unknown: [0x01, 0x18, 0x00, 0x55, 0x01],
-            // Everything after unknown is nulled
-            mode: LightingMode::Wave,
-            brightness: Brightness::Off,
-            speed: Speed::VeryFast,
-            pad: 0x0,
-            rainbow: 0x0,
-            color: RGB8::new(0, 0, 0x42).into(),
+        let packet = Packet::new(Payload::WriteConfig {
+            offset: DeviceConfig::OFFSET_UNKNOWN_18,
+            data: vec![0x01],
         })
         .to_vec();
 
         assert_eq!(
-            packet[..17],
-            vec![
-                0x04, 0xB7, 0x00, 0x06, 0x01, 0x18, 0x00, 0x55, 0x01, 0x00, 0x00, 0x00, 0x00, 0x00,
-                0x00, 0x00, 0x42
-            ]
+            packet,
+            vec![0x04, 0x75, 0x00, 0x06, 0x01, 0x18, 0x00, 0x55, 0x01]
         );
     }
 
+    #[test]
+    fn device_config() {
+        // Captured animation packets parse as config writes
+        let pkt = hex::decode("04EC0106090000550013030000007E00F4").unwrap();
+        let deserialized: Packet<Payload> = Cursor::new(pkt).read_ne().unwrap();
+        let data = match deserialized.payload() {
+            Payload::WriteConfig { offset, data } => {
+                assert_eq!(*offset, AnimationConfig::OFFSET);
+                data.clone()
+            }
+            payload => panic!("{:?}", payload),
+        };
+
+        let mut config = data;
+        config.resize(DEVICE_CONFIG_SIZE, 0x00);
+        config[DeviceConfig::OFFSET_UNKNOWN_18 as usize] = 0x01;
+        let config = DeviceConfig::from_bytes(&config).unwrap();
+        let animation = AnimationParams::from(config.animation);
+        assert_eq!(animation.mode, LightingMode::Ripples);
+        assert_eq!(animation.brightness, Brightness::High);
+        assert_eq!(animation.speed, Speed::VeryFast);
+        assert_eq!(animation.color, OwnRGB8::new(0x7E, 0x00, 0xF4));
+        assert!(!animation.rainbow);
+        assert_eq!(config.unknown_18, 0x01);
+
+        assert!(DeviceConfig::from_bytes(&[0x00; 9]).is_err());
+    }
+
     #[test]
     fn unhandled_packet() {
         let packet = b"\x04\xEE\x01\x42\x09\x00\x00\x55\x00\x12\x03\x03\x00\x00\x7E\x00\xF4";
@@ -752,10 +1042,11 @@ mod tests {
             reader.read_ne().expect("Failed reading unhandled packet");
 
         assert_eq!(deserialized.checksum(), 0x1EE);
-        // Unknown payload types get mapped to 0xFF
-        assert_eq!(deserialized.payload().payload_type(), 0xFF);
+        // Unknown payload types are kept
+        assert_eq!(deserialized.payload().payload_type(), 0x42);
+        assert_eq!(deserialized.clone().to_vec(), packet.to_vec());
         match deserialized.payload() {
-            Payload::Unhandled { data } => {
+            Payload::Unhandled { data, .. } => {
                 assert_eq!(
                     data[..],
                     b"\x09\x00\x00\x55\x00\x12\x03\x03\x00\x00\x7E\x00\xF4"[..]
@@ -767,6 +1058,122 @@ mod tests {
         }
     }
 
+    #[test]
+    fn raw_packet() {
+        let packet = Packet::new(Payload::Unhandled {
+            payload_type: 0x03,
+            data: vec![0x22, 0x00, 0x00],
+        });
+        assert_eq!(packet.checksum(), 0x25);
+        packet.verify_checksum().expect("Checksum mismatch");
+
+        let bytes = padded_packet_bytes(&packet).expect("Failed serializing raw packet");
+        assert_eq!(bytes.len(), PACKET_SIZE);
+        assert_eq!(bytes[..7], b"\x04\x25\x00\x03\x22\x00\x00"[..]);
+        assert!(bytes[7..].iter().all(|&b| b == 0));
+
+        // 4 byte header + 60 bytes of data fit, anything longer is rejected
+        let fits = Packet::new(Payload::Unhandled {
+            payload_type: 0x42,
+            data: vec![0x01; 60],
+        });
+        assert!(padded_packet_bytes(&fits).is_ok());
+        let too_long = Packet::new(Payload::Unhandled {
+            payload_type: 0x42,
+            data: vec![0x01; 61],
+        });
+        assert!(matches!(
+            padded_packet_bytes(&too_long),
+            Err(CherryRgbError::InvalidArgument(..))
+        ));
+    }
+
+    #[test]
+    fn packet_round_trip() {
+        for (index, &pkt_str) in packets().iter().enumerate() {
+            let mut pkt =
+                hex::decode(pkt_str.replace(' ', "")).expect("Failed to convert pkt hexstream");
+            let deserialized: Packet<Payload> = Cursor::new(&pkt).read_ne().unwrap();
+            assert_eq!(
+                deserialized.to_vec(),
+                pkt,
+                "Failed round trip for pkt {}",
+                index
+            );
+
+            // Including the padding of USB reports
+            pkt.resize(64, 0x00);
+            let deserialized: Packet<Payload> = Cursor::new(&pkt).read_ne().unwrap();
+            assert_eq!(
+                deserialized.to_vec(),
+                pkt,
+                "Failed round trip for pkt {}",
+                index
+            );
+        }
+    }
+
+    #[test]
+    fn read_requests() {
+        let requests: Vec<Vec<u8>> = ReadConfig::chunked(0x00, DEVICE_CONFIG_SIZE)
+            .map(|r| Packet::new(r).to_vec())
+            .chain(GetKeymap::chunked(0x00, TOTAL_KEYS * 3).map(|r| Packet::new(r).to_vec()))
+            .chain(GetKeyIndexes::chunked(0x00, TOTAL_KEYS).map(|r| Packet::new(r).to_vec()))
+            .chain(GetCustomLED::chunked(0x00, TOTAL_KEYS * 3).map(|r| Packet::new(r).to_vec()))
+            .collect();
+        // Read requests as captured from the official software
+        let captured: Vec<Vec<u8>> = packets()
+            .iter()
+            .filter(|pkt| pkt.len() == "04 25 00 03 22 00 00".len())
+            .map(|pkt| hex::decode(pkt.replace(' ', "")).unwrap())
+            .collect();
+        // Captures end after the offset
+        let requests: Vec<&[u8]> = requests.iter().map(|packet| &packet[..7]).collect();
+        assert_eq!(requests, captured);
+    }
+
+    #[test]
+    fn read_custom_colors() {
+        // Responses carry the requested colors
+        let mut response = vec![0x04, 0x00, 0x00, 0x0b, 0x06, 0x03, 0x00, 0x00];
+        response.extend_from_slice(&[0xff, 0x00, 0x00, 0x00, 0x80, 0x00]);
+        let deserialized: Packet<ResponseFor<GetCustomLED>> = Cursor::new(&response)
+            .read_ne()
+            .expect("Failed reading response");
+        let response = deserialized.into_payload();
+        assert_eq!(response.data_offset, 3);
+        assert_eq!(response.data[..], [0xff, 0x00, 0x00, 0x00, 0x80, 0x00]);
+
+        // Responses of other types are rejected
+        let keymap = b"\x04\x00\x00\x07\x03\x00\x00\x00\x00\x00\x04";
+        assert!(Cursor::new(keymap)
+            .read_ne::<Packet<ResponseFor<GetCustomLED>>>()
+            .is_err());
+    }
+
+    #[test]
+    fn keep_existing_colors_twice() {
+        let mut keys = CustomKeyLeds::new();
+        keys.set_led(3, OwnRGB8::new(200, 100, 0)).unwrap();
+        let mut saved = SavedLighting::custom_colors(keys, Brightness::Medium, 50);
+
+        // Existing colors are taken from the saved lighting as given, not as applied,
+        // so keeping them repeatedly doesn't scale them again
+        for _ in 0..2 {
+            let profile = read_color_profile(r#"{ "1": "00ff00" }"#).unwrap();
+            let keys = saved.keys().modify_from(profile).unwrap();
+            let lighting = SavedLighting::custom_colors(keys, Brightness::Medium, 50);
+            saved = serde_json::from_str(&serde_json::to_string(&lighting).unwrap()).unwrap();
+            assert_eq!(saved.keys().leds()[1], OwnRGB8::new(0, 255, 0));
+            assert_eq!(saved.keys().leds()[3], OwnRGB8::new(200, 100, 0));
+            assert_eq!(saved.software_brightness, 50);
+            assert_eq!(
+                saved.applied_scene().unwrap().keys.unwrap().leds()[3],
+                OwnRGB8::new(100, 50, 0)
+            );
+        }
+    }
+
     #[test]
     fn deserialize_color_profile() {
         let color_profile = r#"
@@ -787,6 +1194,488 @@ mod tests {
         assert_eq!(match_this, profile_keys);
     }
 
+    #[test]
+    fn decode_key_report() {
+        // ESC (usage 0x29) and A (usage 0x04)
+        let mut report = [0u8; 16];
+        report[0] = 0x01;
+        report[1 + (0x29 - 4) / 8] |= 1 << ((0x29 - 4) % 8);
+        report[1] |= 0x01;
+        assert_eq!(layout::pressed_keys(&report), vec![9, 0]);
+        // Other report IDs are ignored
+        report[0] = 0x03;
+        assert!(layout::pressed_keys(&report).is_empty());
+        assert_eq!(layout::key_position(84), Some((14, 0)));
+        assert_eq!(layout::key_position(TOTAL_KEYS), None);
+    }
+
+    #[cfg(feature = "scripting")]
+    #[test]
+    fn script_effect() {
+        let mut effect = ScriptEffect::new(
+            "test",
+            r#"
+                for i in 0..KEYS { leds[i] = rgb(frame, 0, 0); }
+                for e in events { if e.pressed { leds[e.index] = rgb(0, 0, 255); } }
+            "#,
+        )
+        .unwrap();
+        effect.render(Duration::ZERO).unwrap();
+        effect.key_report(&[0x01, 0x01]);
+        let mut expected =
+            CustomKeyLeds::from_leds(vec![OwnRGB8::new(1, 0, 0); TOTAL_KEYS]).unwrap();
+        expected.set_led(9, OwnRGB8::new(0, 0, 255)).unwrap();
+        let frame = effect.render(Duration::from_millis(100)).unwrap().unwrap();
+        assert_eq!(frame.to_vec(), expected.to_vec());
+        // Media key reports don't release keyboard keys
+        effect.key_report(&[0x03, 0x00]);
+        let expected = CustomKeyLeds::from_leds(vec![OwnRGB8::new(2, 0, 0); TOTAL_KEYS]).unwrap();
+        let frame = effect.render(Duration::from_millis(200)).unwrap().unwrap();
+        assert_eq!(frame.to_vec(), expected.to_vec());
+
+        // Scripts can't import other files
+        let module = std::env::temp_dir().join(format!("cherryrgb_test_{}", std::process::id()));
+        std::fs::write(module.with_extension("rhai"), "export const X = 1;").unwrap();
+        let source = format!("import {:?} as m;", module.display().to_string());
+        let mut effect = ScriptEffect::new("test", &source).unwrap();
+        let result = effect.render(Duration::ZERO);
+        std::fs::remove_file(module.with_extension("rhai")).unwrap();
+        assert!(result.is_err());
+
+        // Endless loops get terminated
+        let mut effect = ScriptEffect::new("test", "loop {}").unwrap();
+        assert!(effect.render(Duration::ZERO).is_err());
+        assert!(ScriptEffect::new("test", "eval(\"1\")").is_err());
+    }
+
+    #[test]
+    fn keyframe_animation() {
+        let animation = r#"
+            repeat = "ping_pong"
+
+            [groups]
+            left = [0, 1]
+
+            [[keyframes]]
+            time = 0
+            keys = { left = "000000", "1" = "ff0000" }
+
+            [[keyframes]]
+            time = 1000
+            keys = { left = "ffffff" }
+        "#;
+        let animation = read_keyframe_animation(animation, FileFormat::Toml).unwrap();
+        let mut player = KeyframePlayer::new("test", &animation).unwrap();
+        let frame = |player: &mut KeyframePlayer, ms| {
+            let leds = player.render(Duration::from_millis(ms)).unwrap().unwrap();
+            leds.to_vec()[..6].to_vec()
+        };
+
+        assert_eq!(frame(&mut player, 0), vec![0, 0, 0, 0xff, 0, 0]);
+        assert_eq!(
+            frame(&mut player, 500),
+            vec![0x80, 0x80, 0x80, 0xff, 0x80, 0x80]
+        );
+        assert_eq!(frame(&mut player, 1000), vec![0xff; 6]);
+        // Going backwards
+        assert_eq!(frame(&mut player, 1500), frame(&mut player, 500));
+        assert_eq!(frame(&mut player, 2000), frame(&mut player, 0));
+
+        let mut animation = animation;
+        animation.keyframes[1]
+            .keys
+            .insert("126".into(), "ffffff".into());
+        assert!(KeyframePlayer::new("test", &animation).is_err());
+    }
+
+    #[test]
+    fn transition_steps() {
+        let red = CustomKeyLeds::from_leds(vec![OwnRGB8::new(255, 0, 0); TOTAL_KEYS]).unwrap();
+        let blue = CustomKeyLeds::from_leds(vec![OwnRGB8::new(0, 0, 255); TOTAL_KEYS]).unwrap();
+        let from = LightingState::CustomColors(red.clone());
+        let to = LightingState::CustomColors(blue.clone());
+
+        // Crossfade between custom colors, never blanking the keys
+        let transition = Transition::new(Some(&from), &to, Duration::from_millis(400));
+        let steps = transition.steps();
+        assert_eq!(steps.len(), 11);
+        assert_eq!(transition.duration(), Duration::from_millis(400));
+        assert!(matches!(&steps[0], (at, TransitionStep::CustomMode(leds))
+            if at.is_zero() && leds.leds() == red.leds()));
+        assert!(matches!(&steps[5].1, TransitionStep::CustomColors(leds)
+            if leds.leds()[0] == OwnRGB8::new(128, 0, 128)));
+        assert!(matches!(&steps[10].1, TransitionStep::CustomColors(leds)
+            if leds.leds() == blue.leds()));
+
+        // Hardware animation is faded out and in through the brightness levels
+        let animation = |mode, brightness| {
+            LightingState::Animation(AnimationParams {
+                mode,
+                brightness,
+                speed: Speed::Slow,
+                color: OwnRGB8::new(0, 255, 0),
+                rainbow: false,
+            })
+        };
+        let transition = Transition::new(
+            Some(&animation(LightingMode::Wave, Brightness::Medium)),
+            &animation(LightingMode::Static, Brightness::High),
+            Duration::from_millis(1000),
+        );
+        let levels: Vec<(LightingMode, Brightness)> = transition
+            .steps()
+            .iter()
+            .map(|(_, step)| match step {
+                TransitionStep::Animation(p) => (p.mode.clone(), p.brightness.clone()),
+                _ => panic!("unexpected step {:?}", step),
+            })
+            .collect();
+        assert_eq!(
+            levels,
+            vec![
+                (LightingMode::Wave, Brightness::Low),
+                (LightingMode::Wave, Brightness::Off),
+                (LightingMode::Static, Brightness::Off),
+                (LightingMode::Static, Brightness::Low),
+                (LightingMode::Static, Brightness::Medium),
+                (LightingMode::Static, Brightness::High),
+            ]
+        );
+        assert_eq!(transition.duration(), Duration::from_millis(1000));
+
+        // Without duration, the target is applied at once
+        let transition = Transition::new(Some(&from), &to, Duration::ZERO);
+        assert_eq!(transition.steps().len(), 1);
+    }
+
+    #[test]
+    fn dim_step() {
+        let params = AnimationParams {
+            mode: LightingMode::Wave,
+            brightness: Brightness::High,
+            speed: Speed::Slow,
+            color: OwnRGB8::new(255, 0, 0),
+            rainbow: false,
+        };
+        match TransitionStep::Animation(params.clone()).dim(&Brightness::Low) {
+            TransitionStep::Animation(dimmed) => assert_eq!(dimmed.brightness, Brightness::Low),
+            step => panic!("Unexpected step {:?}", step),
+        }
+        match TransitionStep::Animation(params).dim(&Brightness::Full) {
+            TransitionStep::Animation(dimmed) => assert_eq!(dimmed.brightness, Brightness::High),
+            step => panic!("Unexpected step {:?}", step),
+        }
+
+        let leds = CustomKeyLeds::from_leds(vec![OwnRGB8::new(200, 100, 0)]).unwrap();
+        match TransitionStep::CustomColors(leds).dim(&Brightness::Medium) {
+            TransitionStep::CustomColors(dimmed) => {
+                assert_eq!(dimmed.leds()[0], OwnRGB8::new(100, 50, 0))
+            }
+            step => panic!("Unexpected step {:?}", step),
+        }
+    }
+
+    #[test]
+    fn scale_custom_colors() {
+        let leds = CustomKeyLeds::from_leds(vec![OwnRGB8::new(255, 100, 0)]).unwrap();
+        assert_eq!(leds.scale(0.5).leds()[0], OwnRGB8::new(128, 50, 0));
+        assert_eq!(leds.scale(0.0).leds()[0], OwnRGB8::new(0, 0, 0));
+        assert_eq!(leds.scale(1.0).leds()[0], OwnRGB8::new(255, 100, 0));
+        assert_eq!(Brightness::Medium.factor(), 0.5);
+    }
+
+    #[test]
+    fn calibration() {
+        let calibration = Calibration::from_toml(
+            "gamma = [1.0, 2.0, 1.0]\nwhite_point = [1.0, 1.0, 0.5]\nmin_visible = 10",
+        )
+        .unwrap();
+        assert_eq!(
+            calibration.apply(&OwnRGB8::new(255, 255, 255)),
+            OwnRGB8::new(255, 255, 128)
+        );
+        assert_eq!(
+            calibration.apply(&OwnRGB8::new(0, 128, 2)),
+            OwnRGB8::new(0, 64, 10)
+        );
+        assert!(Calibration::default().is_identity());
+        assert_eq!(
+            Calibration::from_toml(&calibration.to_toml()).unwrap(),
+            calibration
+        );
+        // Missing fields keep their defaults
+        assert_eq!(
+            Calibration::from_toml("min_visible = 10").unwrap().gamma,
+            [1.0; 3]
+        );
+    }
+
+    #[test]
+    fn compositor() {
+        let color = |r, g, b| CustomKeyLeds::from_leds(vec![OwnRGB8::new(r, g, b); 2]).unwrap();
+        let base = color(100, 200, 0);
+
+        let mut compositor = Compositor::default();
+        compositor.set_layer(Layer::new("add", 1, BlendMode::Add, color(100, 100, 10)));
+        compositor.set_layer(Layer::new(
+            "dim",
+            5,
+            BlendMode::Multiply,
+            color(128, 128, 128),
+        ));
+        let mut flash = Layer::new("flash", 3, BlendMode::Replace, color(0, 0, 255));
+        // Only the first key is covered, half transparent
+        flash.alpha = Some(vec![128]);
+        compositor.set_layer(flash);
+
+        let names: Vec<&str> = compositor
+            .layers()
+            .iter()
+            .map(|l| l.name.as_str())
+            .collect();
+        assert_eq!(names, vec!["add", "flash", "dim"]);
+
+        let leds = compositor.compose(&base);
+        assert_eq!(leds.leds()[0], OwnRGB8::new(50, 64, 67));
+        assert_eq!(leds.leds()[1], OwnRGB8::new(100, 128, 5));
+        // Keys without colors in base or layers stay dark
+        assert_eq!(leds.leds()[2], OwnRGB8::new(0, 0, 0));
+
+        // Replacing a layer keeps a single instance of it
+        compositor.set_layer(Layer::new("add", 1, BlendMode::Max, color(0, 0, 0)));
+        assert_eq!(compositor.layers().len(), 3);
+        assert!(compositor.remove_layer("flash"));
+        assert!(!compositor.remove_layer("flash"));
+        let leds = compositor.compose(&base);
+        assert_eq!(leds.leds()[0], OwnRGB8::new(50, 100, 0));
+    }
+
+    #[test]
+    fn key_names() {
+        assert_eq!(layout::key_index("esc"), Some(0));
+        assert_eq!(layout::key_index("F1"), Some(18));
+        assert_eq!(layout::key_index("digit1"), Some(7));
+        assert_eq!(layout::key_index("42"), Some(42));
+        assert_eq!(layout::key_index("126"), None);
+        assert_eq!(layout::key_index("nokey"), None);
+        assert_eq!(layout::key_name(41), Some("space"));
+        assert_eq!(layout::key_name(6), None);
+    }
+
+    #[test]
+    fn heatmap() {
+        let mut counts = KeyPressCounts::new();
+        // ESC (usage 0x29) pressed and held, pressed again, then together with A (usage 0x04)
+        let esc = |report: &mut [u8]| report[1 + (0x29 - 4) / 8] |= 1 << ((0x29 - 4) % 8);
+        let mut report = [0u8; 30];
+        report[0] = 1;
+        let released = report;
+        esc(&mut report);
+        for r in [report, report, released, report, released] {
+            counts.record(&r);
+        }
+        report[1] |= 1;
+        counts.record(&report);
+        // Other report IDs don't release keys
+        counts.record(&[3, 0, 0]);
+        counts.record(&report);
+        assert_eq!(counts.counts()[0], 3);
+        assert_eq!(counts.counts()[9], 1);
+        assert_eq!(counts.total(), 4);
+
+        let gradient = [OwnRGB8::new(0, 0, 0), OwnRGB8::new(200, 0, 0)];
+        let leds = counts.render(HeatmapScale::Linear, &gradient, ColorSpace::Rgb);
+        assert_eq!(leds.leds()[0], OwnRGB8::new(200, 0, 0));
+        assert_eq!(leds.leds()[9], OwnRGB8::new(67, 0, 0));
+        assert_eq!(leds.leds()[1], OwnRGB8::new(0, 0, 0));
+        let leds = counts.render(HeatmapScale::Log, &[], ColorSpace::Rgb);
+        assert_eq!(leds.leds()[0], OwnRGB8::new(255, 0, 0));
+
+        let csv = counts.to_csv();
+        assert!(csv.starts_with("index,key,count\n0,esc,3\n"));
+        assert!(csv.contains("\n9,a,1\n"));
+    }
+
+    #[test]
+    fn night_light() {
+        use nightlight::{NightLight, NightLightSchedule};
+
+        // Berlin, 2024-06-21 12:00 UTC: sunrise 02:43 UTC, sunset 19:33 UTC
+        let noon = 1718971200;
+        let (sunrise, sunset) = nightlight::sun_times(noon, 52.52, 13.405).unwrap();
+        assert!((sunrise - (noon - 9 * 3600 - 17 * 60)).abs() < 120);
+        assert!((sunset - (noon + 7 * 3600 + 33 * 60)).abs() < 120);
+        // Polar day in Tromsø
+        assert_eq!(nightlight::sun_times(noon, 69.65, 18.96), None);
+
+        let mut night_light = NightLight {
+            schedule: NightLightSchedule::Fixed {
+                start: nightlight::parse_time_of_day("22:00").unwrap(),
+                end: nightlight::parse_time_of_day("06:00").unwrap(),
+            },
+            temperature: 3500,
+            fade: Duration::from_secs(3600),
+        };
+        let local = |h: i64, m: i64| night_light.temperature_at(noon + (h - 12) * 3600 + m * 60, 0);
+        assert_eq!(local(12, 0), 6500);
+        assert_eq!(local(22, 30), 5000);
+        assert_eq!(local(2, 0), 3500);
+        assert_eq!(local(5, 30), 5000);
+        assert_eq!(local(6, 0), 6500);
+        // 22:30 local time in UTC+2
+        assert_eq!(
+            night_light.temperature_at(noon + 8 * 3600 + 30 * 60, 7200),
+            5000
+        );
+
+        night_light.schedule = NightLightSchedule::Sun {
+            latitude: 52.52,
+            longitude: 13.405,
+        };
+        assert_eq!(night_light.temperature_at(noon, 0), 6500);
+        assert_eq!(night_light.temperature_at(sunset + 1800, 0), 5000);
+        assert_eq!(night_light.temperature_at(sunset + 3 * 3600, 0), 3500);
+        assert_eq!(night_light.temperature_at(sunrise - 1800, 0), 5000);
+        assert!(nightlight::parse_time_of_day("24:00").is_err());
+
+        let white = OwnRGB8::new(255, 255, 255);
+        assert_eq!(nightlight::warm(&white, 6500), white);
+        let warm: RGB8 = nightlight::warm(&white, 3500).into();
+        assert!(warm.r == 255 && warm.g < 255 && warm.b < warm.g);
+    }
+
+    #[test]
+    fn scheduler() {
+        use schedule::{Schedule, ScheduledProfile, Scheduler, WeekTime, Weekday};
+        use std::sync::{Arc, Mutex};
+
+        let schedule = Schedule::from_toml(
+            r#"
+            [profiles]
+            work = { color_profile = "work.json" }
+            evening = { animation = { mode = "breathing", brightness = "low", color = "ff8000" } }
+            night = "off"
+
+            [[rules]]
+            profile = "work"
+            days = ["mon", "tue", "wed", "thu", "fri"]
+            start = "09:00"
+            end = "17:00"
+
+            [[rules]]
+            profile = "evening"
+            start = "19:00"
+            end = "23:00"
+
+            [[rules]]
+            profile = "night"
+            start = "23:00"
+            end = "07:00"
+            "#,
+        )
+        .unwrap();
+        let at = |weekday, hour: u32, minute: u32| WeekTime {
+            weekday,
+            minute: hour * 60 + minute,
+        };
+        assert_eq!(schedule.active(at(Weekday::Mon, 9, 0)), Some("work"));
+        assert_eq!(schedule.active(at(Weekday::Sat, 9, 0)), None);
+        assert_eq!(schedule.active(at(Weekday::Sun, 23, 30)), Some("night"));
+        assert_eq!(schedule.active(at(Weekday::Mon, 6, 59)), Some("night"));
+        assert_eq!(schedule.active(at(Weekday::Mon, 17, 0)), None);
+        assert_eq!(
+            schedule.next_change(at(Weekday::Fri, 12, 0)),
+            Some((at(Weekday::Fri, 17, 0), None))
+        );
+        assert_eq!(
+            schedule.next_change(at(Weekday::Sat, 7, 0)),
+            Some((at(Weekday::Sat, 19, 0), Some("evening")))
+        );
+
+        match schedule.profiles["evening"].lighting_state(Path::new(".")) {
+            Ok(LightingState::Animation(params)) => {
+                assert_eq!(params.mode, LightingMode::Breathing);
+                assert_eq!(params.brightness, Brightness::Low);
+                assert_eq!(params.color, OwnRGB8::new(255, 128, 0));
+            }
+            state => panic!("Unexpected state {:?}", state),
+        }
+        assert!(matches!(schedule.profiles["night"], ScheduledProfile::Off));
+
+        let now = Arc::new(Mutex::new(at(Weekday::Mon, 8, 0)));
+        let clock = {
+            let now = Arc::clone(&now);
+            move || *now.lock().unwrap()
+        };
+        let mut scheduler = Scheduler::new(schedule, Box::new(clock));
+        assert!(scheduler.poll().is_none());
+        *now.lock().unwrap() = at(Weekday::Mon, 9, 30);
+        assert_eq!(scheduler.poll().map(|(name, _)| name), Some("work"));
+        assert!(scheduler.poll().is_none());
+        let status = scheduler.status();
+        assert_eq!(status.active.as_deref(), Some("work"));
+        assert_eq!(status.next_change, Some(at(Weekday::Mon, 17, 0)));
+        assert_eq!(status.next, None);
+
+        assert!(Schedule::from_toml(
+            "[[rules]]\nprofile = \"x\"\nstart = \"00:00\"\nend = \"00:00\""
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn lock_state() {
+        let state = layout::LockState::from_output_report(&[0x01, 0x03]).unwrap();
+        assert!(state.num_lock && state.caps_lock && !state.scroll_lock);
+        let state = layout::LockState::from_output_report(&[0x01, 0x04, 0x00]).unwrap();
+        assert_eq!(
+            state,
+            layout::LockState {
+                scroll_lock: true,
+                ..Default::default()
+            }
+        );
+        assert_eq!(layout::LockState::from_output_report(&[0x02, 0x01]), None);
+        assert_eq!(layout::LockState::from_output_report(&[0x01]), None);
+
+        for leds in 0..8 {
+            let state = layout::LockState::from_output_report(&[0x01, leds]).unwrap();
+            assert_eq!(state.leds(), leds);
+        }
+    }
+
+    #[test]
+    fn notification() {
+        let notification = Notification {
+            keys: vec![0, 18],
+            color: OwnRGB8::new(255, 0, 0),
+            pulses: 2,
+            duration: Duration::from_secs(2),
+        };
+        let alpha = |ms| notification.alpha_at(Duration::from_millis(ms));
+        assert_eq!(alpha(0), Some(0.0));
+        assert!(matches!(alpha(500), Some(a) if (a - 1.0).abs() < 1e-6));
+        assert!(matches!(alpha(1500), Some(a) if (a - 1.0).abs() < 1e-6));
+        assert_eq!(alpha(2000), None);
+
+        let layer = notification.layer("n", 1000, 1.0);
+        let alpha = layer.alpha.unwrap();
+        assert_eq!((alpha[0], alpha[1], alpha[18]), (255, 0, 255));
+
+        let flash = Notification {
+            keys: vec![],
+            pulses: 0,
+            ..notification
+        };
+        assert_eq!(flash.alpha_at(Duration::from_millis(1999)), Some(1.0));
+        assert!(flash
+            .layer("n", 1000, 0.5)
+            .alpha
+            .unwrap()
+            .iter()
+            .all(|&a| a == 128));
+    }
+
     #[test]
     fn test_modify_from() {
         let mut cleds = CustomKeyLeds::new();
@@ -828,4 +1717,360 @@ mod tests {
         };
         assert_eq!(first.to_vec(), expected.to_vec());
     }
+
+    #[test]
+    fn color_spaces() {
+        let orange = OwnRGB8::new(255, 128, 0);
+        let hsv = Hsv::from(&orange);
+        assert!((hsv.h - 30.1).abs() < 0.1 && hsv.s == 1.0 && hsv.v == 1.0);
+        let hsl = Hsl::from(&orange);
+        assert!((hsl.l - 0.5).abs() < 0.01 && hsl.s == 1.0);
+        let lab = Oklab::from(&OwnRGB8::new(255, 255, 255));
+        assert!((lab.l - 1.0).abs() < 0.001 && lab.a.abs() < 0.001 && lab.b.abs() < 0.001);
+        // Round trips
+        for color in [
+            orange.clone(),
+            OwnRGB8::new(12, 200, 99),
+            OwnRGB8::new(80, 80, 80),
+        ] {
+            assert_eq!(OwnRGB8::from(Hsv::from(&color)), color);
+            assert_eq!(OwnRGB8::from(Hsl::from(&color)), color);
+            assert_eq!(OwnRGB8::from(Oklab::from(&color)), color);
+        }
+
+        let (red, blue) = (OwnRGB8::new(255, 0, 0), OwnRGB8::new(0, 0, 255));
+        assert_eq!(
+            color::interpolate(&red, &blue, 0.5, ColorSpace::Rgb),
+            OwnRGB8::new(128, 0, 128)
+        );
+        // Shorter way around the hue circle: red -> magenta -> blue
+        assert_eq!(
+            color::interpolate(&red, &blue, 0.5, ColorSpace::Hsv),
+            OwnRGB8::new(255, 0, 255)
+        );
+        let gradient = color::gradient(&[red.clone(), blue.clone()], 5, ColorSpace::Oklab);
+        assert_eq!(gradient.len(), 5);
+        assert_eq!((&gradient[0], &gradient[4]), (&red, &blue));
+
+        let wheel = color::hue_wheel(3, 1.0, 1.0);
+        assert_eq!(wheel, vec![red, OwnRGB8::new(0, 255, 0), blue]);
+
+        assert_eq!(OwnRGB8::from_str("hsv(30 100% 100%)").unwrap(), orange);
+        assert_eq!(
+            OwnRGB8::from_str("hsl(120, 1, 0.5)").unwrap(),
+            OwnRGB8::new(0, 255, 0)
+        );
+        assert_eq!(
+            OwnRGB8::from_str("oklab(100% 0 0)").unwrap(),
+            OwnRGB8::new(255, 255, 255)
+        );
+        assert!(OwnRGB8::from_str("hsv(30 100%)").is_err());
+    }
+
+    #[test]
+    fn css_colors() {
+        let orange = OwnRGB8::new(255, 136, 0);
+        for color in [
+            "ff8800",
+            "#ff8800",
+            "#F80",
+            "f80",
+            "#f80f",
+            "rgb(255, 136, 0)",
+            "rgb(255 136 0 / 100%)",
+            "rgba(100%, 53.33%, 0%, 1)",
+            "hsl(32deg 100% 50%)",
+            "hsl(0.0889turn, 1, 0.5)",
+            "hsla(32, 100%, 50%, 1)",
+            "hwb(32 0% 0%)",
+        ] {
+            assert_eq!(OwnRGB8::from_str(color), Ok(orange.clone()), "{color}");
+        }
+        assert_eq!(OwnRGB8::from_str("red"), Ok(OwnRGB8::new(255, 0, 0)));
+        assert_eq!(
+            OwnRGB8::from_str("RebeccaPurple"),
+            Ok(OwnRGB8::new(0x66, 0x33, 0x99))
+        );
+        assert_eq!(
+            OwnRGB8::from_str("oklch(100% 0 0)"),
+            Ok(OwnRGB8::new(255, 255, 255))
+        );
+
+        assert_eq!(OwnRGB8::from_str(" "), Err(ColorParseError::Empty));
+        assert_eq!(
+            OwnRGB8::from_str("ff000"),
+            Err(ColorParseError::InvalidHex("ff000".into()))
+        );
+        assert_eq!(
+            OwnRGB8::from_str("#ggg"),
+            Err(ColorParseError::InvalidHex("ggg".into()))
+        );
+        assert_eq!(
+            OwnRGB8::from_str("redish"),
+            Err(ColorParseError::UnknownName("redish".into()))
+        );
+        assert_eq!(
+            OwnRGB8::from_str("cmyk(0 0 0 0)"),
+            Err(ColorParseError::UnknownFunction("cmyk".into()))
+        );
+        assert_eq!(
+            OwnRGB8::from_str("rgb(1 2)"),
+            Err(ColorParseError::ComponentCount {
+                function: "rgb".into(),
+                found: 2
+            })
+        );
+        assert_eq!(
+            OwnRGB8::from_str("hsl(x 1 1)"),
+            Err(ColorParseError::InvalidComponent {
+                function: "hsl".into(),
+                component: "x".into()
+            })
+        );
+        assert_eq!(
+            OwnRGB8::from_str("rgb(0 0 0 / 50%)"),
+            Err(ColorParseError::Transparent)
+        );
+        assert_eq!(
+            OwnRGB8::from_str("transparent"),
+            Err(ColorParseError::Transparent)
+        );
+    }
+
+    #[test]
+    fn profile_formats() {
+        // Version 1 with comments and trailing comma
+        let v1 = r#"
+            {
+                // Top row
+                "1": "ff0000", // "2": "00ff00",
+                "3": "url://not-a-comment",
+            }
+        "#;
+        let err = Profile::parse(v1, FileFormat::Json)
+            .unwrap()
+            .profile_keys()
+            .unwrap_err();
+        assert!(format!("{err:?}").contains("url://not-a-comment"));
+        let v1 = v1.replace("url://not-a-comment", "0000ff");
+        let keys = read_color_profile(&v1).unwrap();
+        assert_eq!(
+            keys,
+            vec![
+                ProfileKey::new(1, OwnRGB8::new(255, 0, 0)),
+                ProfileKey::new(3, OwnRGB8::new(0, 0, 255)),
+            ]
+        );
+
+        let toml = r##"
+            version = 2
+            default = "black"
+
+            [groups]
+            wasd = ["w", "a", "s", "d"]
+            row = [0, "2-4"]
+
+            [keys]
+            "0-20" = "#00f"
+            row = "lime"
+            wasd = "red"
+            esc = "white"
+
+            [[animation.keyframes]]
+            time = 0
+            keys = { wasd = "ff0000" }
+        "##;
+        let profile = Profile::parse(toml, FileFormat::Toml).unwrap();
+        let keys = profile.profile_keys().unwrap();
+        assert_eq!(keys.len(), TOTAL_KEYS);
+        let color = |name: &str| {
+            let index = layout::key_index(name).unwrap();
+            keys[index].rgb_value.clone()
+        };
+        assert_eq!(color("esc"), OwnRGB8::new(255, 255, 255));
+        assert_eq!(color("w"), OwnRGB8::new(255, 0, 0));
+        assert_eq!(keys[3].rgb_value, OwnRGB8::new(0, 255, 0));
+        assert_eq!(keys[20].rgb_value, OwnRGB8::new(0, 0, 255));
+        let wasd = profile.resolve_keys("wasd").unwrap();
+        let other = (21..TOTAL_KEYS).find(|i| !wasd.contains(i)).unwrap();
+        assert_eq!(keys[other].rgb_value, OwnRGB8::new(0, 0, 0));
+        let animation = profile.animation().unwrap().unwrap();
+        assert_eq!(animation.groups["row"], vec![0, 2, 3, 4]);
+
+        let yaml = "
+            version: 2
+            groups:
+              pair: [5, 6]
+            keys:
+              7: red
+              pair: blue
+        ";
+        let keys = Profile::parse(yaml, FileFormat::Yaml)
+            .unwrap()
+            .profile_keys()
+            .unwrap();
+        assert_eq!(
+            keys.iter().map(|k| k.key_index).collect::<Vec<_>>(),
+            vec![5, 6, 7]
+        );
+
+        // Saving custom colors as profile
+        let mut leds = CustomKeyLeds::new();
+        leds.set_led(3, OwnRGB8::new(255, 128, 0)).unwrap();
+        let profile = Profile::from_key_leds(&leds);
+        assert_eq!(profile.keys.len(), 1);
+        for format in [FileFormat::Json, FileFormat::Toml, FileFormat::Yaml] {
+            let text = profile.to_text(format).unwrap();
+            let keys = Profile::parse(&text, format)
+                .unwrap()
+                .profile_keys()
+                .unwrap();
+            assert_eq!(CustomKeyLeds::try_from(keys).unwrap().leds(), leds.leds());
+        }
+
+        assert!(Profile::parse("version = 3", FileFormat::Toml).is_err());
+        let unknown = "version = 2\nkeys = { nokey = \"red\" }";
+        assert!(Profile::parse(unknown, FileFormat::Toml)
+            .unwrap()
+            .profile_keys()
+            .is_err());
+        let reversed = "version = 2\nkeys = { \"20-0\" = \"red\" }";
+        assert!(Profile::parse(reversed, FileFormat::Toml)
+            .unwrap()
+            .profile_keys()
+            .is_err());
+    }
+
+    #[test]
+    fn scene() {
+        let mut keys = CustomKeyLeds::new();
+        keys.set_led(3, OwnRGB8::new(200, 100, 0)).unwrap();
+        let scene = Scene {
+            mode: LightingMode::Custom,
+            brightness: Brightness::Medium,
+            speed: Speed::Slow,
+            color: OwnRGB8::default(),
+            rainbow: false,
+            keys: Some(keys),
+        };
+        for format in [FileFormat::Json, FileFormat::Toml, FileFormat::Yaml] {
+            let content = write_scene(&scene, format).unwrap();
+            let read = read_scene(&content, format).unwrap();
+            assert_eq!(read.brightness, Brightness::Medium);
+            assert_eq!(read.keys.unwrap().leds()[3], OwnRGB8::new(200, 100, 0));
+        }
+        // Custom colors are scaled by the brightness
+        match scene.lighting_state() {
+            LightingState::CustomColors(keys) => {
+                assert_eq!(keys.leds()[3], OwnRGB8::new(100, 50, 0))
+            }
+            state => panic!("Unexpected state {:?}", state),
+        }
+
+        let wave = Scene {
+            mode: LightingMode::Wave,
+            ..scene
+        };
+        // Custom colors wouldn't be visible
+        assert!(matches!(
+            read_scene(
+                &write_scene(&wave, FileFormat::Json).unwrap(),
+                FileFormat::Json
+            ),
+            Err(CherryRgbError::InvalidArgument(..))
+        ));
+        assert!(matches!(
+            wave.lighting_state(),
+            LightingState::Animation(AnimationParams {
+                mode: LightingMode::Wave,
+                ..
+            })
+        ));
+        let json = r#"{"mode":"Rain","brightness":"Low","speed":"Fast","color":{"r":0,"g":255,"b":0},"rainbow":true}"#;
+        let rain = read_scene(json, FileFormat::Json).unwrap();
+        assert!(rain.keys.is_none() && rain.rainbow);
+        let from_state = Scene::from(LightingState::Animation(rain.animation()));
+        assert_eq!(from_state.animation(), rain.animation());
+    }
+
+    #[test]
+    fn saved_lighting() {
+        let mut keys = CustomKeyLeds::new();
+        keys.set_led(3, OwnRGB8::new(200, 100, 0)).unwrap();
+        let mut saved = SavedLighting::custom_colors(keys, Brightness::Medium, 50);
+        saved.effect = Some(RpcEffect {
+            name: "blink".into(),
+            source: RpcEffectSource::Script("fn render(t) {}".into()),
+            fps: 10,
+            duration: None,
+        });
+        let json = serde_json::to_string(&saved).unwrap();
+        let read: SavedLighting = serde_json::from_str(&json).unwrap();
+
+        // Custom colors are kept as given, but applied scaled
+        assert_eq!(read.keys().leds()[3], OwnRGB8::new(200, 100, 0));
+        let scene = read.applied_scene().unwrap();
+        assert_eq!(scene.keys.unwrap().leds()[3], OwnRGB8::new(100, 50, 0));
+        assert_eq!(read.effect.unwrap().name, "blink");
+
+        // Animations have no custom colors
+        let wave = SavedLighting::from(Scene {
+            mode: LightingMode::Wave,
+            ..read.scene.unwrap()
+        });
+        assert_eq!(wave.keys().leds()[3], OwnRGB8::default());
+        let read: SavedLighting = serde_json::from_str(r#"{"scene":null}"#).unwrap();
+        assert_eq!(read.software_brightness, 100);
+        assert!(read.lighting_state().is_none());
+
+        assert_eq!(
+            SavedLighting::file_name("00c8_usb1-2.4"),
+            "state_00c8_usb1-2.4.json"
+        );
+        assert_eq!(SavedLighting::file_name("../x y"), "state_.._x_y.json");
+    }
+
+    #[test]
+    fn history() {
+        let lighting = |mode: LightingMode| {
+            SavedLighting::from(Scene::from(LightingState::Animation(AnimationParams {
+                mode,
+                brightness: Brightness::Full,
+                speed: Speed::Fast,
+                color: OwnRGB8::default(),
+                rainbow: false,
+            })))
+        };
+        let mode = |lighting: Option<&SavedLighting>| {
+            lighting.map(|lighting| lighting.scene.as_ref().unwrap().mode.clone())
+        };
+
+        let mut history = History::default();
+        assert!(history.undo().is_none());
+        history.push(lighting(LightingMode::Wave));
+        history.push(lighting(LightingMode::Rain));
+        history.push(lighting(LightingMode::Spectrum));
+        assert_eq!(mode(history.undo()), Some(LightingMode::Rain));
+        assert_eq!(mode(history.undo()), Some(LightingMode::Wave));
+        assert!(history.undo().is_none());
+        assert_eq!(mode(history.redo()), Some(LightingMode::Rain));
+        assert!(history.to_string().starts_with("   3 animation Spectrum"));
+        assert!(history.to_string().contains("*  2 animation Rain"));
+
+        // Applying new lighting drops the undone entries
+        history.push(lighting(LightingMode::Breathing));
+        assert!(history.redo().is_none());
+        assert_eq!(history.len(), 3);
+        assert_eq!(mode(history.undo()), Some(LightingMode::Rain));
+
+        let json = serde_json::to_string(&history).unwrap();
+        let mut read: History = serde_json::from_str(&json).unwrap();
+        assert_eq!(mode(read.current()), Some(LightingMode::Rain));
+        assert_eq!(mode(read.redo()), Some(LightingMode::Breathing));
+
+        for _ in 0..HISTORY_LENGTH {
+            history.push(lighting(LightingMode::Custom));
+        }
+        assert_eq!(history.len(), HISTORY_LENGTH);
+    }
 }
@@ -1,13 +1,16 @@
 use crate::{
     calc_checksum,
     extensions::{OwnRGB8, ToVec},
-    CherryRgbError, CHUNK_SIZE, TOTAL_KEYS,
+    protocol::SetCustomLED,
+    write_file_atomically, CherryRgbError, KeyframeAnimation, CHUNK_SIZE, TOTAL_KEYS,
 };
+#[cfg(all(target_os = "linux", feature = "uhid"))]
+use crate::{color::ColorSpace, HeatmapScale};
 
-use binrw::{binrw, until_eof, BinRead, BinWrite, BinWriterExt, Endian};
+use binrw::{binrw, until_eof, BinRead, BinReaderExt, BinWrite, BinWriterExt, Endian};
 use clap::ValueEnum;
 use serde::{Deserialize, Serialize};
-use std::convert::TryFrom;
+use std::{convert::TryFrom, fmt, path::Path, time::Duration};
 use strum_macros::{EnumProperty, EnumString};
 
 /// Mode attributes:
@@ -99,6 +102,93 @@ pub enum Brightness {
     Full = 4,
 }
 
+impl Brightness {
+    /// Relative brightness, from 0.0 (off) to 1.0 (full)
+    pub fn factor(&self) -> f32 {
+        self.clone() as u8 as f32 / Brightness::Full as u8 as f32
+    }
+}
+
+/// Size of the device configuration, as read by the official software
+pub const DEVICE_CONFIG_SIZE: usize = 0x22;
+
+/// LED animation fields at the start of the device configuration
+#[binrw]
+#[brw(little)]
+#[derive(Clone, Debug, PartialEq)]
+pub struct AnimationConfig {
+    pub unknown_00: u8,
+    pub mode: LightingMode,
+    pub brightness: Brightness,
+    pub speed: Speed,
+    pub unknown_04: u8,
+    pub rainbow: u8,
+    pub color: OwnRGB8,
+}
+
+impl AnimationConfig {
+    /// Offset within the device configuration
+    pub const OFFSET: u16 = 0x00;
+}
+
+impl From<AnimationParams> for AnimationConfig {
+    fn from(params: AnimationParams) -> Self {
+        Self {
+            unknown_00: 0x00,
+            mode: params.mode,
+            brightness: params.brightness,
+            speed: params.speed,
+            unknown_04: 0x00,
+            rainbow: if params.rainbow { 1 } else { 0 },
+            color: params.color,
+        }
+    }
+}
+
+impl From<AnimationConfig> for AnimationParams {
+    fn from(config: AnimationConfig) -> Self {
+        Self {
+            mode: config.mode,
+            brightness: config.brightness,
+            speed: config.speed,
+            color: config.color,
+            rainbow: config.rainbow != 0,
+        }
+    }
+}
+
+/// Device configuration, read with `protocol::ReadConfig` and written with
+/// `protocol::WriteConfig`.
+/// Fields named by their offset are not understood yet.
+#[binrw]
+#[brw(little)]
+#[derive(Clone, Debug, PartialEq)]
+pub struct DeviceConfig {
+    pub animation: AnimationConfig,
+    pub unknown_09: [u8; 15],
+    /// Set to 1 by the official software after changing the animation
+    pub unknown_18: u8,
+    pub unknown_19: [u8; 9],
+}
+
+impl DeviceConfig {
+    /// Offset of `unknown_18`
+    pub const OFFSET_UNKNOWN_18: u16 = 0x18;
+
+    /// Parse the configuration as read from the keyboard
+    pub fn from_bytes(data: &[u8]) -> Result<Self, CherryRgbError> {
+        if data.len() != DEVICE_CONFIG_SIZE {
+            return Err(CherryRgbError::InvalidArgument(
+                "Invalid device config size".into(),
+                data.len().to_string(),
+            ));
+        }
+        std::io::Cursor::new(data)
+            .read_le()
+            .map_err(|err| CherryRgbError::ParseError(format!("parsing device config, err={err}")))
+    }
+}
+
 /// Represents the mapping of a key to a certain function/keycode
 #[binrw]
 #[derive(Clone, Debug)]
@@ -112,7 +202,8 @@ pub trait PayloadType {
     fn payload_type(&self) -> u8;
 }
 
-/// Payloads
+/// Payloads of any type, as sent and received, e.g. for parsing captures.
+/// The keyboard is talked to with the typed requests in `protocol`.
 #[binrw]
 #[br(little, import(payload_type: u8))]
 #[bw(little)]
@@ -122,8 +213,15 @@ pub enum Payload {
     TransactionStart,
     #[br(pre_assert(payload_type == 0x2))]
     TransactionEnd,
+    /// Read `data_len` bytes of the device configuration from `data_offset`, see `DeviceConfig`
     #[br(pre_assert(payload_type == 0x3))]
-    Unknown3 { unk: u8 },
+    ReadConfig {
+        data_len: u8,
+        data_offset: u16,
+        padding: u8,
+        #[br(count = data_len)]
+        data: Vec<u8>,
+    },
     #[br(pre_assert(payload_type == 0x5))]
     Unknown5 { unk: u8 },
     #[br(pre_assert(payload_type == 0x7))]
@@ -134,15 +232,16 @@ pub enum Payload {
         #[br(count = data_len)]
         keymap: Vec<u8>,
     },
+    /// Write `data` into the device configuration at `offset`, see `DeviceConfig`
     #[br(pre_assert(payload_type == 0x6))]
-    SetAnimation {
-        unknown: [u8; 5],
-        mode: LightingMode,
-        brightness: Brightness,
-        speed: Speed,
-        pad: u8,
-        rainbow: u8,
-        color: OwnRGB8,
+    WriteConfig {
+        #[br(temp)]
+        #[bw(calc = data.len() as u8)]
+        len: u8,
+        offset: u16,
+        #[brw(magic = 0x55u8)]
+        #[br(count = len)]
+        data: Vec<u8>,
     },
     #[br(pre_assert(payload_type == 0xB))]
     SetCustomLED {
@@ -162,7 +261,11 @@ pub enum Payload {
         #[br(count = data_len)]
         key_data: Vec<u8>,
     },
+    /// Any other payload, kept as read so it serializes to the same bytes
     Unhandled {
+        #[br(calc = payload_type)]
+        #[bw(ignore)]
+        payload_type: u8,
         #[br(parse_with = until_eof)]
         data: Vec<u8>,
     },
@@ -173,16 +276,13 @@ impl PayloadType for Payload {
         match self {
             Payload::TransactionStart => 0x1,
             Payload::TransactionEnd => 0x2,
-            Payload::Unknown3 { .. } => 0x3,
+            Payload::ReadConfig { .. } => 0x3,
             Payload::Unknown5 { .. } => 0x5,
             Payload::GetKeymap { .. } => 0x7,
-            Payload::SetAnimation { .. } => 0x6,
+            Payload::WriteConfig { .. } => 0x6,
             Payload::SetCustomLED { .. } => 0xB,
             Payload::GetKeyIndexes { .. } => 0x1B,
-            _ => {
-                log::error!("Unhandled Payload: {:?}", self);
-                0xFF
-            }
+            Payload::Unhandled { payload_type, .. } => *payload_type,
         }
     }
 }
@@ -213,6 +313,10 @@ where
     payload_type: u8,
     #[br(args(payload_type))]
     inner: T,
+    /// Bytes following the payload, e.g. padding of USB reports, kept so packets
+    /// serialize to the same bytes as read. Not covered by the checksum.
+    #[br(parse_with = until_eof)]
+    trailing: Vec<u8>,
 }
 
 impl<T> Packet<T>
@@ -222,7 +326,11 @@ where
     pub fn new(inner: T) -> Self {
         let checksum = calc_checksum(inner.payload_type(), &inner.clone().to_vec());
 
-        Self { checksum, inner }
+        Self {
+            checksum,
+            inner,
+            trailing: vec![],
+        }
     }
 
     pub fn checksum(&self) -> u16 {
@@ -233,6 +341,10 @@ where
         &self.inner
     }
 
+    pub fn into_payload(self) -> T {
+        self.inner
+    }
+
     /// Verify checksum
     pub fn verify_checksum(&self) -> Result<(), CherryRgbError> {
         let payload = self.inner.clone().to_vec();
@@ -249,6 +361,15 @@ where
     }
 }
 
+/// Response to `CherryKeyboard::send_raw`
+#[derive(Clone, Debug)]
+pub struct RawResponse {
+    /// Bytes as read from the keyboard
+    pub data: Vec<u8>,
+    /// Parsed packet, if the magic byte matched
+    pub packet: Option<Packet<Payload>>,
+}
+
 /// Wrapper around custom LED color for all keys
 #[derive(Default, Debug, Clone, Serialize, Deserialize)]
 pub struct CustomKeyLeds {
@@ -346,7 +467,7 @@ impl CustomKeyLeds {
     }
 
     /// Get array of payloads to be then provided to `send_payload`
-    pub fn get_payloads(self) -> Result<Vec<Payload>, CherryRgbError> {
+    pub fn get_payloads(self) -> Result<Vec<SetCustomLED>, CherryRgbError> {
         let key_data = self.to_vec();
 
         let result = key_data
@@ -355,9 +476,8 @@ impl CustomKeyLeds {
             .map(|(index, chunk)| {
                 let data_offset = index * CHUNK_SIZE;
 
-                Payload::SetCustomLED {
+                SetCustomLED {
                     data_offset: data_offset as u16,
-                    padding: 0x00,
                     key_leds_data: chunk.to_vec(),
                 }
             })
@@ -373,6 +493,266 @@ impl CustomKeyLeds {
         }
         Ok(self)
     }
+
+    /// Colors of all keys
+    pub fn leds(&self) -> &[OwnRGB8] {
+        &self.key_leds
+    }
+
+    /// Interpolate each key color towards `other`, `t` ranges from 0.0 (self) to 1.0 (other)
+    pub fn interpolate(&self, other: &CustomKeyLeds, t: f32) -> Self {
+        let len = std::cmp::max(self.key_leds.len(), other.key_leds.len());
+        let black = OwnRGB8::default();
+        Self {
+            key_leds: (0..len)
+                .map(|i| {
+                    let from = self.key_leds.get(i).unwrap_or(&black);
+                    let to = other.key_leds.get(i).unwrap_or(&black);
+                    from.lerp(to, t)
+                })
+                .collect(),
+        }
+    }
+
+    /// Scale all key colors by `factor`, from 0.0 (off) to 1.0 (unchanged).
+    /// Allows finer steps than the hardware `Brightness` levels.
+    pub fn scale(&self, factor: f32) -> Self {
+        let black = OwnRGB8::default();
+        Self {
+            key_leds: self
+                .key_leds
+                .iter()
+                .map(|c| black.lerp(c, factor))
+                .collect(),
+        }
+    }
+}
+
+/// Parameters of a hardware LED animation, see `CherryKeyboard::set_led_animation`
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct AnimationParams {
+    pub mode: LightingMode,
+    pub brightness: Brightness,
+    pub speed: Speed,
+    pub color: OwnRGB8,
+    pub rainbow: bool,
+}
+
+/// Lighting as applied to the keyboard
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub enum LightingState {
+    /// Hardware animation
+    Animation(AnimationParams),
+    /// Custom color for each key
+    CustomColors(CustomKeyLeds),
+}
+
+/// Complete lighting setup: hardware animation and optionally custom key colors,
+/// see `CherryKeyboard::apply_scene`
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Scene {
+    pub mode: LightingMode,
+    pub brightness: Brightness,
+    pub speed: Speed,
+    pub color: OwnRGB8,
+    pub rainbow: bool,
+    /// Custom key colors, visible in `LightingMode::Custom`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub keys: Option<CustomKeyLeds>,
+}
+
+impl Scene {
+    /// Custom key colors are only visible in `LightingMode::Custom`, reject them otherwise
+    pub fn validate(&self) -> Result<(), CherryRgbError> {
+        if self.keys.is_some() && self.mode != LightingMode::Custom {
+            return Err(CherryRgbError::InvalidArgument(
+                "Scene with custom key colors needs mode Custom".into(),
+                format!("{:?}", self.mode),
+            ));
+        }
+        Ok(())
+    }
+
+    pub fn animation(&self) -> AnimationParams {
+        AnimationParams {
+            mode: self.mode.clone(),
+            brightness: self.brightness.clone(),
+            speed: self.speed.clone(),
+            color: self.color.clone(),
+            rainbow: self.rainbow,
+        }
+    }
+
+    /// Scene as lighting state, e.g. for transitions. Custom colors are scaled by the
+    /// brightness, as lighting states show them at full brightness.
+    pub fn lighting_state(&self) -> LightingState {
+        match (&self.mode, &self.keys) {
+            (LightingMode::Custom, Some(keys)) => {
+                LightingState::CustomColors(keys.scale(self.brightness.factor()))
+            }
+            _ => LightingState::Animation(self.animation()),
+        }
+    }
+}
+
+impl From<LightingState> for Scene {
+    fn from(state: LightingState) -> Self {
+        match state {
+            LightingState::Animation(params) => Self {
+                mode: params.mode,
+                brightness: params.brightness,
+                speed: params.speed,
+                color: params.color,
+                rainbow: params.rainbow,
+                keys: None,
+            },
+            LightingState::CustomColors(keys) => Self {
+                mode: LightingMode::Custom,
+                brightness: Brightness::Full,
+                speed: Speed::Slow,
+                color: OwnRGB8::default(),
+                rainbow: false,
+                keys: Some(keys),
+            },
+        }
+    }
+}
+
+/// Last applied lighting, saved to restore it later on
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct SavedLighting {
+    /// Hardware animation or custom colors. Custom colors are kept as given,
+    /// so they can be modified later on.
+    pub scene: Option<Scene>,
+    /// Scale of the custom colors in percent
+    #[serde(default = "SavedLighting::full_software_brightness")]
+    pub software_brightness: u8,
+    /// Software effect played after applying the scene
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub effect: Option<RpcEffect>,
+}
+
+impl Default for SavedLighting {
+    fn default() -> Self {
+        Self {
+            scene: None,
+            software_brightness: Self::full_software_brightness(),
+            effect: None,
+        }
+    }
+}
+
+impl From<Scene> for SavedLighting {
+    fn from(scene: Scene) -> Self {
+        Self {
+            scene: Some(scene),
+            ..Default::default()
+        }
+    }
+}
+
+impl SavedLighting {
+    fn full_software_brightness() -> u8 {
+        100
+    }
+
+    /// Custom colors, as given before scaling them by `software_brightness`
+    pub fn custom_colors(
+        keys: CustomKeyLeds,
+        brightness: Brightness,
+        software_brightness: u8,
+    ) -> Self {
+        Self {
+            scene: Some(Scene {
+                mode: LightingMode::Custom,
+                brightness,
+                speed: Speed::Slow,
+                color: OwnRGB8::default(),
+                rainbow: false,
+                keys: Some(keys),
+            }),
+            software_brightness,
+            effect: None,
+        }
+    }
+
+    /// Custom colors of the scene, all black if it isn't set to custom colors
+    pub fn keys(&self) -> CustomKeyLeds {
+        match &self.scene {
+            Some(Scene {
+                mode: LightingMode::Custom,
+                keys: Some(keys),
+                ..
+            }) => keys.clone(),
+            _ => CustomKeyLeds::new(),
+        }
+    }
+
+    /// Scene as sent to the keyboard, i.e. with custom colors scaled by `software_brightness`
+    pub fn applied_scene(&self) -> Option<Scene> {
+        let mut scene = self.scene.clone()?;
+        scene.keys = scene
+            .keys
+            .map(|keys| keys.scale(self.software_brightness as f32 / 100.0));
+        Some(scene)
+    }
+
+    /// Applied scene as lighting state, see `Scene::lighting_state`
+    pub fn lighting_state(&self) -> Option<LightingState> {
+        self.applied_scene().map(|scene| scene.lighting_state())
+    }
+
+    /// File name of the saved lighting of a keyboard, see `CherryKeyboard::device_id`
+    pub fn file_name(device_id: &str) -> String {
+        let name: String = device_id
+            .chars()
+            .map(|c| match c {
+                'a'..='z' | 'A'..='Z' | '0'..='9' | '-' | '.' => c,
+                _ => '_',
+            })
+            .collect();
+        format!("state_{name}.json")
+    }
+
+    /// Read saved lighting (JSON)
+    pub fn load(path: &Path) -> Result<Self, CherryRgbError> {
+        let json = std::fs::read_to_string(path)
+            .map_err(|err| CherryRgbError::ParseError(format!("reading {:?}, err={err}", path)))?;
+        Ok(serde_json::from_str(&json)?)
+    }
+
+    /// Write saved lighting (JSON), the file is replaced atomically
+    pub fn save(&self, path: &Path) -> Result<(), CherryRgbError> {
+        write_file_atomically(path, &serde_json::to_string(self)?)
+    }
+}
+
+impl fmt::Display for SavedLighting {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.scene {
+            Some(scene) if scene.mode == LightingMode::Custom => {
+                let lit = match &scene.keys {
+                    Some(keys) => keys
+                        .leds()
+                        .iter()
+                        .filter(|color| **color != OwnRGB8::default())
+                        .count(),
+                    None => 0,
+                };
+                write!(f, "custom colors ({lit} keys lit)")?
+            }
+            Some(scene) => write!(
+                f,
+                "animation {:?} ({:?}, {:?})",
+                scene.mode, scene.brightness, scene.speed
+            )?,
+            None => write!(f, "-")?,
+        }
+        if let Some(effect) = &self.effect {
+            write!(f, ", effect {}", effect.name)?;
+        }
+        Ok(())
+    }
 }
 
 /// Parameters for set_led_animation (sent serialized from
@@ -386,3 +766,51 @@ pub struct RpcAnimation {
     pub color: Option<OwnRGB8>,
     pub rainbow: bool,
 }
+
+/// Parameters for a transition to a new lighting state (sent serialized from
+/// cherryrgb_ncli to cherryrgb_service).
+#[cfg(all(target_os = "linux", feature = "uhid"))]
+#[derive(Debug, Serialize, Deserialize)]
+pub struct RpcTransition {
+    pub target: LightingState,
+    pub duration: Duration,
+}
+
+/// Lighting to apply as chosen by the client, the service saves it as given
+/// (sent serialized from cherryrgb_ncli to cherryrgb_service).
+#[cfg(all(target_os = "linux", feature = "uhid"))]
+#[derive(Debug, Serialize, Deserialize)]
+pub struct RpcLighting {
+    pub lighting: SavedLighting,
+    /// Fade into the lighting instead of applying it right away
+    pub transition: Option<Duration>,
+}
+
+/// Parameters for showing the key press heatmap (sent serialized from
+/// cherryrgb_ncli to cherryrgb_service).
+#[cfg(all(target_os = "linux", feature = "uhid"))]
+#[derive(Debug, Serialize, Deserialize)]
+pub struct RpcHeatmap {
+    pub scale: HeatmapScale,
+    pub gradient: Vec<OwnRGB8>,
+    #[serde(default)]
+    pub space: ColorSpace,
+}
+
+/// Definition of a software effect
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub enum RpcEffectSource {
+    /// Source code of a Rhai script
+    Script(String),
+    Keyframes(KeyframeAnimation),
+}
+
+/// Parameters for running a software effect (sent serialized from
+/// cherryrgb_ncli to cherryrgb_service).
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct RpcEffect {
+    pub name: String,
+    pub source: RpcEffectSource,
+    pub fps: u32,
+    pub duration: Option<Duration>,
+}
@@ -0,0 +1,190 @@
+//! Night light: warms colors by lowering their color temperature at night
+//!
+//! Night is either given by fixed times or by sunset and sunrise, which are
+//! computed locally from the coordinates (see the sunrise equation).
+
+use crate::OwnRGB8;
+use rgb::RGB8;
+use std::time::Duration;
+
+/// Color temperature of unmodified colors (Kelvin)
+pub const DAYLIGHT_TEMPERATURE: u32 = 6500;
+
+const SECONDS_PER_DAY: i64 = 86400;
+/// Julian day of the unix epoch
+const JULIAN_DAY_UNIX_EPOCH: f64 = 2440587.5;
+/// Julian day of J2000.0
+const JULIAN_DAY_J2000: f64 = 2451545.0;
+
+/// When it's night
+#[derive(Clone, Debug, PartialEq)]
+pub enum NightLightSchedule {
+    /// From `start` to `end`, minutes since local midnight
+    Fixed { start: u32, end: u32 },
+    /// From sunset to sunrise at the given location (degrees, north and east are positive)
+    Sun { latitude: f64, longitude: f64 },
+}
+
+/// Night light settings
+#[derive(Clone, Debug, PartialEq)]
+pub struct NightLight {
+    pub schedule: NightLightSchedule,
+    /// Color temperature at night (Kelvin)
+    pub temperature: u32,
+    /// Length of the fade between day and night, it ends at the start
+    /// and starts at the end of the night
+    pub fade: Duration,
+}
+
+impl NightLight {
+    /// Color temperature at `unix_time`, rounded to 50 K so colors don't have to be resent
+    /// too often. `utc_offset` (seconds) is the local time zone offset for fixed schedules.
+    pub fn temperature_at(&self, unix_time: i64, utc_offset: i32) -> u32 {
+        let fade = self.fade.as_secs() as f64;
+        // Time (seconds) since the start and until the end of the night, if it's night
+        let night = match self.schedule {
+            NightLightSchedule::Fixed { start, end } => {
+                let now = (unix_time + utc_offset as i64).rem_euclid(SECONDS_PER_DAY);
+                let day = SECONDS_PER_DAY;
+                let since_start = (now - start as i64 * 60).rem_euclid(day);
+                let length = ((end as i64 - start as i64) * 60).rem_euclid(day);
+                (since_start < length).then(|| (since_start, length - since_start))
+            }
+            NightLightSchedule::Sun {
+                latitude,
+                longitude,
+            } => sun_night(unix_time, latitude, longitude),
+        };
+
+        let factor = match night {
+            None => 0.0,
+            Some(_) if fade <= 0.0 => 1.0,
+            Some((since_start, until_end)) => {
+                (since_start.min(until_end) as f64 / fade).clamp(0.0, 1.0)
+            }
+        };
+        let day = DAYLIGHT_TEMPERATURE as f64;
+        let temperature = day + (self.temperature as f64 - day) * factor;
+        ((temperature / 50.0).round() * 50.0) as u32
+    }
+}
+
+/// Time (seconds) since the last sunset and until the next sunrise, if the sun is down
+fn sun_night(unix_time: i64, latitude: f64, longitude: f64) -> Option<(i64, i64)> {
+    // Sunrise and sunset of the neighbouring days, far off during polar day or night
+    let times = |unix_time| match daylight(unix_time, latitude, longitude) {
+        Daylight::Between(sunrise, sunset) => (sunrise, sunset),
+        _ => (i64::MAX / 2, i64::MIN / 2),
+    };
+    match daylight(unix_time, latitude, longitude) {
+        Daylight::Always => None,
+        Daylight::Never => Some((i64::MAX, i64::MAX)),
+        Daylight::Between(sunrise, _) if unix_time < sunrise => {
+            let (_, sunset) = times(unix_time - SECONDS_PER_DAY);
+            Some((unix_time - sunset, sunrise - unix_time))
+        }
+        Daylight::Between(_, sunset) if unix_time >= sunset => {
+            let (sunrise, _) = times(unix_time + SECONDS_PER_DAY);
+            Some((unix_time - sunset, sunrise - unix_time))
+        }
+        Daylight::Between(..) => None,
+    }
+}
+
+enum Daylight {
+    /// Sunrise and sunset (unix time)
+    Between(i64, i64),
+    /// Polar day
+    Always,
+    /// Polar night
+    Never,
+}
+
+/// Sunrise equation, accurate to about a minute
+fn daylight(unix_time: i64, latitude: f64, longitude: f64) -> Daylight {
+    let julian_day = unix_time as f64 / SECONDS_PER_DAY as f64 + JULIAN_DAY_UNIX_EPOCH;
+    // Days since J2000.0 at local (solar) noon
+    let day = (julian_day - JULIAN_DAY_J2000 + longitude / 360.0).round();
+    let mean_solar_noon = day - longitude / 360.0;
+    let anomaly = (357.5291 + 0.98560028 * mean_solar_noon)
+        .rem_euclid(360.0)
+        .to_radians();
+    let center =
+        1.9148 * anomaly.sin() + 0.02 * (2.0 * anomaly).sin() + 0.0003 * (3.0 * anomaly).sin();
+    let ecliptic_longitude = (anomaly.to_degrees() + center + 180.0 + 102.9372)
+        .rem_euclid(360.0)
+        .to_radians();
+    let transit = JULIAN_DAY_J2000 + mean_solar_noon + 0.0053 * anomaly.sin()
+        - 0.0069 * (2.0 * ecliptic_longitude).sin();
+    let sin_declination = ecliptic_longitude.sin() * 23.4397_f64.to_radians().sin();
+    let cos_declination = sin_declination.asin().cos();
+    let latitude = latitude.to_radians();
+    let cos_hour_angle = ((-0.833_f64).to_radians().sin() - latitude.sin() * sin_declination)
+        / (latitude.cos() * cos_declination);
+
+    if cos_hour_angle < -1.0 {
+        return Daylight::Always;
+    }
+    if cos_hour_angle > 1.0 {
+        return Daylight::Never;
+    }
+    let hour_angle = cos_hour_angle.acos().to_degrees() / 360.0;
+    let to_unix = |julian_day: f64| {
+        ((julian_day - JULIAN_DAY_UNIX_EPOCH) * SECONDS_PER_DAY as f64).round() as i64
+    };
+    Daylight::Between(to_unix(transit - hour_angle), to_unix(transit + hour_angle))
+}
+
+/// Sunrise and sunset (unix time) of the day at `unix_time` at the given location,
+/// `None` during polar day or night
+pub fn sun_times(unix_time: i64, latitude: f64, longitude: f64) -> Option<(i64, i64)> {
+    match daylight(unix_time, latitude, longitude) {
+        Daylight::Between(sunrise, sunset) => Some((sunrise, sunset)),
+        _ => None,
+    }
+}
+
+/// Factors of the red, green and blue channel for the given color temperature,
+/// relative to daylight. Approximation of the black body colors by Tanner Helland.
+pub fn temperature_factors(kelvin: u32) -> [f32; 3] {
+    let blackbody = |kelvin: u32| {
+        let t = kelvin.clamp(1000, 40000) as f32 / 100.0;
+        let r = match t {
+            t if t <= 66.0 => 255.0,
+            t => 329.69873 * (t - 60.0).powf(-0.13320476),
+        };
+        let g = match t {
+            t if t <= 66.0 => 99.4708 * t.ln() - 161.11957,
+            t => 288.12217 * (t - 60.0).powf(-0.075514846),
+        };
+        let b = match t {
+            t if t >= 66.0 => 255.0,
+            t if t <= 19.0 => 0.0,
+            t => 138.51773 * (t - 10.0).ln() - 305.0448,
+        };
+        [r, g, b].map(|c: f32| c.clamp(0.0, 255.0))
+    };
+    let color = blackbody(kelvin);
+    let daylight = blackbody(DAYLIGHT_TEMPERATURE);
+    [0, 1, 2].map(|i| (color[i] / daylight[i]).clamp(0.0, 1.0))
+}
+
+/// Shift color to the given color temperature
+pub fn warm(color: &OwnRGB8, kelvin: u32) -> OwnRGB8 {
+    let [r, g, b] = temperature_factors(kelvin);
+    let color: RGB8 = color.clone().into();
+    let scale = |c: u8, f: f32| (c as f32 * f).round() as u8;
+    OwnRGB8::new(scale(color.r, r), scale(color.g, g), scale(color.b, b))
+}
+
+/// Parse local time of day (e.g. 21:30) into minutes since midnight
+pub fn parse_time_of_day(arg: &str) -> Result<u32, String> {
+    let err = || format!("Invalid time '{arg}', expected HH:MM");
+    let (hours, minutes) = arg.split_once(':').ok_or_else(err)?;
+    let hours: u32 = hours.parse().map_err(|_| err())?;
+    let minutes: u32 = minutes.parse().map_err(|_| err())?;
+    if hours > 23 || minutes > 59 {
+        return Err(err());
+    }
+    Ok(hours * 60 + minutes)
+}
@@ -0,0 +1,56 @@
+use crate::{BlendMode, CustomKeyLeds, Layer, OwnRGB8, TOTAL_KEYS};
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+
+/// Keys flashing or pulsing for a limited time, shown as a `Layer`
+/// on top of the current lighting
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Notification {
+    /// LED indexes, the whole board if empty
+    #[serde(default)]
+    pub keys: Vec<usize>,
+    pub color: OwnRGB8,
+    /// Number of pulses, the keys are lit constantly if 0
+    #[serde(default)]
+    pub pulses: u32,
+    /// Total duration of the notification
+    pub duration: Duration,
+}
+
+impl Notification {
+    /// Opacity at `elapsed` time since the notification was started,
+    /// `None` once it has finished
+    pub fn alpha_at(&self, elapsed: Duration) -> Option<f32> {
+        if elapsed >= self.duration {
+            return None;
+        }
+        if self.pulses == 0 {
+            return Some(1.0);
+        }
+        let progress = elapsed.as_secs_f32() / self.duration.as_secs_f32() * self.pulses as f32;
+        Some((progress.fract() * std::f32::consts::PI).sin())
+    }
+
+    /// Layer showing the notification at the given opacity
+    pub fn layer(&self, name: &str, priority: i32, alpha: f32) -> Layer {
+        let alpha = (alpha.clamp(0.0, 1.0) * 255.0).round() as u8;
+        let mut layer = Layer::new(
+            name,
+            priority,
+            BlendMode::Replace,
+            CustomKeyLeds::from_leds(vec![self.color.clone(); TOTAL_KEYS]).unwrap(),
+        );
+        layer.alpha = Some(
+            (0..TOTAL_KEYS)
+                .map(|index| {
+                    if self.keys.is_empty() || self.keys.contains(&index) {
+                        alpha
+                    } else {
+                        0
+                    }
+                })
+                .collect(),
+        );
+        layer
+    }
+}
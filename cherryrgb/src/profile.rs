@@ -0,0 +1,275 @@
+//! Color profiles
+//!
+//! Version 1 profiles are a flat JSON object of key index -> color.
+//! Version 2 profiles may be written in JSON, TOML or YAML and address keys by index,
+//! name (see `layout::key_index`), range of either (e.g. `0-20`, `q-p`) or group.
+//! Groups and ranges are applied first, so single keys override them.
+//!
+//! Example (TOML):
+//! ```toml
+//! version = 2
+//! default = "000020"
+//!
+//! [groups]
+//! wasd = ["w", "a", "s", "d"]
+//!
+//! [keys]
+//! "0-20" = "orange"
+//! wasd = "red"
+//! esc = "#fff"
+//!
+//! # Optional keyframe animation, played after the colors are set
+//! [animation]
+//! repeat = "ping_pong"
+//!
+//! [[animation.keyframes]]
+//! time = 0
+//! keys = { wasd = "red" }
+//!
+//! [[animation.keyframes]]
+//! time = 1000
+//! keys = { wasd = "ffff00" }
+//! ```
+
+use crate::{
+    color::parse_color, keyframes::GROUP_ALL, layout, CherryRgbError, CustomKeyLeds, FileFormat,
+    KeyframeAnimation, OwnRGB8, ProfileKey, TOTAL_KEYS,
+};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::{cmp::Reverse, collections::BTreeMap, fmt, path::Path};
+
+/// Latest version of the profile format
+pub const PROFILE_VERSION: u64 = 2;
+
+/// Member of a key group: LED index, key name or range
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum KeyRef {
+    Index(usize),
+    Name(String),
+}
+
+impl fmt::Display for KeyRef {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            KeyRef::Index(index) => write!(f, "{index}"),
+            KeyRef::Name(name) => write!(f, "{name}"),
+        }
+    }
+}
+
+/// Color profile, version 1 profiles are converted on reading
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Profile {
+    pub version: u64,
+    /// Color of all keys not listed in `keys`, these are left unchanged if not set
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub default: Option<String>,
+    /// Named sets of keys, usable in `keys` and the animation
+    #[serde(default)]
+    pub groups: BTreeMap<String, Vec<KeyRef>>,
+    /// Key, range or group -> color
+    #[serde(default)]
+    pub keys: BTreeMap<String, String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub animation: Option<KeyframeAnimation>,
+}
+
+impl Profile {
+    /// Parse profile of any version. JSON may contain `//` comments and trailing commas.
+    pub fn parse(profile: &str, format: FileFormat) -> Result<Self, CherryRgbError> {
+        let value: Value = match format {
+            FileFormat::Json => serde_json::from_str(&strip_json_extensions(profile))?,
+            FileFormat::Toml => toml::from_str(profile)
+                .map_err(|err| CherryRgbError::ParseError(format!("parsing profile, err={err}")))?,
+            FileFormat::Yaml => serde_yaml::from_str(profile)
+                .map_err(|err| CherryRgbError::ParseError(format!("parsing profile, err={err}")))?,
+        };
+        let root = value.as_object().ok_or_else(|| {
+            CherryRgbError::ParseError("No valid colors found in color profile.".into())
+        })?;
+
+        match root.get("version") {
+            // Version 1: key index -> color
+            None => {
+                let keys = root
+                    .iter()
+                    .map(|(key, value)| {
+                        let key_index = key.parse::<usize>().map_err(|err| {
+                            CherryRgbError::ParseError(format!(
+                                "parsing key index {}, err={err}",
+                                key
+                            ))
+                        })?;
+                        let color = value.as_str().ok_or_else(|| {
+                            CherryRgbError::ParseError(format!(
+                                "Invalid color for key with index {key_index}"
+                            ))
+                        })?;
+                        Ok((key.clone(), color.to_string()))
+                    })
+                    .collect::<Result<_, CherryRgbError>>()?;
+                Ok(Self {
+                    version: 1,
+                    default: None,
+                    groups: BTreeMap::new(),
+                    keys,
+                    animation: None,
+                })
+            }
+            Some(version) if version.as_u64() == Some(PROFILE_VERSION) => {
+                Ok(serde_json::from_value(value)?)
+            }
+            Some(version) => Err(CherryRgbError::ParseError(format!(
+                "Unsupported profile version {version}"
+            ))),
+        }
+    }
+
+    /// Profile setting all keys to the given colors, black keys are covered by `default`
+    pub fn from_key_leds(key_leds: &CustomKeyLeds) -> Self {
+        let black = OwnRGB8::default();
+        let keys = key_leds
+            .leds()
+            .iter()
+            .enumerate()
+            .filter(|(_, color)| **color != black)
+            .map(|(index, color)| (index.to_string(), color.to_hex()))
+            .collect();
+        Self {
+            version: PROFILE_VERSION,
+            default: Some(black.to_hex()),
+            groups: BTreeMap::new(),
+            keys,
+            animation: None,
+        }
+    }
+
+    /// Serialize profile in the given format
+    pub fn to_text(&self, format: FileFormat) -> Result<String, CherryRgbError> {
+        match format {
+            FileFormat::Json => Ok(serde_json::to_string_pretty(self)?),
+            FileFormat::Toml => toml::to_string(self)
+                .map_err(|err| CherryRgbError::ParseError(format!("writing profile, err={err}"))),
+            FileFormat::Yaml => serde_yaml::to_string(self)
+                .map_err(|err| CherryRgbError::ParseError(format!("writing profile, err={err}"))),
+        }
+    }
+
+    /// Read profile file, the format is determined by the file extension
+    pub fn load(path: &Path) -> Result<Self, CherryRgbError> {
+        let profile = std::fs::read_to_string(path)
+            .map_err(|err| CherryRgbError::ParseError(format!("reading {:?}, err={err}", path)))?;
+        Self::parse(&profile, FileFormat::from_path(path))
+    }
+
+    /// LED indexes of a key, range or group
+    pub fn resolve_keys(&self, keys: &str) -> Result<Vec<usize>, CherryRgbError> {
+        if keys == GROUP_ALL {
+            return Ok((0..TOTAL_KEYS).collect());
+        }
+        match self.groups.get(keys) {
+            Some(members) => members
+                .iter()
+                .map(|member| resolve_key_or_range(&member.to_string()))
+                .collect::<Result<Vec<_>, _>>()
+                .map(|keys| keys.concat()),
+            None => resolve_key_or_range(keys),
+        }
+    }
+
+    /// Colors of all keys set by the profile, ordered by LED index
+    pub fn profile_keys(&self) -> Result<Vec<ProfileKey>, CherryRgbError> {
+        let color = |color: &str| {
+            parse_color(color).map_err(|err| {
+                CherryRgbError::ParseError(format!("Failed parsing color '{color}', err: {err}"))
+            })
+        };
+        let mut colors: BTreeMap<usize, OwnRGB8> = BTreeMap::new();
+        if let Some(default) = &self.default {
+            let default = color(default)?;
+            colors.extend((0..TOTAL_KEYS).map(|index| (index, default.clone())));
+        }
+
+        let mut entries = self
+            .keys
+            .iter()
+            .map(|(keys, value)| Ok((self.resolve_keys(keys)?, color(value)?)))
+            .collect::<Result<Vec<_>, CherryRgbError>>()?;
+        // Larger sets first, so more specific entries win
+        entries.sort_by_key(|(keys, _)| Reverse(keys.len()));
+        for (keys, color) in entries {
+            colors.extend(keys.into_iter().map(|index| (index, color.clone())));
+        }
+
+        Ok(colors
+            .into_iter()
+            .map(|(index, color)| ProfileKey::new(index, color))
+            .collect())
+    }
+
+    /// Embedded animation, the profile's groups are available in it as well
+    pub fn animation(&self) -> Result<Option<KeyframeAnimation>, CherryRgbError> {
+        let mut animation = match &self.animation {
+            Some(animation) => animation.clone(),
+            None => return Ok(None),
+        };
+        for name in self.groups.keys() {
+            if !animation.groups.contains_key(name) {
+                animation
+                    .groups
+                    .insert(name.clone(), self.resolve_keys(name)?);
+            }
+        }
+        Ok(Some(animation))
+    }
+}
+
+/// LED indexes of a single key or an inclusive range of keys (e.g. `0-20`, `q-p`)
+fn resolve_key_or_range(keys: &str) -> Result<Vec<usize>, CherryRgbError> {
+    let unknown = || CherryRgbError::ParseError(format!("Unknown key, range or group '{keys}'"));
+    if let Some(index) = layout::key_index(keys) {
+        return Ok(vec![index]);
+    }
+    let (first, last) = keys.split_once('-').ok_or_else(unknown)?;
+    match (layout::key_index(first), layout::key_index(last)) {
+        (Some(first), Some(last)) if first <= last => Ok((first..=last).collect()),
+        _ => Err(unknown()),
+    }
+}
+
+/// Remove `//` comments and trailing commas outside of strings
+fn strip_json_extensions(json: &str) -> String {
+    let mut stripped = String::with_capacity(json.len());
+    let mut chars = json.chars().peekable();
+    let mut in_string = false;
+    while let Some(c) = chars.next() {
+        if in_string {
+            stripped.push(c);
+            match c {
+                '\\' => stripped.extend(chars.next()),
+                '"' => in_string = false,
+                _ => {}
+            }
+            continue;
+        }
+        match c {
+            '"' => {
+                in_string = true;
+                stripped.push(c);
+            }
+            '/' if chars.peek() == Some(&'/') => while chars.next_if(|&c| c != '\n').is_some() {},
+            '}' | ']' => {
+                let end = stripped.trim_end().len();
+                if stripped[..end].ends_with(',') {
+                    stripped.remove(end - 1);
+                }
+                stripped.push(c);
+            }
+            c => stripped.push(c),
+        }
+    }
+    stripped
+}
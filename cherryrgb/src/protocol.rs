@@ -0,0 +1,189 @@
+//! Typed requests sent to the keyboard and the responses they are answered with.
+//! See `Payload` for parsing packets of any type, e.g. from captures.
+
+use crate::{
+    models::{PayloadReadWrite, PayloadType},
+    Payload, CHUNK_SIZE,
+};
+use binrw::binrw;
+use std::{convert::TryFrom, fmt};
+
+/// Payload sent from host to keyboard, answered by the keyboard with `Self::Response`
+pub trait Request: PayloadReadWrite + Clone + fmt::Debug {
+    type Response: PayloadReadWrite + Clone + fmt::Debug;
+}
+
+/// Response the keyboard answers a request with
+pub type ResponseFor<R> = <R as Request>::Response;
+
+/// Start RGB setting transaction
+#[binrw]
+#[br(little, import(payload_type: u8), pre_assert(payload_type == 0x1))]
+#[bw(little)]
+#[derive(Clone, Debug)]
+pub struct TransactionStart;
+
+/// End RGB setting transaction
+#[binrw]
+#[br(little, import(payload_type: u8), pre_assert(payload_type == 0x2))]
+#[bw(little)]
+#[derive(Clone, Debug)]
+pub struct TransactionEnd;
+
+#[binrw]
+#[br(little, import(payload_type: u8), pre_assert(payload_type == 0x5))]
+#[bw(little)]
+#[derive(Clone, Debug)]
+pub struct Unknown5 {
+    pub unk: u8,
+}
+
+/// Write `data` into the device configuration at `offset`, see `DeviceConfig`
+#[binrw]
+#[br(little, import(payload_type: u8), pre_assert(payload_type == 0x6))]
+#[bw(little)]
+#[derive(Clone, Debug)]
+pub struct WriteConfig {
+    #[br(temp)]
+    #[bw(calc = data.len() as u8)]
+    len: u8,
+    pub offset: u16,
+    #[brw(magic = 0x55u8)]
+    #[br(count = len)]
+    pub data: Vec<u8>,
+}
+
+/// Set custom colors of the keys, 3 bytes per key starting at `data_offset`
+#[binrw]
+#[br(little, import(payload_type: u8), pre_assert(payload_type == 0xB))]
+#[bw(little)]
+#[derive(Clone, Debug)]
+pub struct SetCustomLED {
+    #[br(temp)]
+    #[bw(calc = key_leds_data.len() as u8)]
+    data_len: u8,
+    #[brw(pad_after = 1)]
+    pub data_offset: u16,
+    #[br(count = data_len)]
+    pub key_leds_data: Vec<u8>,
+}
+
+/// Read `data_len` bytes from `data_offset`, the payload type selects what is read
+#[binrw]
+#[br(little, import(payload_type: u8), pre_assert(payload_type == PAYLOAD_TYPE))]
+#[bw(little)]
+#[derive(Clone, Debug)]
+pub struct Read<const PAYLOAD_TYPE: u8> {
+    pub data_len: u8,
+    #[brw(pad_after = 1)]
+    pub data_offset: u16,
+}
+
+/// Data read by `Read`
+#[binrw]
+#[br(little, import(payload_type: u8), pre_assert(payload_type == PAYLOAD_TYPE))]
+#[bw(little)]
+#[derive(Clone, Debug)]
+pub struct ReadResponse<const PAYLOAD_TYPE: u8> {
+    #[br(temp)]
+    #[bw(calc = data.len() as u8)]
+    data_len: u8,
+    #[brw(pad_after = 1)]
+    pub data_offset: u16,
+    #[br(count = data_len)]
+    pub data: Vec<u8>,
+}
+
+/// Read the device configuration, see `DeviceConfig`
+pub type ReadConfig = Read<0x3>;
+/// Read the keymap, 3 bytes per key
+pub type GetKeymap = Read<0x7>;
+/// Read custom colors of the keys, 3 bytes per key
+pub type GetCustomLED = Read<0xB>;
+/// Read key indexes
+pub type GetKeyIndexes = Read<0x1B>;
+
+impl PayloadType for TransactionStart {
+    fn payload_type(&self) -> u8 {
+        0x1
+    }
+}
+
+impl PayloadType for TransactionEnd {
+    fn payload_type(&self) -> u8 {
+        0x2
+    }
+}
+
+impl PayloadType for Unknown5 {
+    fn payload_type(&self) -> u8 {
+        0x5
+    }
+}
+
+impl PayloadType for WriteConfig {
+    fn payload_type(&self) -> u8 {
+        0x6
+    }
+}
+
+impl PayloadType for SetCustomLED {
+    fn payload_type(&self) -> u8 {
+        0xB
+    }
+}
+
+impl<const PAYLOAD_TYPE: u8> PayloadType for Read<PAYLOAD_TYPE> {
+    fn payload_type(&self) -> u8 {
+        PAYLOAD_TYPE
+    }
+}
+
+impl<const PAYLOAD_TYPE: u8> PayloadType for ReadResponse<PAYLOAD_TYPE> {
+    fn payload_type(&self) -> u8 {
+        PAYLOAD_TYPE
+    }
+}
+
+impl PayloadReadWrite for TransactionStart {}
+impl PayloadReadWrite for TransactionEnd {}
+impl PayloadReadWrite for Unknown5 {}
+impl PayloadReadWrite for WriteConfig {}
+impl PayloadReadWrite for SetCustomLED {}
+impl<const PAYLOAD_TYPE: u8> PayloadReadWrite for Read<PAYLOAD_TYPE> {}
+impl<const PAYLOAD_TYPE: u8> PayloadReadWrite for ReadResponse<PAYLOAD_TYPE> {}
+
+// The responses to writes are not understood, they are parsed as any payload
+impl Request for TransactionStart {
+    type Response = Payload;
+}
+
+impl Request for TransactionEnd {
+    type Response = Payload;
+}
+
+impl Request for Unknown5 {
+    type Response = Payload;
+}
+
+impl Request for WriteConfig {
+    type Response = Payload;
+}
+
+impl Request for SetCustomLED {
+    type Response = Payload;
+}
+
+impl<const PAYLOAD_TYPE: u8> Request for Read<PAYLOAD_TYPE> {
+    type Response = ReadResponse<PAYLOAD_TYPE>;
+}
+
+impl<const PAYLOAD_TYPE: u8> Read<PAYLOAD_TYPE> {
+    /// Requests reading `len` bytes from `offset`, in chunks fitting into a packet
+    pub fn chunked(offset: u16, len: usize) -> impl Iterator<Item = Self> {
+        (0..len).step_by(CHUNK_SIZE).map(move |chunk_offset| Self {
+            data_len: std::cmp::min(len - chunk_offset, CHUNK_SIZE) as u8,
+            data_offset: offset + chunk_offset as u16,
+        })
+    }
+}
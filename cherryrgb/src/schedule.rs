@@ -0,0 +1,351 @@
+//! Time-of-day scheduling of profiles
+//!
+//! Rules are matched in order, later rules take precedence. A rule ending
+//! before it starts continues into the next day.
+//!
+//! Example (TOML):
+//! ```toml
+//! [profiles]
+//! work = { color_profile = "work.json" }
+//! evening = { animation = { mode = "breathing", brightness = "low", color = "ff8000" } }
+//! night = "off"
+//!
+//! [[rules]]
+//! profile = "work"
+//! days = ["mon", "tue", "wed", "thu", "fri"]
+//! start = "09:00"
+//! end = "17:00"
+//!
+//! [[rules]]
+//! profile = "evening"
+//! start = "19:00"
+//! end = "23:00"
+//!
+//! [[rules]]
+//! profile = "night"
+//! start = "23:00"
+//! end = "07:00"
+//! ```
+
+use crate::{
+    nightlight::parse_time_of_day, AnimationParams, Brightness, CherryRgbError, CustomKeyLeds,
+    LightingMode, LightingState, OwnRGB8, Profile, Speed,
+};
+use serde::{Deserialize, Deserializer, Serialize};
+use std::{
+    collections::BTreeMap,
+    convert::TryFrom,
+    fmt,
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+const MINUTES_PER_DAY: u32 = 24 * 60;
+
+/// Day of the week
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Weekday {
+    Mon,
+    Tue,
+    Wed,
+    Thu,
+    Fri,
+    Sat,
+    Sun,
+}
+
+const WEEKDAYS: [Weekday; 7] = [
+    Weekday::Mon,
+    Weekday::Tue,
+    Weekday::Wed,
+    Weekday::Thu,
+    Weekday::Fri,
+    Weekday::Sat,
+    Weekday::Sun,
+];
+
+impl Weekday {
+    /// Day by number of days since monday, wrapping around
+    pub fn from_days_since_monday(days: u32) -> Self {
+        WEEKDAYS[days as usize % 7]
+    }
+
+    fn days_since_monday(self) -> u32 {
+        self as u32
+    }
+}
+
+/// Point in the week, local time
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct WeekTime {
+    pub weekday: Weekday,
+    /// Minutes since midnight
+    pub minute: u32,
+}
+
+impl WeekTime {
+    fn add_minutes(self, minutes: u32) -> Self {
+        let total = self.minute + minutes;
+        Self {
+            weekday: Weekday::from_days_since_monday(
+                self.weekday.days_since_monday() + total / MINUTES_PER_DAY,
+            ),
+            minute: total % MINUTES_PER_DAY,
+        }
+    }
+}
+
+impl fmt::Display for WeekTime {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{:?} {:02}:{:02}",
+            self.weekday,
+            self.minute / 60,
+            self.minute % 60
+        )
+    }
+}
+
+/// Source of the current local time, can be replaced in tests
+pub trait Clock: Send {
+    fn now(&self) -> WeekTime;
+}
+
+impl<F: Fn() -> WeekTime + Send> Clock for F {
+    fn now(&self) -> WeekTime {
+        self()
+    }
+}
+
+/// Hardware animation, names as on the command line
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct AnimationPreset {
+    pub mode: String,
+    pub brightness: Option<String>,
+    pub speed: Option<String>,
+    /// Color (e.g. ff0000), white if not set
+    pub color: Option<String>,
+    #[serde(default)]
+    pub rainbow: bool,
+}
+
+/// What a schedule switches to
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ScheduledProfile {
+    /// Color profile file, relative to the schedule file
+    ColorProfile(PathBuf),
+    Animation(AnimationPreset),
+    /// All LEDs off
+    Off,
+}
+
+impl ScheduledProfile {
+    /// Lighting state to apply, files are looked up relative to `base_dir`
+    pub fn lighting_state(&self, base_dir: &Path) -> Result<LightingState, CherryRgbError> {
+        let parse_error = |what: &str, value: &str| {
+            CherryRgbError::ParseError(format!("Invalid {what} '{value}' in schedule"))
+        };
+        match self {
+            ScheduledProfile::ColorProfile(path) => {
+                let profile = Profile::load(&base_dir.join(path))?;
+                let keys = CustomKeyLeds::try_from(profile.profile_keys()?)?;
+                Ok(LightingState::CustomColors(keys))
+            }
+            ScheduledProfile::Animation(preset) => {
+                let mode = LightingMode::from_str(&preset.mode)
+                    .map_err(|_| parse_error("mode", &preset.mode))?;
+                let brightness = match &preset.brightness {
+                    Some(b) => Brightness::from_str(b).map_err(|_| parse_error("brightness", b))?,
+                    None => Brightness::Full,
+                };
+                let speed = match &preset.speed {
+                    Some(s) => Speed::from_str(s).map_err(|_| parse_error("speed", s))?,
+                    None => Speed::Medium,
+                };
+                let color = match &preset.color {
+                    Some(c) => OwnRGB8::from_str(c).map_err(|_| parse_error("color", c))?,
+                    None => OwnRGB8::new(255, 255, 255),
+                };
+                Ok(LightingState::Animation(AnimationParams {
+                    mode,
+                    brightness,
+                    speed,
+                    color,
+                    rainbow: preset.rainbow,
+                }))
+            }
+            ScheduledProfile::Off => Ok(LightingState::CustomColors(CustomKeyLeds::new())),
+        }
+    }
+}
+
+fn deserialize_time_of_day<'de, D: Deserializer<'de>>(deserializer: D) -> Result<u32, D::Error> {
+    let time = String::deserialize(deserializer)?;
+    parse_time_of_day(&time).map_err(serde::de::Error::custom)
+}
+
+/// Profile which is active on certain days during a time range
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ScheduleRule {
+    pub profile: String,
+    /// Days the time range starts on, every day if empty
+    #[serde(default)]
+    pub days: Vec<Weekday>,
+    /// Start (HH:MM), minutes since midnight
+    #[serde(deserialize_with = "deserialize_time_of_day")]
+    pub start: u32,
+    /// End (HH:MM, exclusive), minutes since midnight. The whole day, if equal to `start`
+    #[serde(deserialize_with = "deserialize_time_of_day")]
+    pub end: u32,
+}
+
+impl ScheduleRule {
+    fn starts_on(&self, weekday: Weekday) -> bool {
+        self.days.is_empty() || self.days.contains(&weekday)
+    }
+
+    /// True, if the rule applies at the given time
+    pub fn matches(&self, at: WeekTime) -> bool {
+        let yesterday = Weekday::from_days_since_monday(at.weekday.days_since_monday() + 6);
+        match self.start.cmp(&self.end) {
+            std::cmp::Ordering::Less => {
+                self.starts_on(at.weekday) && (self.start..self.end).contains(&at.minute)
+            }
+            std::cmp::Ordering::Equal => self.starts_on(at.weekday),
+            std::cmp::Ordering::Greater => {
+                (self.starts_on(at.weekday) && at.minute >= self.start)
+                    || (self.starts_on(yesterday) && at.minute < self.end)
+            }
+        }
+    }
+}
+
+/// Named profiles and the rules when to activate them
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Schedule {
+    #[serde(default)]
+    pub profiles: BTreeMap<String, ScheduledProfile>,
+    #[serde(default)]
+    pub rules: Vec<ScheduleRule>,
+    /// Directory color profile files are relative to
+    #[serde(skip)]
+    pub base_dir: PathBuf,
+}
+
+impl Schedule {
+    /// Parse schedule (TOML) and check that all rules refer to known profiles
+    pub fn from_toml(schedule: &str) -> Result<Self, CherryRgbError> {
+        let schedule: Schedule = toml::from_str(schedule)
+            .map_err(|err| CherryRgbError::ParseError(format!("parsing schedule, err={err}")))?;
+        if let Some(rule) = schedule
+            .rules
+            .iter()
+            .find(|rule| !schedule.profiles.contains_key(&rule.profile))
+        {
+            return Err(CherryRgbError::ParseError(format!(
+                "Unknown profile '{}' in schedule",
+                rule.profile
+            )));
+        }
+        Ok(schedule)
+    }
+
+    /// Read schedule file
+    pub fn load(path: &Path) -> Result<Self, CherryRgbError> {
+        let toml = std::fs::read_to_string(path)
+            .map_err(|err| CherryRgbError::ParseError(format!("reading {:?}, err={err}", path)))?;
+        let mut schedule = Self::from_toml(&toml)?;
+        schedule.base_dir = path.parent().map(Path::to_owned).unwrap_or_default();
+        Ok(schedule)
+    }
+
+    /// Name of the profile active at the given time
+    pub fn active(&self, at: WeekTime) -> Option<&str> {
+        self.rules
+            .iter()
+            .rev()
+            .find(|rule| rule.matches(at))
+            .map(|rule| rule.profile.as_str())
+    }
+
+    /// Next time the active profile changes within a week, and the profile active from then on
+    pub fn next_change(&self, at: WeekTime) -> Option<(WeekTime, Option<&str>)> {
+        let active = self.active(at);
+        (1..=7 * MINUTES_PER_DAY)
+            .map(|minutes| at.add_minutes(minutes))
+            .map(|at| (at, self.active(at)))
+            .find(|(_, profile)| *profile != active)
+    }
+}
+
+/// Active and upcoming profile, see `Scheduler::status`
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ScheduleStatus {
+    pub active: Option<String>,
+    pub next_change: Option<WeekTime>,
+    /// Profile active after the next change, `None` if no rule applies then
+    pub next: Option<String>,
+}
+
+impl fmt::Display for ScheduleStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = |profile: &Option<String>| profile.clone().unwrap_or_else(|| "-".to_string());
+        writeln!(f, "Active profile: {}", name(&self.active))?;
+        match self.next_change {
+            Some(at) => write!(f, "Next profile: {} at {}", name(&self.next), at),
+            None => write!(f, "Next profile: -"),
+        }
+    }
+}
+
+/// Switches profiles according to a schedule
+pub struct Scheduler {
+    schedule: Schedule,
+    clock: Box<dyn Clock>,
+    active: Option<String>,
+}
+
+impl Scheduler {
+    pub fn new(schedule: Schedule, clock: Box<dyn Clock>) -> Self {
+        Self {
+            schedule,
+            clock,
+            active: None,
+        }
+    }
+
+    /// Returns the profile to switch to, if another profile has become active since the last poll.
+    /// The lighting is left alone, while no rule applies.
+    pub fn poll(&mut self) -> Option<(&str, &ScheduledProfile)> {
+        let active = self.schedule.active(self.clock.now());
+        if active == self.active.as_deref() {
+            return None;
+        }
+        self.active = active.map(str::to_owned);
+        let name = active?;
+        self.schedule
+            .profiles
+            .get_key_value(name)
+            .map(|(name, profile)| (name.as_str(), profile))
+    }
+
+    pub fn schedule(&self) -> &Schedule {
+        &self.schedule
+    }
+
+    pub fn status(&self) -> ScheduleStatus {
+        let now = self.clock.now();
+        let next = self.schedule.next_change(now);
+        ScheduleStatus {
+            active: self.schedule.active(now).map(str::to_owned),
+            next_change: next.map(|(at, _)| at),
+            next: next.and_then(|(_, profile)| profile.map(str::to_owned)),
+        }
+    }
+}
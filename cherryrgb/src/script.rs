@@ -0,0 +1,201 @@
+#![cfg(feature = "scripting")]
+
+//! Custom lighting effects written in [Rhai](https://rhai.rs)
+//!
+//! The script is evaluated once per frame. The following variables are available:
+//!
+//! * `time` - seconds since the effect was started (float)
+//! * `frame` - number of the current frame, starting at 0
+//! * `leds` - array of `KEYS` colors (0xRRGGBB), initialized with the previous frame
+//! * `pressed` - LED indexes of the keys currently held down
+//! * `events` - key events since the last frame, e.g. `#{ index: 42, pressed: true }`
+//! * `state` - object map which is kept between frames
+//!
+//! Helper functions: `rgb(r, g, b)`, `red(c)`, `green(c)`, `blue(c)`,
+//! `key_x(index)`, `key_y(index)` and the constants `KEYS`, `COLUMNS`, `ROWS`.
+//!
+//! Example (a ripple of red following each keypress):
+//! ```text
+//! for i in 0..KEYS { leds[i] = rgb(0, 0, 32); }
+//! for e in events { if e.pressed { state.last = e.index; state.since = time; } }
+//! if "last" in state {
+//!     let r = (time - state.since) * 10.0;
+//!     for i in 0..KEYS {
+//!         let d = abs(key_x(i) - key_x(state.last)) + abs(key_y(i) - key_y(state.last));
+//!         if d.to_float() <= r && d.to_float() > r - 1.5 { leds[i] = rgb(255, 0, 0); }
+//!     }
+//! }
+//! ```
+
+use crate::{
+    effect::Effect,
+    layout::{self, COLUMNS, ROWS},
+    CherryRgbError, CustomKeyLeds, OwnRGB8, TOTAL_KEYS,
+};
+use rgb::RGB8;
+use rhai::{Array, Dynamic, Engine, Map, Scope, AST};
+use std::{
+    collections::BTreeSet,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+/// Max. number of operations a script may execute per frame
+const MAX_OPERATIONS: u64 = 1_000_000;
+/// Max. wall clock time a script may run per frame
+const MAX_FRAME_TIME: Duration = Duration::from_millis(50);
+
+/// Compiled lighting effect script and its state between frames
+pub struct ScriptEffect {
+    name: String,
+    engine: Engine,
+    ast: AST,
+    deadline: Arc<Mutex<Instant>>,
+    frame: i64,
+    leds: Array,
+    state: Map,
+    pressed: BTreeSet<usize>,
+    events: Array,
+}
+
+fn script_error<E: std::fmt::Display>(name: &str, err: E) -> CherryRgbError {
+    CherryRgbError::ScriptError(format!("{name}: {err}"))
+}
+
+fn color_to_int(color: OwnRGB8) -> i64 {
+    let c: RGB8 = color.into();
+    ((c.r as i64) << 16) | ((c.g as i64) << 8) | c.b as i64
+}
+
+fn int_to_color(value: i64) -> OwnRGB8 {
+    OwnRGB8::new((value >> 16) as u8, (value >> 8) as u8, value as u8)
+}
+
+/// Create an engine which can't exhaust memory or time of the hosting process
+fn sandboxed_engine(deadline: Arc<Mutex<Instant>>) -> Engine {
+    let mut engine = Engine::new();
+    // Scripts must not be able to load other files
+    engine.set_module_resolver(rhai::module_resolvers::DummyModuleResolver::new());
+    engine.set_max_operations(MAX_OPERATIONS);
+    engine.set_max_call_levels(32);
+    engine.set_max_expr_depths(64, 32);
+    engine.set_max_string_size(4096);
+    engine.set_max_array_size(4 * TOTAL_KEYS);
+    engine.set_max_map_size(4 * TOTAL_KEYS);
+    engine.disable_symbol("eval");
+    engine.on_progress(move |_| {
+        if Instant::now() > *deadline.lock().unwrap() {
+            Some("Frame time exceeded".into())
+        } else {
+            None
+        }
+    });
+
+    engine.register_fn("rgb", |r: i64, g: i64, b: i64| {
+        let clamp = |v: i64| v.clamp(0, 255) as u8;
+        color_to_int(OwnRGB8::new(clamp(r), clamp(g), clamp(b)))
+    });
+    engine.register_fn("red", |c: i64| (c >> 16) & 0xff);
+    engine.register_fn("green", |c: i64| (c >> 8) & 0xff);
+    engine.register_fn("blue", |c: i64| c & 0xff);
+    engine.register_fn("key_x", |index: i64| {
+        layout::key_position(index as usize).map_or(-1, |(x, _)| x as i64)
+    });
+    engine.register_fn("key_y", |index: i64| {
+        layout::key_position(index as usize).map_or(-1, |(_, y)| y as i64)
+    });
+
+    engine
+}
+
+impl ScriptEffect {
+    /// Compile script source. `name` is only used in error messages.
+    pub fn new(name: &str, source: &str) -> Result<Self, CherryRgbError> {
+        let deadline = Arc::new(Mutex::new(Instant::now()));
+        let engine = sandboxed_engine(Arc::clone(&deadline));
+        let ast = engine
+            .compile(source)
+            .map_err(|err| script_error(name, err))?;
+
+        Ok(Self {
+            name: name.to_owned(),
+            engine,
+            ast,
+            deadline,
+            frame: 0,
+            leds: (0..TOTAL_KEYS).map(|_| Dynamic::from_int(0)).collect(),
+            state: Map::new(),
+            pressed: BTreeSet::new(),
+            events: Array::new(),
+        })
+    }
+}
+
+impl Effect for ScriptEffect {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn key_report(&mut self, report: &[u8]) {
+        // Reports with other report IDs (e.g. media keys) don't tell anything about the keyboard keys
+        if !matches!(report.first(), Some(1)) {
+            return;
+        }
+        let pressed: BTreeSet<usize> = layout::pressed_keys(report).into_iter().collect();
+        for (index, is_pressed) in pressed
+            .difference(&self.pressed)
+            .map(|&i| (i, true))
+            .chain(self.pressed.difference(&pressed).map(|&i| (i, false)))
+        {
+            let mut event = Map::new();
+            event.insert("index".into(), Dynamic::from_int(index as i64));
+            event.insert("pressed".into(), Dynamic::from_bool(is_pressed));
+            self.events.push(event.into());
+        }
+        self.pressed = pressed;
+    }
+
+    /// Run the script once and return the resulting key colors
+    fn render(&mut self, elapsed: Duration) -> Result<Option<CustomKeyLeds>, CherryRgbError> {
+        let mut scope = Scope::new();
+        scope
+            .push_constant("KEYS", TOTAL_KEYS as i64)
+            .push_constant("COLUMNS", COLUMNS as i64)
+            .push_constant("ROWS", ROWS as i64)
+            .push("time", elapsed.as_secs_f64())
+            .push("frame", self.frame)
+            .push("leds", std::mem::take(&mut self.leds))
+            .push(
+                "pressed",
+                self.pressed
+                    .iter()
+                    .map(|&i| Dynamic::from_int(i as i64))
+                    .collect::<Array>(),
+            )
+            .push("events", std::mem::take(&mut self.events))
+            .push("state", std::mem::take(&mut self.state));
+
+        *self.deadline.lock().unwrap() = Instant::now() + MAX_FRAME_TIME;
+        let result = self.engine.run_ast_with_scope(&mut scope, &self.ast);
+        self.frame += 1;
+
+        self.state = scope.get_value("state").unwrap_or_default();
+        self.leds = scope.get_value("leds").unwrap_or_default();
+        result.map_err(|err| script_error(&self.name, err))?;
+
+        if self.leds.len() != TOTAL_KEYS {
+            return Err(script_error(
+                &self.name,
+                format!("leds must contain {} colors", TOTAL_KEYS),
+            ));
+        }
+        let colors = self
+            .leds
+            .iter()
+            .map(|c| c.as_int().map(int_to_color))
+            .collect::<Result<Vec<OwnRGB8>, _>>()
+            .map_err(|t| script_error(&self.name, format!("leds contains a {t}")))?;
+
+        CustomKeyLeds::from_leds(colors).map(Some)
+    }
+}
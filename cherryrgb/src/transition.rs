@@ -0,0 +1,204 @@
+//! Smooth transitions between lighting states
+//!
+//! Custom colors are crossfaded frame by frame. Hardware animations can't be
+//! interpolated, so they are faded out and in by stepping through the brightness levels.
+
+use crate::{AnimationParams, Brightness, CustomKeyLeds, LightingState, OwnRGB8};
+use std::time::Duration;
+
+/// Frames per second of custom color crossfades
+const FRAMES_PER_SECOND: u32 = 25;
+
+/// Brightness levels, from dark to bright
+const BRIGHTNESS_LEVELS: [Brightness; 5] = [
+    Brightness::Off,
+    Brightness::Low,
+    Brightness::Medium,
+    Brightness::High,
+    Brightness::Full,
+];
+
+/// Single command of a transition
+#[derive(Clone, Debug)]
+pub enum TransitionStep {
+    /// Set hardware animation, see `CherryKeyboard::set_led_animation`
+    Animation(AnimationParams),
+    /// Switch to custom mode, see `CherryKeyboard::set_custom_colors`
+    CustomMode(CustomKeyLeds),
+    /// Send the next crossfade frame, see `CherryKeyboard::update_custom_colors`
+    CustomColors(CustomKeyLeds),
+}
+
+/// Precomputed steps leading from one lighting state to another
+#[derive(Clone, Debug)]
+pub struct Transition {
+    steps: Vec<(Duration, TransitionStep)>,
+}
+
+impl Transition {
+    /// Plan the transition. If the current state is unknown (`from` is `None`),
+    /// the target is faded in from dark.
+    pub fn new(from: Option<&LightingState>, to: &LightingState, duration: Duration) -> Self {
+        let mut steps = vec![];
+
+        if duration.is_zero() {
+            steps.push((Duration::ZERO, to.clone().into()));
+            return Self { steps };
+        }
+
+        match (from, to) {
+            (Some(LightingState::CustomColors(from)), LightingState::CustomColors(to)) => {
+                crossfade(&mut steps, from, to, Duration::ZERO, duration);
+            }
+            (from, to) => {
+                // Fade out to dark during the first half, fade in during the second
+                let half = match from {
+                    Some(_) => duration / 2,
+                    None => Duration::ZERO,
+                };
+                match from {
+                    Some(LightingState::Animation(params)) => {
+                        fade_out_animation(&mut steps, params, half)
+                    }
+                    Some(LightingState::CustomColors(leds)) => crossfade(
+                        &mut steps,
+                        leds,
+                        &CustomKeyLeds::new(),
+                        Duration::ZERO,
+                        half,
+                    ),
+                    None => {}
+                }
+                match to {
+                    LightingState::Animation(params) => {
+                        fade_in_animation(&mut steps, params, half, duration - half)
+                    }
+                    LightingState::CustomColors(leds) => crossfade(
+                        &mut steps,
+                        &CustomKeyLeds::new(),
+                        leds,
+                        half,
+                        duration - half,
+                    ),
+                }
+            }
+        }
+
+        Self { steps }
+    }
+
+    /// Steps and their time offsets since the start of the transition
+    pub fn steps(&self) -> &[(Duration, TransitionStep)] {
+        &self.steps
+    }
+
+    /// Total length of the transition
+    pub fn duration(&self) -> Duration {
+        self.steps.last().map_or(Duration::ZERO, |(at, _)| *at)
+    }
+}
+
+impl TransitionStep {
+    /// Apply `f` to all colors, e.g. for post-processing before sending the step
+    pub fn map_colors<F: Fn(&OwnRGB8) -> OwnRGB8>(self, f: F) -> Self {
+        let map_leds = |leds: CustomKeyLeds| {
+            CustomKeyLeds::from_leds(leds.leds().iter().map(&f).collect()).unwrap()
+        };
+        match self {
+            TransitionStep::Animation(mut params) => {
+                params.color = f(&params.color);
+                TransitionStep::Animation(params)
+            }
+            TransitionStep::CustomMode(leds) => TransitionStep::CustomMode(map_leds(leds)),
+            TransitionStep::CustomColors(leds) => TransitionStep::CustomColors(map_leds(leds)),
+        }
+    }
+
+    /// Limit brightness. Animations use the brightness level of the keyboard,
+    /// custom colors are darkened instead.
+    pub fn dim(self, brightness: &Brightness) -> Self {
+        match self {
+            TransitionStep::Animation(mut params) => {
+                if params.brightness.clone() as u8 > brightness.clone() as u8 {
+                    params.brightness = brightness.clone();
+                }
+                TransitionStep::Animation(params)
+            }
+            step => {
+                let black = OwnRGB8::default();
+                let factor = brightness.factor();
+                step.map_colors(|color| black.lerp(color, factor))
+            }
+        }
+    }
+}
+
+/// Step which applies the state at once
+impl From<LightingState> for TransitionStep {
+    fn from(state: LightingState) -> Self {
+        match state {
+            LightingState::Animation(params) => TransitionStep::Animation(params),
+            LightingState::CustomColors(leds) => TransitionStep::CustomMode(leds),
+        }
+    }
+}
+
+fn brightness_index(brightness: &Brightness) -> usize {
+    BRIGHTNESS_LEVELS
+        .iter()
+        .position(|b| b == brightness)
+        .unwrap_or(0)
+}
+
+fn fade_out_animation(
+    steps: &mut Vec<(Duration, TransitionStep)>,
+    params: &AnimationParams,
+    length: Duration,
+) {
+    let levels = brightness_index(&params.brightness);
+    for (i, level) in (0..levels).rev().enumerate() {
+        let mut params = params.clone();
+        params.brightness = BRIGHTNESS_LEVELS[level].clone();
+        steps.push((
+            length * i as u32 / levels as u32,
+            TransitionStep::Animation(params),
+        ));
+    }
+}
+
+fn fade_in_animation(
+    steps: &mut Vec<(Duration, TransitionStep)>,
+    params: &AnimationParams,
+    start: Duration,
+    length: Duration,
+) {
+    let levels = brightness_index(&params.brightness);
+    for (level, brightness) in BRIGHTNESS_LEVELS.iter().enumerate().take(levels + 1) {
+        let mut params = params.clone();
+        params.brightness = brightness.clone();
+        let at = match levels {
+            0 => start,
+            _ => start + length * level as u32 / levels as u32,
+        };
+        steps.push((at, TransitionStep::Animation(params)));
+    }
+}
+
+fn crossfade(
+    steps: &mut Vec<(Duration, TransitionStep)>,
+    from: &CustomKeyLeds,
+    to: &CustomKeyLeds,
+    start: Duration,
+    length: Duration,
+) {
+    // Switching to custom mode is a no-op, if the keyboard is already showing `from`
+    steps.push((start, TransitionStep::CustomMode(from.clone())));
+    let frames = ((length.as_secs_f32() * FRAMES_PER_SECOND as f32) as u32).max(1);
+    for frame in 1..=frames {
+        let t = frame as f32 / frames as f32;
+        steps.push((
+            start + length * frame / frames,
+            TransitionStep::CustomColors(from.interpolate(to, t)),
+        ));
+    }
+}
@@ -1,11 +1,30 @@
 #![cfg(all(target_os = "linux", feature = "uhid"))]
 
+use crate::layout::LockState;
 use log::error;
-use uhid_virt::{Bus, CreateParams, UHIDDevice};
+use nix::{
+    fcntl::OFlag,
+    poll::{poll, PollFd, PollFlags},
+};
+use std::{
+    convert::TryFrom,
+    fs::{File, OpenOptions},
+    io::{ErrorKind, Read, Write},
+    os::unix::{fs::OpenOptionsExt, io::AsRawFd},
+};
+use uhid_virt::{Bus, CreateParams, InputEvent, OutputEvent, StreamError, UHID_EVENT_SIZE};
+
+const UHID_PATH: &str = "/dev/uhid";
+/// How long to wait for the UHID device to accept an event, in milliseconds
+const WRITE_TIMEOUT: i32 = 100;
 
 /// Virtual HID device for injecting key events into the Linux HID subsystem
 pub struct VirtKbd {
-    device: UHIDDevice<std::fs::File>,
+    /// UHID device, opened non-blocking so output events can be polled by the driver loop.
+    /// uhid-virt's `UHIDDevice` only offers blocking reads, so events are (de)serialized
+    /// with its codec instead.
+    device: File,
+    lock_state: LockState,
 }
 
 impl Default for VirtKbd {
@@ -16,23 +35,33 @@ impl Default for VirtKbd {
 
 impl VirtKbd {
     pub fn new() -> Self {
-        VirtKbd {
-            device: UHIDDevice::create(CreateParams {
-                name: String::from("cherryrgb"),
-                phys: String::from(""), // ?
-                uniq: String::from(""), // ?
-                bus: Bus::USB,
-                vendor: 0xdead,
-                product: 0xbeef,
-                version: 0,
-                country: 0,
-                #[rustfmt::skip]
-                rd_data: [
-                    // Annotated report descriptor generated by running hid-decode
-                    // on the event device of my Cherry MX 10.0 N RGB
-                    // hid-decode is part of hid-tools
-                    // See: https://gitlab.freedesktop.org/libevdev/hid-tools
-                    //
+        let device = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .custom_flags(OFlag::O_NONBLOCK.bits())
+            .open(UHID_PATH)
+            .map_err(|err| error!("Could not open {}: {:?}", UHID_PATH, err))
+            .expect("Could not create VirtKbd");
+        let mut vkbd = VirtKbd {
+            device,
+            lock_state: LockState::default(),
+        };
+        vkbd.write_event(InputEvent::Create(CreateParams {
+            name: String::from("cherryrgb"),
+            phys: String::from(""), // ?
+            uniq: String::from(""), // ?
+            bus: Bus::USB,
+            vendor: 0xdead,
+            product: 0xbeef,
+            version: 0,
+            country: 0,
+            #[rustfmt::skip]
+            rd_data: [
+                // Annotated report descriptor generated by running hid-decode
+                // on the event device of my Cherry MX 10.0 N RGB
+                // hid-decode is part of hid-tools
+                // See: https://gitlab.freedesktop.org/libevdev/hid-tools
+                //
 					0x05, 0x01,                    // Usage Page (Generic Desktop)
 					0x09, 0x06,                    // Usage (Keyboard)
 					0xa1, 0x01,                    // Collection (Application)
@@ -45,6 +74,16 @@ impl VirtKbd {
 					0x75, 0x01,                    //  Report Size (1)
 					0x95, 0x78,                    //  Report Count (120)
 					0x81, 0x02,                    //  Input (Data,Var,Abs)
+					// Not part of the original descriptor: lock LEDs, so the host sends output reports
+					0x05, 0x08,                    //  Usage Page (LEDs)
+					0x19, 0x01,                    //  Usage Minimum (1)
+					0x29, 0x03,                    //  Usage Maximum (3)
+					0x95, 0x03,                    //  Report Count (3)
+					0x75, 0x01,                    //  Report Size (1)
+					0x91, 0x02,                    //  Output (Data,Var,Abs)
+					0x95, 0x01,                    //  Report Count (1)
+					0x75, 0x05,                    //  Report Size (5)
+					0x91, 0x01,                    //  Output (Cnst,Arr,Abs)
 					0xc0,                          // End Collection
 					0x05, 0x01,                    // Usage Page (Generic Desktop)
 					0x09, 0x80,                    // Usage (System Control)
@@ -129,17 +168,78 @@ impl VirtKbd {
 					0x81, 0x06,                    //   Input (Data,Var,Rel)
 					0xc0,                          //  End Collection
 					0xc0,                          // End Collection
-                ]
-                .to_vec(),
-            })
-            .map_err(|err| error!("Could not create VirtKbd: {:?}", err))
-            .expect("Could not create VirtKbd"),
+            ]
+            .to_vec(),
+        }))
+        .map_err(|err| error!("Could not create VirtKbd: {:?}", err))
+        .expect("Could not create VirtKbd");
+        vkbd
+    }
+
+    /// Write a single event, waiting up to `WRITE_TIMEOUT` if the device is busy
+    fn write_event(&mut self, event: InputEvent) -> std::io::Result<()> {
+        let event: [u8; UHID_EVENT_SIZE] = event.into();
+        loop {
+            match self.device.write(&event) {
+                Ok(_) => return Ok(()),
+                Err(err) if err.kind() == ErrorKind::Interrupted => {}
+                Err(err) if err.kind() == ErrorKind::WouldBlock => {
+                    let mut fds = [PollFd::new(self.device.as_raw_fd(), PollFlags::POLLOUT)];
+                    if poll(&mut fds, WRITE_TIMEOUT)? == 0 {
+                        return Err(err);
+                    }
+                }
+                Err(err) => return Err(err),
+            }
         }
     }
 
+    /// Read a single event, fails with `WouldBlock` if there is none pending
+    fn read_event(&mut self) -> Result<OutputEvent, StreamError> {
+        let mut event = [0u8; UHID_EVENT_SIZE];
+        self.device
+            .read_exact(&mut event)
+            .map_err(StreamError::Io)?;
+        OutputEvent::try_from(event)
+    }
+
     /// Forward a single HID event.
     /// See: CherryKeyboard::forward_filtered_keys()
     pub fn forward(&mut self, input: &[u8]) {
-        self.device.write(input).unwrap();
+        if let Err(err) = self.write_event(InputEvent::Input { data: input }) {
+            error!("Failed to forward key event, err={}", err);
+        }
+    }
+
+    /// Lock indicators as last set by the host
+    pub fn lock_state(&self) -> LockState {
+        self.lock_state
+    }
+
+    /// Process pending output events of the host (without blocking).
+    /// Returns the new lock state, if it has changed.
+    pub fn read_lock_state(&mut self) -> Option<LockState> {
+        let previous = self.lock_state;
+        loop {
+            match self.read_event() {
+                Ok(OutputEvent::Output { data }) => {
+                    log::debug!(" - output report: {:?}", data);
+                    if let Some(state) = LockState::from_output_report(&data) {
+                        self.lock_state = state;
+                    }
+                }
+                Ok(_) => {}
+                Err(StreamError::Io(err)) => {
+                    if err.kind() != std::io::ErrorKind::WouldBlock {
+                        error!("Failed to read from VirtKbd, err={}", err);
+                    }
+                    break;
+                }
+                Err(StreamError::UnknownEventType(event_type)) => {
+                    log::debug!("Unknown UHID event type {}", event_type);
+                }
+            }
+        }
+        Some(self.lock_state).filter(|&state| state != previous)
     }
 }
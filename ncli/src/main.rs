@@ -1,17 +1,31 @@
 use std::path::PathBuf;
-use std::{convert::TryFrom, io::Read, io::Write};
+use std::time::Duration;
+use std::{convert::TryFrom, io::BufRead, io::BufReader, io::Write};
 
-use anyhow::{Context, Result};
+use anyhow::{anyhow, Context, Result};
 use cherryrgb::{
-    self, read_color_profile, rgb, Brightness, CustomKeyLeds, LightingMode, OwnRGB8, RpcAnimation,
-    Speed,
+    self, rgb, write_scene, AnimationParams, Brightness, CustomKeyLeds, FileFormat, HeatmapScale,
+    History, KeyPressCounts, Layer, LightingMode, LightingState, Notification, OwnRGB8, Profile,
+    RpcAnimation, RpcEffect, RpcEffectSource, RpcHeatmap, RpcLighting, RpcTransition,
+    SavedLighting, Scene, Speed, TOTAL_KEYS,
 };
+use cherryrgb::{color::ColorSpace, schedule::ScheduleStatus};
 use clap::Parser;
+use serde::de::DeserializeOwned;
 use std::os::unix::net::UnixStream;
 
 mod ncli;
-use ncli::{CliCommand, Opt};
+use ncli::{
+    CliCommand, ColorProfileFileOptions, ExportFormat, HeatmapCommand, LayerCommand, NcliCommand,
+    Opt, PlaybackOptions, ProfileCommand,
+};
 
+#[path = "../../src/effects.rs"]
+mod effects;
+#[path = "../../src/lockfile.rs"]
+mod lockfile;
+#[path = "../../src/profiles.rs"]
+mod profiles;
 #[path = "../../src/state.rs"]
 mod state;
 
@@ -29,16 +43,18 @@ impl UnixClient {
         Ok(Self { sock })
     }
 
-    /// Reset custom key colors to default
-    pub fn reset_custom_colors(&mut self) -> Result<(), anyhow::Error> {
-        writeln!(self.sock, "reset_custom_colors").context(Self::ERR_WRITE)?;
-        Ok(())
-    }
-
-    /// Set custom color for each individual key
-    pub fn set_custom_colors(&mut self, key_leds: CustomKeyLeds) -> Result<(), anyhow::Error> {
-        let json = serde_json::to_string(&key_leds).unwrap();
-        writeln!(self.sock, "set_custom_colors={}", json).context(Self::ERR_WRITE)?;
+    /// Apply lighting as given, the service scales the custom colors by the brightness
+    pub fn set_lighting(
+        &mut self,
+        lighting: &SavedLighting,
+        transition: Option<Duration>,
+    ) -> Result<(), anyhow::Error> {
+        let rpc = RpcLighting {
+            lighting: lighting.clone(),
+            transition,
+        };
+        let json = serde_json::to_string(&rpc).unwrap();
+        writeln!(self.sock, "set_lighting={}", json).context(Self::ERR_WRITE)?;
         Ok(())
     }
 
@@ -62,6 +78,193 @@ impl UnixClient {
         writeln!(self.sock, "set_led_animation={}", json).context(Self::ERR_WRITE)?;
         Ok(())
     }
+
+    /// Apply scene (animation and custom colors)
+    pub fn apply_scene(&mut self, scene: &Scene) -> Result<(), anyhow::Error> {
+        let json = serde_json::to_string(scene).unwrap();
+        writeln!(self.sock, "apply_scene={}", json).context(Self::ERR_WRITE)?;
+        Ok(())
+    }
+
+    /// Fetch the identity of the service's keyboard, see `CherryKeyboard::device_id`
+    pub fn device_id(&mut self) -> Result<String, anyhow::Error> {
+        writeln!(self.sock, "get_device_id").context(Self::ERR_WRITE)?;
+        self.read_reply()
+    }
+
+    /// Fetch the custom colors last set through the service, as given before scaling them
+    pub fn get_custom_colors(&mut self) -> Result<CustomKeyLeds, anyhow::Error> {
+        writeln!(self.sock, "get_custom_colors").context(Self::ERR_WRITE)?;
+        self.read_reply()
+    }
+
+    /// Fetch the current lighting of the service as scene
+    pub fn get_scene(&mut self) -> Result<Scene, anyhow::Error> {
+        writeln!(self.sock, "get_scene").context(Self::ERR_WRITE)?;
+        self.read_reply()
+    }
+
+    /// Fade smoothly from the current lighting into `target`
+    pub fn transition(
+        &mut self,
+        target: LightingState,
+        duration: Duration,
+    ) -> Result<(), anyhow::Error> {
+        let rpc = RpcTransition { target, duration };
+        let json = serde_json::to_string(&rpc).unwrap();
+        writeln!(self.sock, "transition={}", json).context(Self::ERR_WRITE)?;
+        Ok(())
+    }
+
+    /// Add or replace layer
+    pub fn set_layer(&mut self, layer: Layer) -> Result<(), anyhow::Error> {
+        let json = serde_json::to_string(&layer).unwrap();
+        writeln!(self.sock, "set_layer={}", json).context(Self::ERR_WRITE)?;
+        Ok(())
+    }
+
+    /// Remove layer
+    pub fn remove_layer(&mut self, name: &str) -> Result<(), anyhow::Error> {
+        writeln!(self.sock, "remove_layer={}", name).context(Self::ERR_WRITE)?;
+        Ok(())
+    }
+
+    /// Show notification on top of the current lighting
+    pub fn notify(&mut self, notification: Notification) -> Result<(), anyhow::Error> {
+        let json = serde_json::to_string(&notification).unwrap();
+        writeln!(self.sock, "notify={}", json).context(Self::ERR_WRITE)?;
+        Ok(())
+    }
+
+    /// Read a JSON reply, anything else is an error message
+    fn read_reply<T: DeserializeOwned>(&mut self) -> Result<T, anyhow::Error> {
+        let mut reply = String::new();
+        BufReader::new(&self.sock)
+            .read_line(&mut reply)
+            .context("I/O error reading from socket")?;
+        serde_json::from_str(&reply).map_err(|_| anyhow::anyhow!("{}", reply.trim()))
+    }
+
+    /// Fetch key press counts recorded by the service
+    pub fn get_heatmap(&mut self) -> Result<KeyPressCounts, anyhow::Error> {
+        writeln!(self.sock, "get_heatmap").context(Self::ERR_WRITE)?;
+        self.read_reply()
+    }
+
+    /// Fetch active and next profile of the schedule
+    pub fn schedule_status(&mut self) -> Result<ScheduleStatus, anyhow::Error> {
+        writeln!(self.sock, "schedule_status").context(Self::ERR_WRITE)?;
+        self.read_reply()
+    }
+
+    /// Show key press counts as colors
+    pub fn show_heatmap(
+        &mut self,
+        scale: HeatmapScale,
+        gradient: Vec<OwnRGB8>,
+        space: ColorSpace,
+    ) -> Result<(), anyhow::Error> {
+        let rpc = RpcHeatmap {
+            scale,
+            gradient,
+            space,
+        };
+        let json = serde_json::to_string(&rpc).unwrap();
+        writeln!(self.sock, "show_heatmap={}", json).context(Self::ERR_WRITE)?;
+        Ok(())
+    }
+
+    /// Run software effect in the service
+    pub fn run_effect(&mut self, effect: &RpcEffect) -> Result<(), anyhow::Error> {
+        let json = serde_json::to_string(effect).unwrap();
+        writeln!(self.sock, "run_effect={}", json).context(Self::ERR_WRITE)?;
+        Ok(())
+    }
+
+    /// Go back to the lighting applied before by the service
+    pub fn undo(&mut self) -> Result<(), anyhow::Error> {
+        writeln!(self.sock, "undo").context(Self::ERR_WRITE)?;
+        Ok(())
+    }
+
+    /// Apply the lighting undone last again
+    pub fn redo(&mut self) -> Result<(), anyhow::Error> {
+        writeln!(self.sock, "redo").context(Self::ERR_WRITE)?;
+        Ok(())
+    }
+
+    /// Fetch the lighting recently applied by the service
+    pub fn get_history(&mut self) -> Result<History, anyhow::Error> {
+        writeln!(self.sock, "get_history").context(Self::ERR_WRITE)?;
+        self.read_reply()
+    }
+
+    /// Apply the lighting last applied by the service again
+    pub fn restore(&mut self) -> Result<(), anyhow::Error> {
+        writeln!(self.sock, "restore").context(Self::ERR_WRITE)?;
+        Ok(())
+    }
+}
+
+/// Software effect with the given playback options
+fn effect(name: String, source: RpcEffectSource, playback: &PlaybackOptions) -> RpcEffect {
+    RpcEffect {
+        name,
+        source,
+        fps: playback.fps,
+        duration: playback.duration,
+    }
+}
+
+/// Custom colors last set through the service, falling back to the saved state
+fn existing_colors(keyboard: &mut UnixClient) -> Result<CustomKeyLeds> {
+    match keyboard.get_custom_colors() {
+        Ok(keys) => Ok(keys),
+        Err(err) => {
+            log::warn!("Fetching colors from service failed, using saved state: {err:?}");
+            state::load(&keyboard.device_id()?)
+        }
+    }
+}
+
+/// Set custom colors from a color profile file, then play its animation
+fn set_color_profile(
+    keyboard: &mut UnixClient,
+    args: &ColorProfileFileOptions,
+    brightness: Brightness,
+    software_brightness: u8,
+    transition: Option<Duration>,
+) -> Result<()> {
+    let profile =
+        Profile::load(&args.file_path).context(format!("color profile {:?}", args.file_path))?;
+    let colors_from_file = profile
+        .profile_keys()
+        .context("reading colors from color file")?;
+
+    let keys = if args.keep_existing {
+        existing_colors(keyboard)?
+            .modify_from(colors_from_file)
+            .context("assembling custom key leds")?
+    } else {
+        CustomKeyLeds::try_from(colors_from_file).context("assembling custom key leds")?
+    };
+    // The colors are sent as given, so they can be modified later on
+    let mut saved = SavedLighting::custom_colors(keys, brightness, software_brightness);
+    keyboard.set_lighting(&saved, transition)?;
+
+    if let Some(animation) = profile.animation()? {
+        let animation = effect(
+            effects::animation_name(&args.file_path),
+            RpcEffectSource::Keyframes(animation),
+            &args.playback,
+        );
+        keyboard
+            .run_effect(&animation)
+            .context("Failed to play animation")?;
+        saved.effect = Some(animation);
+    }
+    state::save(&keyboard.device_id()?, &saved)?;
+    Ok(())
 }
 
 fn main() -> Result<()> {
@@ -77,52 +280,24 @@ fn main() -> Result<()> {
     let mut keyboard = UnixClient::new(opt.socket_path)?;
 
     match opt.command {
-        CliCommand::CustomColors(args) => {
-            keyboard.reset_custom_colors()?;
+        NcliCommand::Common(CliCommand::CustomColors(args)) => {
             let mut keys = CustomKeyLeds::new();
 
             for (index, color) in args.colors.into_iter().enumerate() {
                 keys.set_led(index, color)?;
             }
-
-            keyboard.set_custom_colors(keys)?;
-        }
-        CliCommand::ColorProfileFile(args) => {
-            let mut f = std::fs::File::open(&args.file_path)
-                .context(format!("color profile {:?}", args.file_path))?;
-            let mut json: String = String::new();
-
-            f.read_to_string(&mut json)?;
-
-            // Allow // comments
-            let re = regex::RegexBuilder::new(r"//.*?$")
-                .multi_line(true)
-                .build()
-                .unwrap();
-            json = re.replace_all(&json, "").to_string();
-            // Allow trailing comma after last element
-            let re = regex::RegexBuilder::new(r",(\s*\})").build().unwrap();
-            json = re.replace_all(&json, "$1").to_string();
-
-            log::debug!("{json}");
-
-            let colors_from_file =
-                read_color_profile(&json).context("reading colors from color file")?;
-
-            if args.keep_existing {
-                let keys = state::load()?
-                    .modify_from(colors_from_file)
-                    .context("assembling custom key leds")?;
-                keyboard.set_custom_colors(keys.clone())?;
-                state::save(keys)?;
-            } else {
-                let keys = CustomKeyLeds::try_from(colors_from_file)
-                    .context("assembling custom key leds")?;
-                keyboard.set_custom_colors(keys.clone())?;
-                state::save(keys)?;
-            }
+            let saved = SavedLighting::custom_colors(keys, opt.brightness, opt.software_brightness);
+            keyboard.set_lighting(&saved, opt.transition)?;
+            state::save(&keyboard.device_id()?, &saved)?;
         }
-        CliCommand::Animation(args) => {
+        NcliCommand::Common(CliCommand::ColorProfileFile(args)) => set_color_profile(
+            &mut keyboard,
+            &args,
+            opt.brightness,
+            opt.software_brightness,
+            opt.transition,
+        )?,
+        NcliCommand::Common(CliCommand::Animation(args)) => {
             let color = args.color.unwrap_or(rgb::RGB8::new(255, 255, 255).into());
 
             log::info!(
@@ -133,9 +308,170 @@ fn main() -> Result<()> {
                 color
             );
 
+            let params = AnimationParams {
+                mode: args.mode,
+                brightness: opt.brightness,
+                speed: args.speed,
+                color,
+                rainbow: args.rainbow,
+            };
+            match opt.transition {
+                Some(duration) => {
+                    keyboard
+                        .transition(LightingState::Animation(params.clone()), duration)
+                        .context("Failed to set led animation")?;
+                }
+                None => keyboard
+                    .set_led_animation(
+                        params.mode.clone(),
+                        params.brightness.clone(),
+                        params.speed.clone(),
+                        params.color.clone(),
+                        params.rainbow,
+                    )
+                    .context("Failed to set led animation")?,
+            }
+            let saved = SavedLighting::from(Scene::from(LightingState::Animation(params)));
+            state::save(&keyboard.device_id()?, &saved)?;
+        }
+        NcliCommand::Common(CliCommand::Script(args)) => {
+            let (name, source) = effects::load_script(&args.name)?;
+            let script = effect(name, RpcEffectSource::Script(source), &args.playback);
+            keyboard
+                .run_effect(&script)
+                .context("Failed to run script")?;
+            let saved = SavedLighting {
+                effect: Some(script),
+                ..Default::default()
+            };
+            state::save(&keyboard.device_id()?, &saved)?;
+        }
+        NcliCommand::Common(CliCommand::AnimationFile(args)) => {
+            let (name, animation) = effects::load_animation(&args.file_path)?;
+            let animation = effect(name, RpcEffectSource::Keyframes(animation), &args.playback);
             keyboard
-                .set_led_animation(args.mode, opt.brightness, args.speed, color, args.rainbow)
-                .context("Failed to set led animation")?;
+                .run_effect(&animation)
+                .context("Failed to play animation")?;
+            let saved = SavedLighting {
+                effect: Some(animation),
+                ..Default::default()
+            };
+            state::save(&keyboard.device_id()?, &saved)?;
+        }
+        NcliCommand::Common(CliCommand::Scene(args)) => {
+            let scene = effects::load_scene(&args.file_path)?;
+            match opt.transition {
+                Some(duration) => keyboard.transition(scene.lighting_state(), duration)?,
+                None => keyboard.apply_scene(&scene)?,
+            }
+            state::save(&keyboard.device_id()?, &SavedLighting::from(scene))?;
+        }
+        NcliCommand::Common(CliCommand::Restore) => keyboard.restore()?,
+        NcliCommand::Common(CliCommand::Undo) => keyboard.undo()?,
+        NcliCommand::Common(CliCommand::Redo) => keyboard.redo()?,
+        NcliCommand::Common(CliCommand::History) => print!("{}", keyboard.get_history()?),
+        NcliCommand::Common(CliCommand::Profile(ProfileCommand::Save(args))) => {
+            let path = match args.file {
+                Some(file) => profiles::import(&args.name, &file)?,
+                None => match keyboard.get_scene()? {
+                    Scene {
+                        mode: LightingMode::Custom,
+                        keys: Some(keys),
+                        ..
+                    } => profiles::save(&args.name, &keys)?,
+                    _ => {
+                        return Err(anyhow!(
+                            "Current lighting is a hardware animation, use save-scene"
+                        ))
+                    }
+                },
+            };
+            println!("Saved profile {} to {path:?}", args.name);
+        }
+        NcliCommand::Common(CliCommand::Profile(ProfileCommand::Load(args))) => {
+            let args = ColorProfileFileOptions {
+                keep_existing: args.keep_existing,
+                file_path: profiles::path(&args.name)?,
+                playback: args.playback,
+            };
+            set_color_profile(
+                &mut keyboard,
+                &args,
+                opt.brightness,
+                opt.software_brightness,
+                opt.transition,
+            )?;
+        }
+        NcliCommand::Common(CliCommand::Profile(ProfileCommand::List)) => {
+            for name in profiles::list()? {
+                println!("{name}");
+            }
+        }
+        NcliCommand::Common(CliCommand::Profile(ProfileCommand::Delete(args))) => {
+            profiles::delete(&args.name)?
+        }
+        NcliCommand::Common(CliCommand::Profile(ProfileCommand::Show(args))) => {
+            print!("{}", profiles::show(&args.name)?)
+        }
+        NcliCommand::SaveScene(args) => {
+            let scene = keyboard.get_scene()?;
+            let content = write_scene(&scene, FileFormat::from_path(&args.file_path))?;
+            std::fs::write(&args.file_path, content)
+                .context(format!("writing scene {:?}", args.file_path))?;
+        }
+        NcliCommand::Layer(LayerCommand::Set(args)) => {
+            let profile = Profile::load(&args.file_path)
+                .context(format!("color profile {:?}", args.file_path))?;
+            let colors_from_file = profile
+                .profile_keys()
+                .context("reading colors from color file")?;
+
+            // Only keys listed in the file are visible
+            let opacity = (args.opacity as u32 * 255 / 100) as u8;
+            let mut alpha = vec![0; TOTAL_KEYS];
+            for key in &colors_from_file {
+                if let Some(a) = alpha.get_mut(key.key_index) {
+                    *a = opacity;
+                }
+            }
+            let keys =
+                CustomKeyLeds::try_from(colors_from_file).context("assembling custom key leds")?;
+
+            let mut layer = Layer::new(&args.name, args.priority, args.blend, keys);
+            layer.alpha = Some(alpha);
+            keyboard.set_layer(layer)?;
+        }
+        NcliCommand::Layer(LayerCommand::Remove { name }) => {
+            keyboard.remove_layer(&name)?;
+        }
+        NcliCommand::Notify(args) => {
+            let duration = args.duration.unwrap_or(match args.pulses {
+                0 => Duration::from_secs(3),
+                pulses => Duration::from_secs(pulses as u64),
+            });
+            keyboard.notify(Notification {
+                keys: args.keys,
+                color: args.color,
+                pulses: args.pulses,
+                duration,
+            })?;
+        }
+        NcliCommand::Status => {
+            println!("{}", keyboard.schedule_status()?);
+        }
+        NcliCommand::Heatmap(HeatmapCommand::Show(args)) => {
+            keyboard.show_heatmap(args.scale, args.gradient, args.color_space)?;
+        }
+        NcliCommand::Heatmap(HeatmapCommand::Export(args)) => {
+            let counts = keyboard.get_heatmap()?;
+            let data = match args.format {
+                ExportFormat::Json => serde_json::to_string_pretty(&counts)? + "\n",
+                ExportFormat::Csv => counts.to_csv(),
+            };
+            match args.output {
+                Some(path) => std::fs::write(&path, data).context(format!("writing {:?}", path))?,
+                None => print!("{}", data),
+            }
         }
     }
 
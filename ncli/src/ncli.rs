@@ -1,10 +1,14 @@
-use cherryrgb::{self, Brightness};
-use clap::Parser;
-use std::path::PathBuf;
+use cherryrgb::{self, color::ColorSpace, layout, BlendMode, Brightness, HeatmapScale, OwnRGB8};
+use clap::{Parser, Subcommand, ValueEnum};
+use std::{path::PathBuf, time::Duration};
 
 #[path = "../../src/commonargs.rs"]
 mod commonargs;
-pub use commonargs::{AnimationArgs, CliCommand, ColorProfileFileOptions, CustomColorOptions};
+pub use commonargs::{
+    parse_duration, AnimationArgs, AnimationFileOptions, CliCommand, ColorProfileFileOptions,
+    CustomColorOptions, PlaybackOptions, ProfileCommand, ProfileLoadOptions, ProfileNameOptions,
+    ProfileSaveOptions, SceneOptions, ScriptOptions,
+};
 
 #[derive(Parser, Debug)]
 #[command(name = "cherryrgb_ncli", author, version, about = "Client for service-based Cherry RGB Keyboard", long_about = None)]
@@ -19,9 +23,144 @@ pub struct Opt {
 
     // Subcommand
     #[command(subcommand)]
-    pub command: CliCommand,
+    pub command: NcliCommand,
 
     /// Set brightness
     #[arg(short, long, default_value_t = Brightness::Full, value_enum)]
     pub brightness: Brightness,
+
+    /// Scale custom colors (percent), for finer steps than --brightness
+    #[arg(long, default_value_t = 100, value_parser = clap::value_parser!(u8).range(0..=100))]
+    pub software_brightness: u8,
+
+    /// Fade smoothly into the new colors or animation, e.g. 500ms
+    #[arg(short, long, value_parser = parse_duration)]
+    pub transition: Option<Duration>,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum NcliCommand {
+    #[command(flatten)]
+    Common(CliCommand),
+
+    /// Manage color layers, which are drawn on top of the current lighting
+    #[command(subcommand)]
+    Layer(LayerCommand),
+
+    /// Flash or pulse keys for a while, then restore the current lighting
+    Notify(NotifyOptions),
+
+    /// Show the active and the next profile of the service's schedule (see --schedule)
+    Status,
+
+    /// Show or export the key press counts recorded by the service (see --heatmap-file)
+    #[command(subcommand)]
+    Heatmap(HeatmapCommand),
+
+    /// Save the current lighting of the service as scene file
+    SaveScene(SaveSceneOptions),
+}
+
+#[derive(Parser, Debug)]
+pub struct SaveSceneOptions {
+    /// Scene file to write, the format (json, toml or yaml) is determined by the extension
+    pub file_path: PathBuf,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum HeatmapCommand {
+    /// Show key press counts as colors on the keyboard
+    Show(HeatmapOptions),
+
+    /// Print or write key press counts
+    Export(HeatmapExportOptions),
+}
+
+#[derive(Parser, Debug)]
+pub struct HeatmapOptions {
+    /// How press counts are mapped onto the gradient
+    #[arg(long, value_enum, default_value_t = HeatmapScale::Log)]
+    pub scale: HeatmapScale,
+
+    /// Comma separated colors from least to most used (e.g. 0000ff,ff0000), defaults to blue, green, yellow, red
+    #[arg(short, long, value_delimiter = ',')]
+    pub gradient: Vec<OwnRGB8>,
+
+    /// Color space the gradient is interpolated in
+    #[arg(long, value_enum, default_value_t = ColorSpace::Rgb)]
+    pub color_space: ColorSpace,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum ExportFormat {
+    Json,
+    Csv,
+}
+
+#[derive(Parser, Debug)]
+pub struct HeatmapExportOptions {
+    /// Output format
+    #[arg(short, long, value_enum, default_value_t = ExportFormat::Json)]
+    pub format: ExportFormat,
+
+    /// Output file, stdout if omitted
+    #[arg(short, long)]
+    pub output: Option<PathBuf>,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum LayerCommand {
+    /// Add a layer from a color profile file or replace the one with the same name
+    Set(LayerOptions),
+
+    /// Remove a layer
+    Remove {
+        /// Name of the layer
+        name: String,
+    },
+}
+
+#[derive(Parser, Debug)]
+pub struct LayerOptions {
+    /// Name of the layer
+    pub name: String,
+
+    /// A json encoded file, specifying key colors. Keys not listed are transparent
+    pub file_path: PathBuf,
+
+    /// Layers with higher priority are drawn on top
+    #[arg(short, long, default_value_t = 0)]
+    pub priority: i32,
+
+    /// How colors are combined with the layers below
+    #[arg(long, value_enum, default_value_t = BlendMode::Replace)]
+    pub blend: BlendMode,
+
+    /// Opacity in percent
+    #[arg(short, long, default_value_t = 100, value_parser = clap::value_parser!(u8).range(0..=100))]
+    pub opacity: u8,
+}
+
+#[derive(Parser, Debug)]
+pub struct NotifyOptions {
+    /// Comma separated key names or LED indexes (e.g. esc,f1,42), the whole board if omitted
+    #[arg(short, long, value_delimiter = ',', value_parser = parse_key)]
+    pub keys: Vec<usize>,
+
+    /// Color (e.g ff0000)
+    #[arg(short, long)]
+    pub color: OwnRGB8,
+
+    /// Number of pulses, the keys are lit constantly if 0
+    #[arg(short, long, default_value_t = 0)]
+    pub pulses: u32,
+
+    /// Total duration (e.g. 3s), defaults to 1s per pulse or 3s without pulses
+    #[arg(long, value_parser = parse_duration)]
+    pub duration: Option<Duration>,
+}
+
+/// Parse key name or LED index
+pub fn parse_key(arg: &str) -> Result<usize, String> {
+    layout::key_index(arg).ok_or_else(|| format!("Unknown key '{arg}'"))
 }
@@ -0,0 +1,15 @@
+use cherryrgb::schedule::{Clock, WeekTime, Weekday};
+use chrono::{Datelike, Local, Timelike};
+
+/// Local time of the system
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> WeekTime {
+        let now = Local::now();
+        WeekTime {
+            weekday: Weekday::from_days_since_monday(now.weekday().num_days_from_monday()),
+            minute: now.hour() * 60 + now.minute(),
+        }
+    }
+}
@@ -0,0 +1,116 @@
+use cherryrgb::{
+    CherryRgbError, CustomKeyLeds, Effect, KeyframePlayer, RpcEffect, RpcEffectSource, ScriptEffect,
+};
+use std::{
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+/// A software effect, driven by the driver loop
+pub struct EffectRunner {
+    effect: Box<dyn Effect>,
+    /// Effect as sent by the client, to save it
+    args: RpcEffect,
+    interval: Duration,
+    started: Instant,
+    until: Option<Instant>,
+    next_frame: Instant,
+    finished: bool,
+}
+
+impl EffectRunner {
+    /// Prepare the effect sent by a client
+    pub fn new(args: RpcEffect) -> Result<Self, CherryRgbError> {
+        let effect: Box<dyn Effect> = match &args.source {
+            RpcEffectSource::Script(source) => Box::new(ScriptEffect::new(&args.name, source)?),
+            RpcEffectSource::Keyframes(animation) => {
+                Box::new(KeyframePlayer::new(&args.name, animation)?)
+            }
+        };
+        let now = Instant::now();
+        Ok(Self {
+            effect,
+            interval: Duration::from_secs(1) / args.fps.max(1),
+            started: now,
+            until: args.duration.map(|d| now + d),
+            next_frame: now,
+            finished: false,
+            args,
+        })
+    }
+
+    /// Feed key report and render the next frame, if it is due
+    pub fn tick(&mut self, report: Option<&[u8]>) -> Option<CustomKeyLeds> {
+        if let Some(report) = report {
+            self.effect.key_report(report);
+        }
+        let now = Instant::now();
+        if matches!(self.until, Some(until) if now >= until) {
+            log::info!("Effect {} finished", self.effect.name());
+            self.finished = true;
+            return None;
+        }
+        if now < self.next_frame {
+            return None;
+        }
+        self.next_frame = now + self.interval;
+
+        match self.effect.render(now - self.started) {
+            Ok(Some(leds)) => Some(leds),
+            Ok(None) => {
+                log::info!("Effect {} finished", self.effect.name());
+                self.finished = true;
+                None
+            }
+            Err(err) => {
+                log::error!("Stopping effect, err={}", err);
+                self.finished = true;
+                None
+            }
+        }
+    }
+
+    pub fn args(&self) -> &RpcEffect {
+        &self.args
+    }
+
+    /// True, if the effect has finished or failed
+    pub fn is_finished(&self) -> bool {
+        self.finished
+    }
+}
+
+/// Running effect, shared by the client handlers and the driver loop.
+/// The driver loop renders frames without holding the keyboard, as scripts may take a while.
+#[derive(Clone)]
+pub struct SharedEffect {
+    args: RpcEffect,
+    runner: Arc<Mutex<EffectRunner>>,
+}
+
+impl SharedEffect {
+    pub fn new(runner: EffectRunner) -> Self {
+        Self {
+            args: runner.args().clone(),
+            runner: Arc::new(Mutex::new(runner)),
+        }
+    }
+
+    /// See `EffectRunner::tick`
+    pub fn tick(&self, report: Option<&[u8]>) -> Option<CustomKeyLeds> {
+        self.runner.lock().unwrap().tick(report)
+    }
+
+    pub fn args(&self) -> &RpcEffect {
+        &self.args
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.runner.lock().unwrap().is_finished()
+    }
+
+    /// True, if both refer to the same running effect
+    pub fn is(&self, other: &SharedEffect) -> bool {
+        Arc::ptr_eq(&self.runner, &other.runner)
+    }
+}
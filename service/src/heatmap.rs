@@ -0,0 +1,62 @@
+use cherryrgb::KeyPressCounts;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+/// Records key presses and saves the counts periodically
+pub struct HeatmapRecorder {
+    path: PathBuf,
+    interval: Duration,
+    counts: KeyPressCounts,
+    last_saved: Instant,
+    dirty: bool,
+}
+
+impl HeatmapRecorder {
+    /// Continue with the counts saved at `path`, if any
+    pub fn new(path: &Path, interval: Duration) -> Self {
+        let counts = match std::fs::read_to_string(path) {
+            Ok(json) => serde_json::from_str(&json).unwrap_or_else(|err| {
+                log::warn!("Ignoring invalid heatmap {:?}, err={}", path, err);
+                KeyPressCounts::new()
+            }),
+            Err(_) => KeyPressCounts::new(),
+        };
+        Self {
+            path: path.to_owned(),
+            interval,
+            counts,
+            last_saved: Instant::now(),
+            dirty: false,
+        }
+    }
+
+    pub fn counts(&self) -> &KeyPressCounts {
+        &self.counts
+    }
+
+    /// Count the key presses of a forwarded report, save if due
+    pub fn record(&mut self, report: &[u8]) {
+        let total = self.counts.total();
+        self.counts.record(report);
+        self.dirty |= self.counts.total() != total;
+        if self.dirty && self.last_saved.elapsed() >= self.interval {
+            self.save();
+        }
+    }
+
+    /// Save counts, if they have changed since they were last saved
+    pub fn save(&mut self) {
+        if !self.dirty {
+            return;
+        }
+        // Write to a temporary file first, so a crash can't leave a truncated file behind
+        let tmp = self.path.with_extension("tmp");
+        let json = serde_json::to_string(&self.counts).unwrap();
+        match std::fs::write(&tmp, json).and_then(|_| std::fs::rename(&tmp, &self.path)) {
+            Ok(_) => log::debug!("Saved heatmap to {:?}", self.path),
+            Err(err) => log::error!("Failed to save heatmap to {:?}, err={}", self.path, err),
+        }
+        self.last_saved = Instant::now();
+        self.dirty = false;
+    }
+}
@@ -0,0 +1,539 @@
+use crate::effect::{EffectRunner, SharedEffect};
+use crate::lockfile;
+use cherryrgb::{
+    layout::{self, LockState},
+    nightlight::{self, NightLight, DAYLIGHT_TEMPERATURE},
+    BlendMode, Brightness, CherryKeyboard, CherryRgbError, Compositor, CustomKeyLeds, History,
+    Layer, LightingState, Notification, OwnRGB8, SavedLighting, Transition, TransitionStep,
+    TOTAL_KEYS,
+};
+use std::collections::VecDeque;
+use std::mem::discriminant;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+/// Priority of notification layers, above regular layers
+const NOTIFICATION_PRIORITY: i32 = 1000;
+/// Priority of the lock indicator layer, above regular layers
+const LOCK_INDICATOR_PRIORITY: i32 = 500;
+const LOCK_INDICATOR_LAYER: &str = "lock-indicators";
+/// Opacity of the tint, if the whole board indicates a lock
+const LOCK_BOARD_ALPHA: u8 = 64;
+
+/// Colors indicating active locks, see `Opt`
+#[derive(Clone, Debug, Default)]
+pub struct LockIndicators {
+    pub caps_lock: Option<OwnRGB8>,
+    pub num_lock: Option<OwnRGB8>,
+    pub scroll_lock: Option<OwnRGB8>,
+    /// Tint the whole board instead of the lock keys only
+    pub board: bool,
+}
+
+impl LockIndicators {
+    /// Layer showing the active locks, `None` if nothing is to be shown
+    fn layer(&self, state: LockState) -> Option<Layer> {
+        let active: Vec<(&str, OwnRGB8)> = [
+            ("capslock", state.caps_lock, &self.caps_lock),
+            ("numlock", state.num_lock, &self.num_lock),
+            ("scrolllock", state.scroll_lock, &self.scroll_lock),
+        ]
+        .iter()
+        .filter_map(|(key, active, color)| match color {
+            Some(color) if *active => Some((*key, color.clone())),
+            _ => None,
+        })
+        .collect();
+        if active.is_empty() {
+            return None;
+        }
+
+        let mut leds = CustomKeyLeds::new();
+        let mut alpha = vec![0; TOTAL_KEYS];
+        if self.board {
+            // The first active lock wins
+            let (_, color) = &active[0];
+            leds = CustomKeyLeds::from_leds(vec![color.clone(); TOTAL_KEYS]).ok()?;
+            alpha = vec![LOCK_BOARD_ALPHA; TOTAL_KEYS];
+        } else {
+            for (key, color) in active {
+                if let Some(index) = layout::key_index(key) {
+                    leds.set_led(index, color).ok()?;
+                    alpha[index] = 255;
+                }
+            }
+        }
+        let mut layer = Layer::new(
+            LOCK_INDICATOR_LAYER,
+            LOCK_INDICATOR_PRIORITY,
+            BlendMode::Replace,
+            leds,
+        );
+        layer.alpha = Some(alpha);
+        Some(layer)
+    }
+}
+
+/// A transition, driven by the driver loop
+struct TransitionRunner {
+    started: Instant,
+    steps: VecDeque<(Duration, TransitionStep)>,
+}
+
+/// Take the same lock on the state file as cherryrgb_cli, see lockfile.rs
+fn lock_state(path: &Path, exclusive: bool) -> Result<std::fs::File, CherryRgbError> {
+    lockfile::lock(path, exclusive)
+        .map_err(|err| CherryRgbError::ParseError(format!("locking {:?}, err={err}", path)))
+}
+
+/// Read saved lighting while holding the state file lock
+pub fn load_state(path: &Path) -> Result<SavedLighting, CherryRgbError> {
+    let _lock = lock_state(path, false)?;
+    SavedLighting::load(path)
+}
+
+/// Lighting controlled by the service: a base state (profile, animation or
+/// software effect) with layers composited on top of it.
+///
+/// Hardware animations can't be composited, they are suspended while layers are shown.
+#[derive(Default)]
+pub struct Lighting {
+    /// Base state as last applied to the keyboard
+    base: Option<LightingState>,
+    /// Base state as chosen by the client, i.e. custom colors before scaling them
+    saved: SavedLighting,
+    /// Last step sent towards the base state, it's resent whenever layers change
+    shown: Option<TransitionStep>,
+    layers: Compositor,
+    /// Running notifications by layer name
+    notifications: Vec<(String, Instant, Notification)>,
+    notification_count: u64,
+    /// Keyboard is in custom mode, so frames can be streamed
+    custom_mode: bool,
+    effect: Option<SharedEffect>,
+    transition: Option<TransitionRunner>,
+    night_light: Option<NightLight>,
+    /// Color temperature all colors are shifted to, `None` during the day
+    temperature: Option<u32>,
+    /// Idle timeout and brightness while idle
+    idle_dimming: Option<(Duration, Brightness)>,
+    last_activity: Option<Instant>,
+    /// Brightness limit, while idle
+    idle: Option<Brightness>,
+    /// File the base state and effect are saved to, whenever they change
+    state_file: Option<PathBuf>,
+    history: History,
+    /// Saved lighting is being applied, so its steps are neither saved nor recorded
+    replaying: bool,
+}
+
+impl Lighting {
+    /// Stop running effects and transitions
+    pub fn stop(&mut self) {
+        self.effect = None;
+        self.transition = None;
+    }
+
+    /// Apply base lighting as chosen by the client, optionally fading from the current one
+    pub fn apply(
+        &mut self,
+        keyboard: &CherryKeyboard,
+        saved: SavedLighting,
+        transition: Option<Duration>,
+    ) -> Result<(), CherryRgbError> {
+        let target = saved.lighting_state().ok_or_else(|| {
+            CherryRgbError::InvalidArgument("Lighting without scene".into(), String::new())
+        })?;
+        self.stop();
+        let from = self.base.replace(target.clone());
+        self.saved = SavedLighting {
+            effect: None,
+            ..saved
+        };
+        self.save_state();
+        let transition =
+            Transition::new(from.as_ref(), &target, transition.unwrap_or(Duration::ZERO));
+        self.transition = Some(TransitionRunner {
+            started: Instant::now(),
+            steps: transition.steps().iter().cloned().collect(),
+        });
+        // Send the first steps right away, the rest is up to the driver loop
+        self.tick_transition(keyboard)
+    }
+
+    /// Base state as chosen by the client, without the running effect
+    pub fn saved(&self) -> &SavedLighting {
+        &self.saved
+    }
+
+    /// Reset custom key colors to default
+    pub fn reset(&mut self, keyboard: &CherryKeyboard) -> Result<(), CherryRgbError> {
+        self.stop();
+        self.base = Some(LightingState::CustomColors(CustomKeyLeds::new()));
+        self.saved = SavedLighting::custom_colors(CustomKeyLeds::new(), Brightness::Full, 100);
+        self.save_state();
+        keyboard.reset_custom_colors()?;
+        self.shown = Some(TransitionStep::CustomMode(CustomKeyLeds::new()));
+        self.custom_mode = true;
+        if self.layers.is_empty() {
+            return Ok(());
+        }
+        self.refresh(keyboard)
+    }
+
+    /// Start software effect, its frames replace the base state
+    pub fn run_effect(
+        &mut self,
+        keyboard: &CherryKeyboard,
+        runner: EffectRunner,
+    ) -> Result<(), CherryRgbError> {
+        self.stop();
+        let black = CustomKeyLeds::new();
+        self.base = Some(LightingState::CustomColors(black.clone()));
+        self.saved = SavedLighting::custom_colors(black.clone(), Brightness::Full, 100);
+        self.send(keyboard, &TransitionStep::CustomMode(black))?;
+        self.effect = Some(SharedEffect::new(runner));
+        self.save_state();
+        Ok(())
+    }
+
+    /// Save the base state and effect to `path` from now on
+    pub fn set_state_file(&mut self, path: PathBuf) {
+        self.state_file = Some(path);
+    }
+
+    pub fn state_file(&self) -> Option<&Path> {
+        self.state_file.as_deref()
+    }
+
+    /// Recently applied lighting, see `undo` and `redo`
+    pub fn history(&self) -> &History {
+        &self.history
+    }
+
+    /// Record the base state and effect in the history and save them
+    fn save_state(&mut self) {
+        if self.replaying {
+            return;
+        }
+        let saved = SavedLighting {
+            effect: self.effect.as_ref().map(|effect| effect.args().clone()),
+            ..self.saved.clone()
+        };
+        self.write_state(&saved);
+        self.history.push(saved);
+    }
+
+    fn write_state(&self, saved: &SavedLighting) {
+        if let Some(path) = &self.state_file {
+            let result = lock_state(path, true).and_then(|_lock| saved.save(path));
+            if let Err(err) = result {
+                log::error!("Failed to save lighting, err={}", err);
+            }
+        }
+    }
+
+    /// Apply saved base state and effect, without recording the steps
+    fn replay(
+        &mut self,
+        keyboard: &CherryKeyboard,
+        saved: &SavedLighting,
+    ) -> Result<(), CherryRgbError> {
+        self.replaying = true;
+        let result = self.apply_saved(keyboard, saved);
+        self.replaying = false;
+        self.write_state(saved);
+        result
+    }
+
+    fn apply_saved(
+        &mut self,
+        keyboard: &CherryKeyboard,
+        saved: &SavedLighting,
+    ) -> Result<(), CherryRgbError> {
+        if saved.scene.is_some() {
+            self.apply(keyboard, saved.clone(), None)?;
+        }
+        if let Some(effect) = &saved.effect {
+            self.run_effect(keyboard, EffectRunner::new(effect.clone())?)?;
+        }
+        Ok(())
+    }
+
+    /// Apply saved base state and effect
+    pub fn restore(
+        &mut self,
+        keyboard: &CherryKeyboard,
+        saved: SavedLighting,
+    ) -> Result<(), CherryRgbError> {
+        self.replay(keyboard, &saved)?;
+        self.history.push(saved);
+        Ok(())
+    }
+
+    /// Go back to the lighting applied before, false if there is nothing to undo
+    pub fn undo(&mut self, keyboard: &CherryKeyboard) -> Result<bool, CherryRgbError> {
+        match self.history.undo().cloned() {
+            Some(saved) => self.replay(keyboard, &saved).map(|_| true),
+            None => Ok(false),
+        }
+    }
+
+    /// Apply the lighting undone last again, false if there is nothing to redo
+    pub fn redo(&mut self, keyboard: &CherryKeyboard) -> Result<bool, CherryRgbError> {
+        match self.history.redo().cloned() {
+            Some(saved) => self.replay(keyboard, &saved).map(|_| true),
+            None => Ok(false),
+        }
+    }
+
+    /// Add or replace layer
+    pub fn set_layer(
+        &mut self,
+        keyboard: &CherryKeyboard,
+        layer: Layer,
+    ) -> Result<(), CherryRgbError> {
+        log::debug!("Setting layer {}", layer.name);
+        self.layers.set_layer(layer);
+        self.refresh(keyboard)
+    }
+
+    /// Remove layer, restores the base state once all layers are gone
+    pub fn remove_layer(
+        &mut self,
+        keyboard: &CherryKeyboard,
+        name: &str,
+    ) -> Result<(), CherryRgbError> {
+        if !self.layers.remove_layer(name) {
+            log::warn!("No such layer: {}", name);
+            return Ok(());
+        }
+        self.refresh(keyboard)
+    }
+
+    /// Show or hide the lock indicators
+    pub fn set_lock_state(
+        &mut self,
+        keyboard: &CherryKeyboard,
+        state: LockState,
+        indicators: &LockIndicators,
+    ) -> Result<(), CherryRgbError> {
+        log::debug!("Lock state: {:?}", state);
+        match indicators.layer(state) {
+            Some(layer) => self.set_layer(keyboard, layer),
+            None => self.remove_layer(keyboard, LOCK_INDICATOR_LAYER),
+        }
+    }
+
+    /// Enable night light, takes effect with the next tick
+    pub fn set_night_light(&mut self, night_light: NightLight) {
+        self.night_light = Some(night_light);
+    }
+
+    /// Dim to `brightness`, if no key has been pressed for `timeout`
+    pub fn set_idle_dimming(&mut self, timeout: Duration, brightness: Brightness) {
+        self.idle_dimming = Some((timeout, brightness));
+    }
+
+    /// Show notification on top of all layers, it's removed automatically
+    pub fn notify(
+        &mut self,
+        keyboard: &CherryKeyboard,
+        notification: Notification,
+    ) -> Result<(), CherryRgbError> {
+        self.notification_count += 1;
+        let name = format!("notification-{}", self.notification_count);
+        log::debug!("Showing {}", name);
+        self.notifications
+            .push((name, Instant::now(), notification));
+        self.tick_notifications(keyboard)
+    }
+
+    /// Running software effect, the driver loop renders its frames, see `tick`
+    pub fn effect(&self) -> Option<SharedEffect> {
+        self.effect.clone()
+    }
+
+    /// Called by the driver loop, sends whatever is due.
+    /// `rendered` is the effect frame, rendered by the driver loop beforehand.
+    pub fn tick(
+        &mut self,
+        keyboard: &CherryKeyboard,
+        report: Option<&[u8]>,
+        rendered: Option<(SharedEffect, Option<CustomKeyLeds>)>,
+    ) {
+        if let Err(err) = self.tick_transition(keyboard) {
+            log::error!("Stopping transition, err={}", err);
+            self.transition = None;
+        }
+        if let Err(err) = self.tick_notifications(keyboard) {
+            log::error!("Failed to send notification, err={}", err);
+        }
+        if let Err(err) = self.tick_night_light(keyboard) {
+            log::error!("Failed to update night light, err={}", err);
+        }
+        if let Err(err) = self.tick_idle(keyboard, report) {
+            log::error!("Failed to dim, err={}", err);
+        }
+
+        let (effect, frame) = match rendered {
+            Some(rendered) => rendered,
+            None => return,
+        };
+        // The effect may have been stopped or replaced while the frame was rendered
+        if !matches!(&self.effect, Some(current) if current.is(&effect)) {
+            return;
+        }
+        if let Some(leds) = frame {
+            self.base = Some(LightingState::CustomColors(leds.clone()));
+            if let Err(err) = self.send(keyboard, &TransitionStep::CustomColors(leds)) {
+                log::error!("Failed to send effect frame, err={}", err);
+            }
+        }
+        if effect.is_finished() {
+            self.effect = None;
+        }
+    }
+
+    /// Send all due transition steps. Steps superseded by a later due step
+    /// of the same kind are skipped, in case the driver loop can't keep up.
+    fn tick_transition(&mut self, keyboard: &CherryKeyboard) -> Result<(), CherryRgbError> {
+        let mut runner = match self.transition.take() {
+            Some(runner) => runner,
+            None => return Ok(()),
+        };
+        let elapsed = runner.started.elapsed();
+        while matches!(runner.steps.front(), Some((at, _)) if *at <= elapsed) {
+            let (_, step) = runner.steps.pop_front().unwrap();
+            let superseded = matches!(runner.steps.front(), Some((at, next))
+                if *at <= elapsed && discriminant(next) == discriminant(&step));
+            if !superseded {
+                self.send(keyboard, &step)?;
+            }
+        }
+        if !runner.steps.is_empty() {
+            self.transition = Some(runner);
+        }
+        Ok(())
+    }
+
+    /// Update the layers of running notifications and remove finished ones
+    fn tick_notifications(&mut self, keyboard: &CherryKeyboard) -> Result<(), CherryRgbError> {
+        if self.notifications.is_empty() {
+            return Ok(());
+        }
+        let now = Instant::now();
+        for (name, started, notification) in &self.notifications {
+            match notification.alpha_at(now - *started) {
+                Some(alpha) => {
+                    let layer = notification.layer(name, NOTIFICATION_PRIORITY, alpha);
+                    self.layers.set_layer(layer);
+                }
+                None => {
+                    log::debug!("{} finished", name);
+                    self.layers.remove_layer(name);
+                }
+            }
+        }
+        self.notifications
+            .retain(|(_, started, notification)| notification.alpha_at(now - *started).is_some());
+        self.refresh(keyboard)
+    }
+
+    /// Resend the current state, if the color temperature has changed
+    fn tick_night_light(&mut self, keyboard: &CherryKeyboard) -> Result<(), CherryRgbError> {
+        let night_light = match &self.night_light {
+            Some(night_light) => night_light,
+            None => return Ok(()),
+        };
+        let now = chrono::Local::now();
+        let temperature =
+            Some(night_light.temperature_at(now.timestamp(), now.offset().local_minus_utc()))
+                .filter(|&t| t < DAYLIGHT_TEMPERATURE);
+        if temperature == self.temperature {
+            return Ok(());
+        }
+        log::debug!("Color temperature: {:?}", temperature);
+        self.temperature = temperature;
+        self.refresh(keyboard)
+    }
+
+    /// Dim when idle, restore the previous brightness on the next key report
+    fn tick_idle(
+        &mut self,
+        keyboard: &CherryKeyboard,
+        report: Option<&[u8]>,
+    ) -> Result<(), CherryRgbError> {
+        let (timeout, brightness) = match &self.idle_dimming {
+            Some((timeout, brightness)) => (*timeout, brightness.clone()),
+            None => return Ok(()),
+        };
+        let now = Instant::now();
+        if report.is_some() {
+            self.last_activity = Some(now);
+            if self.idle.take().is_some() {
+                log::debug!("Key pressed, restoring brightness");
+                return self.refresh(keyboard);
+            }
+            return Ok(());
+        }
+        let last_activity = *self.last_activity.get_or_insert(now);
+        if self.idle.is_none() && now - last_activity >= timeout {
+            log::debug!("Idle, dimming to {:?}", brightness);
+            self.idle = Some(brightness);
+            return self.refresh(keyboard);
+        }
+        Ok(())
+    }
+
+    /// Post-processing of all colors sent to the keyboard
+    fn post_process(&self, step: TransitionStep) -> TransitionStep {
+        let step = match self.temperature {
+            Some(temperature) => step.map_colors(|color| nightlight::warm(color, temperature)),
+            None => step,
+        };
+        match &self.idle {
+            Some(brightness) => step.dim(brightness),
+            None => step,
+        }
+    }
+
+    /// Send base state (or a step towards it) with all layers on top
+    fn send(
+        &mut self,
+        keyboard: &CherryKeyboard,
+        step: &TransitionStep,
+    ) -> Result<(), CherryRgbError> {
+        self.shown = Some(step.clone());
+        if self.layers.is_empty() {
+            self.custom_mode = !matches!(step, TransitionStep::Animation(_));
+            return keyboard.apply_transition_step(&self.post_process(step.clone()));
+        }
+
+        let leds = match step {
+            TransitionStep::Animation(_) => self.layers.compose(&CustomKeyLeds::new()),
+            TransitionStep::CustomMode(leds) | TransitionStep::CustomColors(leds) => {
+                self.layers.compose(leds)
+            }
+        };
+        let step = if self.custom_mode {
+            TransitionStep::CustomColors(leds)
+        } else {
+            self.custom_mode = true;
+            TransitionStep::CustomMode(leds)
+        };
+        keyboard.apply_transition_step(&self.post_process(step))
+    }
+
+    /// Send the current state again, after layers have changed
+    fn refresh(&mut self, keyboard: &CherryKeyboard) -> Result<(), CherryRgbError> {
+        match self.shown.clone() {
+            Some(step) => self.send(keyboard, &step),
+            None if self.layers.is_empty() => Ok(()),
+            None => self.send(
+                keyboard,
+                &TransitionStep::CustomColors(CustomKeyLeds::new()),
+            ),
+        }
+    }
+}
@@ -1,5 +1,12 @@
 use anyhow::{anyhow, Context, Result};
-use cherryrgb::{self, CherryKeyboard, CustomKeyLeds, RpcAnimation, VirtKbd};
+use cherryrgb::{
+    self,
+    nightlight::{NightLight, NightLightSchedule},
+    schedule::{Schedule, Scheduler},
+    AnimationParams, Brightness, Calibration, CherryKeyboard, CherryRgbError, CustomKeyLeds, Layer,
+    LightingState, Notification, RpcAnimation, RpcEffect, RpcHeatmap, RpcLighting, RpcTransition,
+    SavedLighting, Scene, VirtKbd,
+};
 use clap::Parser;
 use file_mode::ModePath;
 use log::LevelFilter;
@@ -11,10 +18,20 @@ use std::sync::{Arc, Mutex};
 use std::{thread, time};
 use systemd_journal_logger::{connected_to_journal, JournalLog};
 
+mod clock;
+use clock::SystemClock;
+mod effect;
+use effect::EffectRunner;
+mod heatmap;
+use heatmap::HeatmapRecorder;
+mod lighting;
+use lighting::{load_state, Lighting, LockIndicators};
 mod service;
 use service::Opt;
 #[path = "../../src/common.rs"]
 mod common;
+#[path = "../../src/lockfile.rs"]
+mod lockfile;
 
 const VERSION: &str = env!("CARGO_PKG_VERSION");
 const NAME: &str = env!("CARGO_PKG_NAME");
@@ -27,6 +44,9 @@ fn handle_client(
     stream: UnixStream,
     keyboard: Arc<CherryKeyboard>,
     mutex: Arc<Mutex<u32>>,
+    lighting: Arc<Mutex<Lighting>>,
+    heatmap: Arc<Mutex<Option<HeatmapRecorder>>>,
+    scheduler: Arc<Mutex<Option<Scheduler>>>,
 ) -> Result<()> {
     let reader = stream;
     let writer = reader.try_clone()?;
@@ -58,7 +78,7 @@ fn handle_client(
                 */
                 if cmd == "reset_custom_colors" {
                     let _guard = mutex.lock().unwrap();
-                    match keyboard.reset_custom_colors() {
+                    match lighting.lock().unwrap().reset(&keyboard) {
                         Ok(res) => res,
                         Err(err) => {
                             let emsg = format!("Errror in reset_custom_colors: {:?}", err);
@@ -87,14 +107,16 @@ fn handle_client(
                         }
                     };
                     let color = args.color.unwrap_or(rgb::RGB8::new(255, 255, 255).into());
-                    let _guard = mutex.lock().unwrap();
-                    match keyboard.set_led_animation(
-                        args.mode,
-                        args.brightness,
-                        args.speed,
+                    let target = LightingState::Animation(AnimationParams {
+                        mode: args.mode,
+                        brightness: args.brightness,
+                        speed: args.speed,
                         color,
-                        args.rainbow,
-                    ) {
+                        rainbow: args.rainbow,
+                    });
+                    let target = SavedLighting::from(Scene::from(target));
+                    let _guard = mutex.lock().unwrap();
+                    match lighting.lock().unwrap().apply(&keyboard, target, None) {
                         Ok(res) => res,
                         Err(err) => {
                             let emsg = format!("Errror in set_led_animation: {:?}", err);
@@ -117,8 +139,9 @@ fn handle_client(
                             return Ok(());
                         }
                     };
+                    let target = SavedLighting::custom_colors(key_leds, Brightness::Full, 100);
                     let _guard = mutex.lock().unwrap();
-                    match keyboard.set_custom_colors(key_leds) {
+                    match lighting.lock().unwrap().apply(&keyboard, target, None) {
                         Ok(res) => res,
                         Err(err) => {
                             let emsg = format!("Errror in set_set_custom_colors: {:?}", err);
@@ -129,6 +152,303 @@ fn handle_client(
                     }
                     continue;
                 }
+                if let Some(stripped) = cmd.strip_prefix("set_lighting=") {
+                    let params = stripped;
+                    let args: RpcLighting = match serde_json::from_str(params) {
+                        Ok(res) => res,
+                        Err(err) => {
+                            log::error!("Unable to deserialize params for set_lighting {:?}", err);
+                            return Ok(());
+                        }
+                    };
+                    let result = match &args.lighting.scene {
+                        Some(scene) => scene.validate(),
+                        None => Err(CherryRgbError::InvalidArgument(
+                            "Lighting without scene".into(),
+                            String::new(),
+                        )),
+                    };
+                    if let Err(err) = result {
+                        let emsg = format!("Errror in set_lighting: {:?}", err);
+                        let _ = writer.write_all(emsg.as_bytes());
+                        log::error!("{}", emsg);
+                        return Ok(());
+                    }
+                    let _guard = mutex.lock().unwrap();
+                    match lighting
+                        .lock()
+                        .unwrap()
+                        .apply(&keyboard, args.lighting, args.transition)
+                    {
+                        Ok(res) => res,
+                        Err(err) => {
+                            let emsg = format!("Errror in set_lighting: {:?}", err);
+                            let _ = writer.write_all(emsg.as_bytes());
+                            log::error!("{}", emsg);
+                            return Ok(());
+                        }
+                    }
+                    continue;
+                }
+                if let Some(stripped) = cmd.strip_prefix("run_effect=") {
+                    let params = stripped;
+                    let args: RpcEffect = match serde_json::from_str(params) {
+                        Ok(res) => res,
+                        Err(err) => {
+                            log::error!("Unable to deserialize params for run_effect {:?}", err);
+                            return Ok(());
+                        }
+                    };
+                    let runner = match EffectRunner::new(args) {
+                        Ok(res) => res,
+                        Err(err) => {
+                            let emsg = format!("Errror in run_effect: {}", err);
+                            let _ = writer.write_all(format!("{}\n", emsg).as_bytes());
+                            log::error!("{}", emsg);
+                            return Ok(());
+                        }
+                    };
+                    let _guard = mutex.lock().unwrap();
+                    match lighting.lock().unwrap().run_effect(&keyboard, runner) {
+                        Ok(res) => res,
+                        Err(err) => {
+                            let emsg = format!("Errror in run_effect: {:?}", err);
+                            let _ = writer.write_all(emsg.as_bytes());
+                            log::error!("{}", emsg);
+                            return Ok(());
+                        }
+                    }
+                    continue;
+                }
+                if let Some(stripped) = cmd.strip_prefix("transition=") {
+                    let params = stripped;
+                    let args: RpcTransition = match serde_json::from_str(params) {
+                        Ok(res) => res,
+                        Err(err) => {
+                            log::error!("Unable to deserialize params for transition {:?}", err);
+                            return Ok(());
+                        }
+                    };
+                    let _guard = mutex.lock().unwrap();
+                    match lighting.lock().unwrap().apply(
+                        &keyboard,
+                        SavedLighting::from(Scene::from(args.target)),
+                        Some(args.duration),
+                    ) {
+                        Ok(res) => res,
+                        Err(err) => {
+                            let emsg = format!("Errror in transition: {:?}", err);
+                            let _ = writer.write_all(emsg.as_bytes());
+                            log::error!("{}", emsg);
+                            return Ok(());
+                        }
+                    }
+                    continue;
+                }
+                if let Some(stripped) = cmd.strip_prefix("set_layer=") {
+                    let params = stripped;
+                    let layer: Layer = match serde_json::from_str(params) {
+                        Ok(res) => res,
+                        Err(err) => {
+                            log::error!("Unable to deserialize params for set_layer {:?}", err);
+                            return Ok(());
+                        }
+                    };
+                    let _guard = mutex.lock().unwrap();
+                    match lighting.lock().unwrap().set_layer(&keyboard, layer) {
+                        Ok(res) => res,
+                        Err(err) => {
+                            let emsg = format!("Errror in set_layer: {:?}", err);
+                            let _ = writer.write_all(emsg.as_bytes());
+                            log::error!("{}", emsg);
+                            return Ok(());
+                        }
+                    }
+                    continue;
+                }
+                if let Some(name) = cmd.strip_prefix("remove_layer=") {
+                    let _guard = mutex.lock().unwrap();
+                    match lighting.lock().unwrap().remove_layer(&keyboard, name) {
+                        Ok(res) => res,
+                        Err(err) => {
+                            let emsg = format!("Errror in remove_layer: {:?}", err);
+                            let _ = writer.write_all(emsg.as_bytes());
+                            log::error!("{}", emsg);
+                            return Ok(());
+                        }
+                    }
+                    continue;
+                }
+                if let Some(stripped) = cmd.strip_prefix("notify=") {
+                    let params = stripped;
+                    let notification: Notification = match serde_json::from_str(params) {
+                        Ok(res) => res,
+                        Err(err) => {
+                            log::error!("Unable to deserialize params for notify {:?}", err);
+                            return Ok(());
+                        }
+                    };
+                    let _guard = mutex.lock().unwrap();
+                    match lighting.lock().unwrap().notify(&keyboard, notification) {
+                        Ok(res) => res,
+                        Err(err) => {
+                            let emsg = format!("Errror in notify: {:?}", err);
+                            let _ = writer.write_all(emsg.as_bytes());
+                            log::error!("{}", emsg);
+                            return Ok(());
+                        }
+                    }
+                    continue;
+                }
+                if let Some(stripped) = cmd.strip_prefix("apply_scene=") {
+                    let params = stripped;
+                    let scene: Scene = match serde_json::from_str(params) {
+                        Ok(res) => res,
+                        Err(err) => {
+                            log::error!("Unable to deserialize params for apply_scene {:?}", err);
+                            return Ok(());
+                        }
+                    };
+                    if let Err(err) = scene.validate() {
+                        let emsg = format!("Errror in apply_scene: {:?}", err);
+                        let _ = writer.write_all(emsg.as_bytes());
+                        log::error!("{}", emsg);
+                        return Ok(());
+                    }
+                    let _guard = mutex.lock().unwrap();
+                    match lighting.lock().unwrap().apply(
+                        &keyboard,
+                        SavedLighting::from(scene),
+                        None,
+                    ) {
+                        Ok(res) => res,
+                        Err(err) => {
+                            let emsg = format!("Errror in apply_scene: {:?}", err);
+                            let _ = writer.write_all(emsg.as_bytes());
+                            log::error!("{}", emsg);
+                            return Ok(());
+                        }
+                    }
+                    continue;
+                }
+                if cmd == "restore" {
+                    let _guard = mutex.lock().unwrap();
+                    let mut lighting = lighting.lock().unwrap();
+                    match lighting.state_file().map(load_state) {
+                        Some(Ok(saved)) => {
+                            if let Err(err) = lighting.restore(&keyboard, saved) {
+                                let emsg = format!("Errror in restore: {:?}", err);
+                                let _ = writer.write_all(emsg.as_bytes());
+                                log::error!("{}", emsg);
+                                return Ok(());
+                            }
+                        }
+                        Some(Err(err)) => log::error!("No saved lighting to restore, err={}", err),
+                        None => log::error!("No saved lighting to restore"),
+                    }
+                    continue;
+                }
+                if cmd == "undo" || cmd == "redo" {
+                    let _guard = mutex.lock().unwrap();
+                    let mut lighting = lighting.lock().unwrap();
+                    let result = match cmd.as_str() {
+                        "undo" => lighting.undo(&keyboard),
+                        _ => lighting.redo(&keyboard),
+                    };
+                    match result {
+                        Ok(true) => {}
+                        Ok(false) => log::info!("Nothing to {}", cmd),
+                        Err(err) => {
+                            let emsg = format!("Errror in {}: {:?}", cmd, err);
+                            let _ = writer.write_all(emsg.as_bytes());
+                            log::error!("{}", emsg);
+                            return Ok(());
+                        }
+                    }
+                    continue;
+                }
+                if cmd == "get_history" {
+                    let json = serde_json::to_string(lighting.lock().unwrap().history()).unwrap();
+                    writer.write_all(format!("{}\n", json).as_bytes())?;
+                    writer.flush()?;
+                    continue;
+                }
+                if cmd == "get_custom_colors" {
+                    // As given by the client, so they aren't scaled twice when modified
+                    let keys = lighting.lock().unwrap().saved().keys();
+                    let json = serde_json::to_string(&keys).unwrap();
+                    writer.write_all(format!("{}\n", json).as_bytes())?;
+                    writer.flush()?;
+                    continue;
+                }
+                if cmd == "get_device_id" {
+                    let json = serde_json::to_string(keyboard.device_id()).unwrap();
+                    writer.write_all(format!("{}\n", json).as_bytes())?;
+                    writer.flush()?;
+                    continue;
+                }
+                if cmd == "get_scene" {
+                    let json = match lighting.lock().unwrap().saved().applied_scene() {
+                        Some(scene) => serde_json::to_string(&scene).unwrap(),
+                        None => "No lighting has been set yet".to_string(),
+                    };
+                    writer.write_all(format!("{}\n", json).as_bytes())?;
+                    writer.flush()?;
+                    continue;
+                }
+                if cmd == "get_heatmap" {
+                    let json = match heatmap.lock().unwrap().as_ref() {
+                        Some(recorder) => serde_json::to_string(recorder.counts()).unwrap(),
+                        None => "Heatmap recording is disabled, see --heatmap-file".to_string(),
+                    };
+                    writer.write_all(format!("{}\n", json).as_bytes())?;
+                    writer.flush()?;
+                    continue;
+                }
+                if cmd == "schedule_status" {
+                    let json = match scheduler.lock().unwrap().as_ref() {
+                        Some(scheduler) => serde_json::to_string(&scheduler.status()).unwrap(),
+                        None => "No schedule, see --schedule".to_string(),
+                    };
+                    writer.write_all(format!("{}\n", json).as_bytes())?;
+                    writer.flush()?;
+                    continue;
+                }
+                if let Some(stripped) = cmd.strip_prefix("show_heatmap=") {
+                    let params = stripped;
+                    let args: RpcHeatmap = match serde_json::from_str(params) {
+                        Ok(res) => res,
+                        Err(err) => {
+                            log::error!("Unable to deserialize params for show_heatmap {:?}", err);
+                            return Ok(());
+                        }
+                    };
+                    let leds = match heatmap.lock().unwrap().as_ref() {
+                        Some(recorder) => {
+                            recorder
+                                .counts()
+                                .render(args.scale, &args.gradient, args.space)
+                        }
+                        None => {
+                            let emsg = "Errror in show_heatmap: recording is disabled";
+                            let _ = writer.write_all(format!("{}\n", emsg).as_bytes());
+                            log::error!("{}", emsg);
+                            return Ok(());
+                        }
+                    };
+                    let target = SavedLighting::custom_colors(leds, Brightness::Full, 100);
+                    let _guard = mutex.lock().unwrap();
+                    match lighting.lock().unwrap().apply(&keyboard, target, None) {
+                        Ok(res) => res,
+                        Err(err) => {
+                            let emsg = format!("Errror in show_heatmap: {:?}", err);
+                            let _ = writer.write_all(emsg.as_bytes());
+                            log::error!("{}", emsg);
+                            return Ok(());
+                        }
+                    }
+                    continue;
+                }
                 log::warn!("received invalid cmd: {:?}", cmd.trim());
                 continue;
             }
@@ -146,6 +466,9 @@ fn socket_server(
     keep_running: Arc<AtomicBool>,
     keyboard: Arc<CherryKeyboard>,
     mutex: Arc<Mutex<u32>>,
+    lighting: Arc<Mutex<Lighting>>,
+    heatmap: Arc<Mutex<Option<HeatmapRecorder>>>,
+    scheduler: Arc<Mutex<Option<Scheduler>>>,
 ) -> Result<()> {
     log::debug!("Listening on {:?}", opt.socket_path);
     let listener = UnixListener::bind(opt.socket_path.clone())?;
@@ -167,9 +490,21 @@ fn socket_server(
                     log::debug!("Got connection on {:?}", opt.socket_path);
                     let keyboard_clone = Arc::clone(&keyboard);
                     let mutex_clone = Arc::clone(&mutex);
+                    let lighting_clone = Arc::clone(&lighting);
+                    let heatmap_clone = Arc::clone(&heatmap);
+                    let scheduler_clone = Arc::clone(&scheduler);
                     let tb = thread::Builder::new().name("handle_client".into());
-                    tb.spawn(|| handle_client(stream, keyboard_clone, mutex_clone))
-                        .unwrap();
+                    tb.spawn(|| {
+                        handle_client(
+                            stream,
+                            keyboard_clone,
+                            mutex_clone,
+                            lighting_clone,
+                            heatmap_clone,
+                            scheduler_clone,
+                        )
+                    })
+                    .unwrap();
                 } else {
                     let _ = std::fs::remove_file(opt.socket_path.clone());
                     break;
@@ -217,6 +552,47 @@ fn main() -> Result<()> {
     let aopt = Arc::new(opt.clone());
     // Mutex for accessing CherryKeyboard
     let amutex = Arc::new(Mutex::new(0));
+    // Lighting state, running software effect or transition
+    let alighting = Arc::new(Mutex::new(Lighting::default()));
+    let schedule = match (
+        opt.night_light_start,
+        opt.night_light_end,
+        opt.latitude,
+        opt.longitude,
+    ) {
+        (Some(start), Some(end), _, _) => Some(NightLightSchedule::Fixed { start, end }),
+        (_, _, Some(latitude), Some(longitude)) => Some(NightLightSchedule::Sun {
+            latitude,
+            longitude,
+        }),
+        _ => None,
+    };
+    if let Some(schedule) = schedule {
+        alighting.lock().unwrap().set_night_light(NightLight {
+            schedule,
+            temperature: opt.night_temperature,
+            fade: time::Duration::from_secs(opt.night_light_fade * 60),
+        });
+    }
+    if let Some(timeout) = opt.idle_timeout {
+        alighting.lock().unwrap().set_idle_dimming(
+            time::Duration::from_secs(timeout),
+            opt.idle_brightness.clone(),
+        );
+    }
+    // Key press counts, if enabled
+    let aheatmap = Arc::new(Mutex::new(opt.heatmap_file.as_ref().map(|path| {
+        HeatmapRecorder::new(path, time::Duration::from_secs(opt.heatmap_save_interval))
+    })));
+
+    // Profile scheduler, if enabled
+    let schedule = match &opt.schedule {
+        Some(path) => Some(Schedule::load(path).context("Failed to load schedule")?),
+        None => None,
+    };
+    let ascheduler = Arc::new(Mutex::new(
+        schedule.map(|schedule| Scheduler::new(schedule, Box::new(SystemClock))),
+    ));
 
     // Allow the usual hex specifiation (starting with 0x) for the product-id
     let pid = common::get_u16_from_string(opt.product_id);
@@ -239,9 +615,41 @@ fn main() -> Result<()> {
     }
 
     let (vendor_id, product_id) = devices.first().unwrap().to_owned();
-    let keyboard =
+    let mut keyboard =
         CherryKeyboard::new(vendor_id, product_id).context("Failed to create keyboard")?;
+    if let Some(path) = &opt.calibration {
+        keyboard.set_calibration(Calibration::load(path).context("Failed to load calibration")?);
+    }
+    // Restore the lighting of the last run, then save it whenever it changes
+    match std::fs::create_dir_all(&opt.state_dir) {
+        Ok(()) => {
+            let state_file = opt
+                .state_dir
+                .join(SavedLighting::file_name(keyboard.device_id()));
+            let mut lighting = alighting.lock().unwrap();
+            if !opt.no_restore && state_file.exists() {
+                log::info!("Restoring lighting from {:?}", state_file);
+                let result =
+                    load_state(&state_file).and_then(|saved| lighting.restore(&keyboard, saved));
+                if let Err(err) = result {
+                    log::error!("Failed to restore lighting, err={}", err);
+                }
+            }
+            lighting.set_state_file(state_file);
+        }
+        Err(err) => log::error!(
+            "Failed to create state directory {:?}, err={}",
+            opt.state_dir,
+            err
+        ),
+    }
     let mut vkb = VirtKbd::new();
+    let lock_indicators = LockIndicators {
+        caps_lock: opt.caps_lock_color.clone(),
+        num_lock: opt.num_lock_color.clone(),
+        scroll_lock: opt.scroll_lock_color.clone(),
+        board: opt.lock_tint_board,
+    };
 
     let aopt_clone = Arc::clone(&aopt);
     let akeyboard = Arc::new(keyboard);
@@ -250,17 +658,68 @@ fn main() -> Result<()> {
     let driver_running = Arc::clone(&running);
     let amutex_clone1 = Arc::clone(&amutex);
     let amutex_clone2 = Arc::clone(&amutex);
+    let alighting_clone = Arc::clone(&alighting);
+    let aheatmap_clone = Arc::clone(&aheatmap);
+    let ascheduler_clone = Arc::clone(&ascheduler);
     let tb = thread::Builder::new().name("socket_server".into());
     let th = tb
-        .spawn(|| socket_server(aopt_clone, server_running, akeyboard_clone, amutex_clone1))
+        .spawn(|| {
+            socket_server(
+                aopt_clone,
+                server_running,
+                akeyboard_clone,
+                amutex_clone1,
+                alighting_clone,
+                aheatmap_clone,
+                ascheduler_clone,
+            )
+        })
         .unwrap();
     log::debug!("Entering driver loop");
     while driver_running.load(Ordering::SeqCst) {
+        let report = {
+            let _guard = amutex_clone2.lock().unwrap();
+            match Arc::clone(&akeyboard).forward_filtered_keys(&mut vkb) {
+                Ok(report) => report,
+                Err(err) => {
+                    log::error!("Failed to forward key events, err={}", err);
+                    break;
+                }
+            }
+        };
+        // Render the effect frame without holding the keyboard, scripts may take up to 50 ms
+        let effect = alighting.lock().unwrap().effect();
+        let rendered = effect.map(|effect| {
+            let frame = effect.tick(report.as_deref());
+            (effect, frame)
+        });
         {
             let _guard = amutex_clone2.lock().unwrap();
-            if let Err(err) = Arc::clone(&akeyboard).forward_filtered_keys(&mut vkb) {
-                log::error!("Failed to forward key events, err={}", err);
-                break;
+            let mut lighting = alighting.lock().unwrap();
+            if let Some(state) = vkb.read_lock_state() {
+                if let Err(err) = akeyboard.set_lock_leds(state) {
+                    log::warn!("Failed to set lock LEDs, err={}", err);
+                }
+                if let Err(err) = lighting.set_lock_state(&akeyboard, state, &lock_indicators) {
+                    log::error!("Failed to show lock state, err={}", err);
+                }
+            }
+            if let Some(scheduler) = ascheduler.lock().unwrap().as_mut() {
+                let base_dir = scheduler.schedule().base_dir.clone();
+                if let Some((name, profile)) = scheduler.poll() {
+                    log::info!("Switching to scheduled profile {}", name);
+                    let result = profile.lighting_state(&base_dir).and_then(|state| {
+                        let target = SavedLighting::from(Scene::from(state));
+                        lighting.apply(&akeyboard, target, None)
+                    });
+                    if let Err(err) = result {
+                        log::error!("Failed to apply profile {}, err={}", name, err);
+                    }
+                }
+            }
+            lighting.tick(&akeyboard, report.as_deref(), rendered);
+            if let (Some(report), Some(recorder)) = (&report, aheatmap.lock().unwrap().as_mut()) {
+                recorder.record(report);
             }
         }
         // Without this sleep, sometimes the mutex appears to be still locked
@@ -268,6 +727,9 @@ fn main() -> Result<()> {
         thread::sleep(time::Duration::from_millis(100));
     }
     running.store(false, Ordering::SeqCst);
+    if let Some(recorder) = aheatmap.lock().unwrap().as_mut() {
+        recorder.save();
+    }
     // This triggers a break in the socket_server accept loop
     let _ = UnixStream::connect(opt.socket_path);
     _ = th.join();
@@ -1,3 +1,4 @@
+use cherryrgb::{nightlight::parse_time_of_day, Brightness, OwnRGB8};
 use clap::Parser;
 use std::path::PathBuf;
 
@@ -23,4 +24,76 @@ pub struct Opt {
     /// Group of the socket
     #[arg(name = "socketgroup", short = 'g', long, default_value = "root")]
     pub socket_group: String,
+
+    /// Color of the Caps Lock key while Caps Lock is active (e.g. ff0000)
+    #[arg(long)]
+    pub caps_lock_color: Option<OwnRGB8>,
+
+    /// Color of the Num Lock key while Num Lock is active
+    #[arg(long)]
+    pub num_lock_color: Option<OwnRGB8>,
+
+    /// Color of the Scroll Lock key while Scroll Lock is active
+    #[arg(long)]
+    pub scroll_lock_color: Option<OwnRGB8>,
+
+    /// Tint the whole board instead of the lock keys only
+    #[arg(long)]
+    pub lock_tint_board: bool,
+
+    /// Count key presses per key and save the counts to this file
+    #[arg(long)]
+    pub heatmap_file: Option<PathBuf>,
+
+    /// Interval for saving the key press counts (seconds)
+    #[arg(long, default_value_t = 300)]
+    pub heatmap_save_interval: u64,
+
+    /// Start of the night light, local time (e.g. 21:30)
+    #[arg(long, value_parser = parse_time_of_day, requires = "night_light_end", conflicts_with = "latitude")]
+    pub night_light_start: Option<u32>,
+
+    /// End of the night light, local time (e.g. 06:30)
+    #[arg(long, value_parser = parse_time_of_day, requires = "night_light_start")]
+    pub night_light_end: Option<u32>,
+
+    /// Latitude (degrees, north is positive), enables the night light from sunset to sunrise
+    #[arg(long, requires = "longitude", allow_hyphen_values = true)]
+    pub latitude: Option<f64>,
+
+    /// Longitude (degrees, east is positive)
+    #[arg(long, requires = "latitude", allow_hyphen_values = true)]
+    pub longitude: Option<f64>,
+
+    /// Color temperature of the night light (Kelvin)
+    #[arg(long, default_value_t = 3400, value_parser = clap::value_parser!(u32).range(1000..=6500))]
+    pub night_temperature: u32,
+
+    /// Length of the fade between day and night (minutes)
+    #[arg(long, default_value_t = 30)]
+    pub night_light_fade: u64,
+
+    /// Dim after this many seconds without key presses, until the next key press
+    #[arg(long)]
+    pub idle_timeout: Option<u64>,
+
+    /// Brightness while idle
+    #[arg(long, value_enum, default_value_t = Brightness::Off)]
+    pub idle_brightness: Brightness,
+
+    /// Switch profiles at certain times, as defined in this file (TOML)
+    #[arg(long)]
+    pub schedule: Option<PathBuf>,
+
+    /// Color calibration (TOML), as created by `cherryrgb_cli calibrate`
+    #[arg(long)]
+    pub calibration: Option<PathBuf>,
+
+    /// Directory the last applied lighting is saved to, one file per keyboard
+    #[arg(long, default_value = "/var/lib/cherryrgb")]
+    pub state_dir: PathBuf,
+
+    /// Don't restore the last applied lighting on start
+    #[arg(long)]
+    pub no_restore: bool,
 }
@@ -0,0 +1,153 @@
+use anyhow::{anyhow, Context, Error};
+use cherryrgb::{layout, Calibration, CherryKeyboard, CustomKeyLeds, OwnRGB8, TOTAL_KEYS};
+use std::convert::TryInto;
+use std::io::{self, BufRead, Write};
+use std::path::PathBuf;
+
+/// Calibration file of the given keyboard model
+pub fn path(vendor_id: u16, product_id: u16) -> Result<PathBuf, Error> {
+    match dirs::config_dir() {
+        Some(dir) => Ok(dir
+            .join("cherryrgb")
+            .join(format!("calibration_{vendor_id:04x}_{product_id:04x}.toml"))),
+        None => Err(anyhow!("Could not get config directory")),
+    }
+}
+
+/// Load calibration of the given keyboard model, if there is one
+pub fn load(vendor_id: u16, product_id: u16) -> Result<Option<Calibration>, Error> {
+    let path = path(vendor_id, product_id)?;
+    if !path.exists() {
+        return Ok(None);
+    }
+    log::debug!("Loading calibration from {path:?}");
+    Ok(Some(Calibration::load(&path)?))
+}
+
+fn save(vendor_id: u16, product_id: u16, calibration: &Calibration) -> Result<PathBuf, Error> {
+    let path = path(vendor_id, product_id)?;
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir)?;
+    }
+    std::fs::write(&path, calibration.to_toml()).context(format!("writing {path:?}"))?;
+    Ok(path)
+}
+
+/// Show pattern, each key colored by its column (0.0 - 1.0 from left to right)
+fn show_pattern<F: Fn(f32) -> OwnRGB8>(keyboard: &CherryKeyboard, color: F) -> Result<(), Error> {
+    let leds = (0..TOTAL_KEYS)
+        .map(|index| {
+            let (column, _) = layout::key_position(index).unwrap();
+            color(column as f32 / (layout::COLUMNS - 1) as f32)
+        })
+        .collect();
+    keyboard.set_custom_colors(CustomKeyLeds::from_leds(leds)?)?;
+    Ok(())
+}
+
+/// Ask until the answer is empty (accept) or can be parsed
+fn ask<T, F: Fn(&str) -> Option<T>>(question: &str, parse: F) -> Result<Option<T>, Error> {
+    loop {
+        print!("{question} ");
+        io::stdout().flush()?;
+        let mut answer = String::new();
+        io::stdin().lock().read_line(&mut answer)?;
+        let answer = answer.trim();
+        if answer.is_empty() {
+            return Ok(None);
+        }
+        match parse(answer) {
+            Some(value) => return Ok(Some(value)),
+            None => println!("Invalid input '{answer}'"),
+        }
+    }
+}
+
+/// Parse three numbers separated by spaces or commas
+fn parse_triple(answer: &str) -> Option<[f32; 3]> {
+    let values: Vec<f32> = answer
+        .split(|c: char| c == ',' || c.is_whitespace())
+        .filter(|s| !s.is_empty())
+        .map(|s| s.parse().ok())
+        .collect::<Option<_>>()?;
+    values.try_into().ok()
+}
+
+/// Interactively tune the calibration using test patterns and save it
+pub fn run_wizard(
+    keyboard: &mut CherryKeyboard,
+    vendor_id: u16,
+    product_id: u16,
+) -> Result<(), Error> {
+    let mut calibration = load(vendor_id, product_id)?.unwrap_or_default();
+    println!("Current calibration:\n{}", calibration.to_toml());
+    println!("Press enter to accept the shown values and continue with the next step.\n");
+
+    // 1. White point
+    loop {
+        keyboard.set_calibration(calibration.clone());
+        show_pattern(keyboard, |_| OwnRGB8::new(255, 255, 255))?;
+        let [r, g, b] = calibration.white_point.map(|c| (c * 100.0).round());
+        let question = format!(
+            "All keys should be neutral white. Red, green, blue scale in percent [{r} {g} {b}]:"
+        );
+        match ask(&question, |answer| {
+            parse_triple(answer).filter(|v| v.iter().all(|c| (0.0..=100.0).contains(c)))
+        })? {
+            Some(scale) => calibration.white_point = scale.map(|c| c / 100.0),
+            None => break,
+        }
+    }
+
+    // 2. Gamma, ramps from dark (left) to bright (right) should look evenly spaced
+    for (channel, name) in ["red", "green", "blue"].iter().enumerate() {
+        loop {
+            keyboard.set_calibration(calibration.clone());
+            show_pattern(keyboard, |t| {
+                let mut rgb = [0; 3];
+                rgb[channel] = (t * 255.0).round() as u8;
+                OwnRGB8::new(rgb[0], rgb[1], rgb[2])
+            })?;
+            let question = format!(
+                "The {name} ramp should brighten evenly from left to right. Gamma (higher darkens the middle) [{}]:",
+                calibration.gamma[channel]
+            );
+            match ask(&question, |answer| {
+                answer
+                    .parse::<f32>()
+                    .ok()
+                    .filter(|g| (0.1..=5.0).contains(g))
+            })? {
+                Some(gamma) => calibration.gamma[channel] = gamma,
+                None => break,
+            }
+        }
+    }
+
+    // 3. Lowest visible value, each column shows the value of its number (1, 2, 3, ...)
+    keyboard.set_calibration(Calibration::default());
+    show_pattern(keyboard, |t| {
+        let value = (t * (layout::COLUMNS - 1) as f32).round() as u8 + 1;
+        OwnRGB8::new(value, value, value)
+    })?;
+    let question = format!(
+        "Count the columns from the left. Number of the first lit column [{}]:",
+        calibration.min_visible
+    );
+    if let Some(min_visible) = ask(&question, |answer| {
+        answer
+            .parse::<u8>()
+            .ok()
+            .filter(|&v| v as usize <= layout::COLUMNS)
+    })? {
+        calibration.min_visible = min_visible;
+    }
+
+    keyboard.set_calibration(calibration.clone());
+    show_pattern(keyboard, |t| {
+        OwnRGB8::new(255, 255, 255).lerp(&OwnRGB8::new(0, 0, 0), t)
+    })?;
+    let path = save(vendor_id, product_id, &calibration)?;
+    println!("Saved calibration to {path:?}");
+    Ok(())
+}
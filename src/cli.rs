@@ -1,9 +1,14 @@
 use cherryrgb::{self, Brightness};
-use clap::Parser;
+use clap::{Parser, Subcommand};
+use std::time::Duration;
 
 #[path = "commonargs.rs"]
 mod commonargs;
-pub use commonargs::{AnimationArgs, CliCommand, ColorProfileFileOptions, CustomColorOptions};
+pub use commonargs::{
+    parse_duration, AnimationArgs, AnimationFileOptions, CliCommand, ColorProfileFileOptions,
+    CustomColorOptions, PlaybackOptions, ProfileCommand, ProfileLoadOptions, ProfileNameOptions,
+    ProfileSaveOptions, SceneOptions, ScriptOptions,
+};
 
 #[derive(Parser, Debug)]
 #[command(name = "cherryrgb_cli", author, version, about = "Test tool for Cherry RGB Keyboard", long_about = None)]
@@ -19,9 +24,64 @@ pub struct Opt {
 
     // Subcommand
     #[command(subcommand)]
-    pub command: CliCommand,
+    pub command: Command,
 
     /// Set brightness
     #[arg(short, long, default_value_t = Brightness::Full, value_enum)]
     pub brightness: Brightness,
+
+    /// Scale custom colors (percent), for finer steps than --brightness
+    #[arg(long, default_value_t = 100, value_parser = clap::value_parser!(u8).range(0..=100))]
+    pub software_brightness: u8,
+
+    /// Fade smoothly into the new colors or animation, e.g. 500ms
+    #[arg(short, long, value_parser = parse_duration)]
+    pub transition: Option<Duration>,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum Command {
+    #[command(flatten)]
+    Common(CliCommand),
+
+    /// Tune gamma and color calibration of the keyboard using test patterns
+    Calibrate,
+
+    /// Send a raw payload and print the response, for protocol research
+    Raw(RawOptions),
+}
+
+#[derive(Parser, Debug)]
+pub struct RawOptions {
+    /// Wrap the payload in a start/end transaction
+    #[arg(long)]
+    pub transaction: bool,
+
+    /// Payload type, as hex (e.g. 03)
+    #[arg(value_parser = parse_hex_byte)]
+    pub payload_type: u8,
+
+    /// Payload data, as hex (e.g. 22 00 00 or 220000)
+    pub data: Vec<String>,
+}
+
+/// Parse a single byte given as hex, optionally prefixed with '0x'
+fn parse_hex_byte(arg: &str) -> Result<u8, String> {
+    let digits = arg.trim_start_matches("0x");
+    u8::from_str_radix(digits, 16).map_err(|_| format!("Invalid hex byte '{arg}'"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::parse_hex_byte;
+
+    #[test]
+    fn hex_byte() {
+        assert_eq!(parse_hex_byte("03"), Ok(0x03));
+        assert_eq!(parse_hex_byte("1b"), Ok(0x1B));
+        assert_eq!(parse_hex_byte("0x1B"), Ok(0x1B));
+        assert!(parse_hex_byte("zz").is_err());
+        assert!(parse_hex_byte("100").is_err());
+        assert!(parse_hex_byte("").is_err());
+    }
 }
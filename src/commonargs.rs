@@ -1,4 +1,4 @@
-use std::path::PathBuf;
+use std::{path::PathBuf, time::Duration};
 
 use cherryrgb::{self, LightingMode, OwnRGB8, Speed};
 use clap::{Parser, Subcommand};
@@ -23,7 +23,7 @@ pub struct AnimationArgs {
 
 #[derive(Parser, Debug)]
 pub struct CustomColorOptions {
-    /// One or more RGB color specs (6-digit hex numbers)
+    /// One or more colors (e.g. ff0000, #f00, red, hsl(0 100% 50%))
     pub colors: Vec<OwnRGB8>,
 }
 
@@ -33,8 +33,110 @@ pub struct ColorProfileFileOptions {
     #[arg(short, long = "keep-existing-colors")]
     pub keep_existing: bool,
 
-    /// A json encoded file, specifying key colors
+    /// Profile file specifying key colors (JSON, TOML or YAML)
     pub file_path: PathBuf,
+
+    /// Playback of the animation embedded in the profile
+    #[command(flatten)]
+    pub playback: PlaybackOptions,
+}
+
+#[derive(Parser, Debug)]
+pub struct PlaybackOptions {
+    /// Frames per second
+    #[arg(short, long, default_value_t = 10)]
+    pub fps: u32,
+
+    /// Stop after the given duration (e.g. 30s or 5m), run until finished or interrupted otherwise
+    #[arg(long, value_parser = parse_duration)]
+    pub duration: Option<Duration>,
+}
+
+#[derive(Parser, Debug)]
+pub struct ScriptOptions {
+    /// Name of a script in ~/.config/cherryrgb/effects (without .rhai extension) or path of a script file
+    pub name: String,
+
+    #[command(flatten)]
+    pub playback: PlaybackOptions,
+}
+
+#[derive(Parser, Debug)]
+pub struct AnimationFileOptions {
+    /// A json or toml encoded file, specifying keyframes
+    pub file_path: PathBuf,
+
+    #[command(flatten)]
+    pub playback: PlaybackOptions,
+}
+
+#[derive(Parser, Debug)]
+pub struct SceneOptions {
+    /// A json, toml or yaml encoded file, specifying animation and custom colors
+    pub file_path: PathBuf,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum ProfileCommand {
+    /// Save the current custom colors, or a profile file, under the given name
+    Save(ProfileSaveOptions),
+    /// Apply a saved profile
+    Load(ProfileLoadOptions),
+    /// List saved profiles
+    List,
+    /// Delete a saved profile
+    Delete(ProfileNameOptions),
+    /// Print a saved profile
+    Show(ProfileNameOptions),
+}
+
+#[derive(Parser, Debug)]
+pub struct ProfileSaveOptions {
+    /// Name of the profile
+    pub name: String,
+
+    /// Profile file to copy into the library, instead of the current custom colors
+    #[arg(short, long)]
+    pub file: Option<PathBuf>,
+}
+
+#[derive(Parser, Debug)]
+pub struct ProfileLoadOptions {
+    /// If enabled, modifies existing color profile
+    #[arg(short, long = "keep-existing-colors")]
+    pub keep_existing: bool,
+
+    /// Name of the profile
+    pub name: String,
+
+    /// Playback of the animation embedded in the profile
+    #[command(flatten)]
+    pub playback: PlaybackOptions,
+}
+
+#[derive(Parser, Debug)]
+pub struct ProfileNameOptions {
+    /// Name of the profile
+    pub name: String,
+}
+
+/// Parse a duration with unit suffix (ms, s, m, h), plain numbers are interpreted as ms
+pub fn parse_duration(arg: &str) -> Result<Duration, String> {
+    let arg = arg.trim();
+    let split = arg.find(|c: char| !c.is_ascii_digit()).unwrap_or(arg.len());
+    let (value, unit) = arg.split_at(split);
+    let value: u64 = value
+        .parse()
+        .map_err(|_| format!("Invalid duration '{arg}'"))?;
+    match unit.trim() {
+        "" | "ms" => Ok(Duration::from_millis(value)),
+        "s" => Ok(Duration::from_secs(value)),
+        "m" => Ok(Duration::from_secs(value * 60)),
+        "h" => Ok(Duration::from_secs(value * 3600)),
+        _ => Err(format!(
+            "Invalid unit in duration '{arg}', use ms, s, m or h"
+        )),
+    }
 }
 
 #[derive(Subcommand, Debug)]
@@ -45,4 +147,21 @@ pub enum CliCommand {
     CustomColors(CustomColorOptions),
     /// Configure custom RGB colors from file
     ColorProfileFile(ColorProfileFileOptions),
+    /// Run a lighting effect script
+    Script(ScriptOptions),
+    /// Play keyframe animation from file
+    AnimationFile(AnimationFileOptions),
+    /// Apply scene (animation and custom colors) from file
+    Scene(SceneOptions),
+    /// Apply the last applied lighting again
+    Restore,
+    /// Go back to the lighting applied before
+    Undo,
+    /// Apply the lighting undone last again
+    Redo,
+    /// Show the lighting applied recently, the current one is marked with *
+    History,
+    /// Manage named color profiles in ~/.config/cherryrgb/profiles
+    #[command(subcommand)]
+    Profile(ProfileCommand),
 }
@@ -0,0 +1,52 @@
+use anyhow::{anyhow, Context, Error};
+use cherryrgb::{read_keyframe_animation, read_scene, FileFormat, KeyframeAnimation, Scene};
+use std::path::{Path, PathBuf};
+
+const SCRIPT_EXTENSION: &str = "rhai";
+
+/// Return the directory containing effect scripts
+pub fn effects_dir() -> Result<PathBuf, Error> {
+    match dirs::config_dir() {
+        Some(path) => Ok(path.join("cherryrgb").join("effects")),
+        None => Err(anyhow!("Could not get config directory")),
+    }
+}
+
+/// Load a script either by path or by name from the effects directory.
+/// Returns the script name and its source.
+pub fn load_script(name: &str) -> Result<(String, String), Error> {
+    let path = Path::new(name);
+    let path = if path.is_file() {
+        path.to_path_buf()
+    } else {
+        effects_dir()?.join(name).with_extension(SCRIPT_EXTENSION)
+    };
+    let source = std::fs::read_to_string(&path).context(format!("script {path:?}"))?;
+    let name = path
+        .file_stem()
+        .map_or(name.to_owned(), |stem| stem.to_string_lossy().into_owned());
+    log::debug!("Loaded script {name} from {path:?}");
+    Ok((name, source))
+}
+
+/// Load a keyframe animation file.
+/// Returns the animation name and the animation.
+pub fn load_animation(path: &Path) -> Result<(String, KeyframeAnimation), Error> {
+    let content = std::fs::read_to_string(path).context(format!("animation {path:?}"))?;
+    let animation = read_keyframe_animation(&content, FileFormat::from_path(path))
+        .context(format!("reading animation {path:?}"))?;
+    Ok((animation_name(path), animation))
+}
+
+/// Load a scene file
+pub fn load_scene(path: &Path) -> Result<Scene, Error> {
+    let content = std::fs::read_to_string(path).context(format!("scene {path:?}"))?;
+    read_scene(&content, FileFormat::from_path(path)).context(format!("reading scene {path:?}"))
+}
+
+/// Name of an animation, derived from its file name
+pub fn animation_name(path: &Path) -> String {
+    path.file_stem().map_or("animation".into(), |stem| {
+        stem.to_string_lossy().into_owned()
+    })
+}
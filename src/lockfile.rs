@@ -0,0 +1,25 @@
+use std::{
+    fs::{File, OpenOptions},
+    io::Error,
+    path::Path,
+};
+
+/// Open and lock the lock file belonging to a state file, the lock is released on drop.
+/// Every process reading or writing the state file takes this advisory lock.
+pub fn lock(path: &Path, exclusive: bool) -> Result<File, Error> {
+    let lock_path = path.with_extension("lock");
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir)?;
+    }
+    let file = OpenOptions::new()
+        .create(true)
+        .truncate(false)
+        .write(true)
+        .open(lock_path)?;
+    if exclusive {
+        file.lock()?;
+    } else {
+        file.lock_shared()?;
+    }
+    Ok(file)
+}
@@ -1,14 +1,157 @@
-use std::{convert::TryFrom, fs::File, io::Read};
+use std::{
+    convert::TryFrom,
+    thread,
+    time::{Duration, Instant},
+};
 
 use anyhow::{anyhow, Context, Result};
-use cherryrgb::{self, read_color_profile, rgb, CherryKeyboard, CustomKeyLeds};
+use cherryrgb::{
+    self, rgb, AnimationParams, Brightness, CherryKeyboard, CustomKeyLeds, Effect, History,
+    KeyframePlayer, LightingMode, LightingState, Profile, RpcEffect, RpcEffectSource,
+    SavedLighting, Scene, ScriptEffect,
+};
 use clap::Parser;
 
 mod cli;
-use cli::{CliCommand, Opt};
+use cli::{CliCommand, ColorProfileFileOptions, Command, Opt, PlaybackOptions, ProfileCommand};
+mod calibrate;
 mod common;
+mod effects;
+mod lockfile;
+mod profiles;
 mod state;
 
+/// Render effect frames and send them to the keyboard until finished
+fn play_effect(
+    keyboard: &CherryKeyboard,
+    effect: &mut dyn Effect,
+    playback: &PlaybackOptions,
+) -> Result<()> {
+    let interval = Duration::from_secs(1) / playback.fps.max(1);
+    let started = Instant::now();
+
+    log::info!("Playing {}", effect.name());
+    keyboard.set_custom_colors(CustomKeyLeds::new())?;
+    loop {
+        let frame_start = Instant::now();
+        let elapsed = started.elapsed();
+        if matches!(playback.duration, Some(d) if elapsed >= d) {
+            break;
+        }
+        match effect.render(elapsed)? {
+            Some(leds) => keyboard.update_custom_colors(leds)?,
+            None => break,
+        }
+        thread::sleep(interval.saturating_sub(frame_start.elapsed()));
+    }
+    Ok(())
+}
+
+/// Fade into new lighting, starting from the last saved state.
+/// Custom colors are faded at the given hardware brightness.
+fn transition(
+    keyboard: &CherryKeyboard,
+    to: LightingState,
+    duration: Duration,
+    brightness: Brightness,
+) -> Result<()> {
+    let from = state::read_state(keyboard.device_id())
+        .ok()
+        .and_then(|saved| saved.lighting_state());
+    keyboard
+        .transition_with_brightness(from.as_ref(), &to, duration, brightness)
+        .context("Failed to fade into new lighting")
+}
+
+/// Software effect as saved in the state
+fn saved_effect(name: String, source: RpcEffectSource, playback: &PlaybackOptions) -> RpcEffect {
+    RpcEffect {
+        name,
+        source,
+        fps: playback.fps,
+        duration: playback.duration,
+    }
+}
+
+/// Play a saved software effect
+fn play_saved_effect(keyboard: &CherryKeyboard, saved: RpcEffect) -> Result<()> {
+    let mut effect: Box<dyn Effect> = match saved.source {
+        RpcEffectSource::Script(source) => Box::new(ScriptEffect::new(&saved.name, &source)?),
+        RpcEffectSource::Keyframes(animation) => {
+            Box::new(KeyframePlayer::new(&saved.name, &animation)?)
+        }
+    };
+    let playback = PlaybackOptions {
+        fps: saved.fps,
+        duration: saved.duration,
+    };
+    play_effect(keyboard, effect.as_mut(), &playback)
+}
+
+/// Apply saved scene, then play the saved effect
+fn apply_saved(keyboard: &CherryKeyboard, saved: SavedLighting) -> Result<()> {
+    log::info!("Applying {saved}");
+    if let Some(scene) = saved.applied_scene() {
+        keyboard
+            .apply_scene(&scene)
+            .context("Failed to apply saved lighting")?;
+    }
+    if let Some(effect) = saved.effect {
+        play_saved_effect(keyboard, effect)?;
+    }
+    Ok(())
+}
+
+/// Set custom colors from a color profile file, then play its animation
+fn set_color_profile(
+    keyboard: &CherryKeyboard,
+    args: &ColorProfileFileOptions,
+    brightness: Brightness,
+    software_brightness: u8,
+    transition_duration: Option<Duration>,
+) -> Result<()> {
+    let profile =
+        Profile::load(&args.file_path).context(format!("color profile {:?}", args.file_path))?;
+    let colors_from_file = profile
+        .profile_keys()
+        .context("reading colors from color file")?;
+
+    let keys = if args.keep_existing {
+        state::load(keyboard.device_id())?
+            .modify_from(colors_from_file)
+            .context("assembling custom key leds")?
+    } else {
+        CustomKeyLeds::try_from(colors_from_file).context("assembling custom key leds")?
+    };
+    // The state keeps the colors as given, so they can be modified later on
+    let scaled = keys.scale(software_brightness as f32 / 100.0);
+    match transition_duration {
+        Some(duration) => transition(
+            keyboard,
+            LightingState::CustomColors(scaled),
+            duration,
+            brightness.clone(),
+        )?,
+        None => keyboard.set_custom_colors_with_brightness(scaled, brightness.clone())?,
+    }
+    let mut saved = SavedLighting::custom_colors(keys, brightness, software_brightness);
+
+    if let Some(animation) = profile.animation()? {
+        let name = effects::animation_name(&args.file_path);
+        let mut player = KeyframePlayer::new(&name, &animation)?;
+        saved.effect = Some(saved_effect(
+            name,
+            RpcEffectSource::Keyframes(animation),
+            &args.playback,
+        ));
+        state::record(keyboard.device_id(), &saved)?;
+        play_effect(keyboard, &mut player, &args.playback)?;
+    } else {
+        state::record(keyboard.device_id(), &saved)?;
+    }
+    Ok(())
+}
+
 fn main() -> Result<()> {
     let opt = Opt::parse();
 
@@ -28,7 +171,7 @@ fn main() -> Result<()> {
     }
 
     let (vendor_id, product_id) = devices.first().unwrap().to_owned();
-    let keyboard =
+    let mut keyboard =
         CherryKeyboard::new(vendor_id, product_id).context("Failed to create keyboard")?;
 
     let loglevel = if opt.debug {
@@ -38,57 +181,46 @@ fn main() -> Result<()> {
     };
     simple_logger::init_with_level(loglevel)?;
 
+    if let Some(calibration) = calibrate::load(vendor_id, product_id)? {
+        keyboard.set_calibration(calibration);
+    }
+
     /* Fun begins */
     keyboard
         .fetch_device_state()
         .context("Fetching device state failed")?;
 
     match opt.command {
-        CliCommand::CustomColors(args) => {
-            keyboard.reset_custom_colors()?;
+        Command::Common(CliCommand::CustomColors(args)) => {
             let mut keys = CustomKeyLeds::new();
 
             for (index, color) in args.colors.into_iter().enumerate() {
                 keys.set_led(index, color)?;
             }
+            let scaled = keys.scale(opt.software_brightness as f32 / 100.0);
 
-            keyboard.set_custom_colors(keys)?;
-        }
-        CliCommand::ColorProfileFile(args) => {
-            let mut f = File::open(&args.file_path)
-                .context(format!("color profile {:?}", args.file_path))?;
-            let mut json: String = String::new();
-
-            f.read_to_string(&mut json)?;
-            // Allow // comments
-            let re = regex::RegexBuilder::new(r"//.*?$")
-                .multi_line(true)
-                .build()
-                .unwrap();
-            json = re.replace_all(&json, "").to_string();
-            // Allow trailing comma after last element
-            let re = regex::RegexBuilder::new(r",(\s*\})").build().unwrap();
-            json = re.replace_all(&json, "$1").to_string();
-
-            log::debug!("{json}");
-
-            let colors_from_file =
-                read_color_profile(&json).context("reading colors from color file")?;
-
-            if args.keep_existing {
-                let keys = state::load()?
-                    .modify_from(colors_from_file)
-                    .context("assembling custom key leds")?;
-                keyboard.set_custom_colors(keys.clone())?;
-                state::save(keys)?;
-            } else {
-                let keys = CustomKeyLeds::try_from(colors_from_file)
-                    .context("assembling custom key leds")?;
-                keyboard.set_custom_colors(keys.clone())?;
-                state::save(keys)?;
+            match opt.transition {
+                Some(duration) => transition(
+                    &keyboard,
+                    LightingState::CustomColors(scaled),
+                    duration,
+                    opt.brightness.clone(),
+                )?,
+                None => {
+                    keyboard.set_custom_colors_with_brightness(scaled, opt.brightness.clone())?
+                }
             }
+            let saved = SavedLighting::custom_colors(keys, opt.brightness, opt.software_brightness);
+            state::record(keyboard.device_id(), &saved)?;
         }
-        CliCommand::Animation(args) => {
+        Command::Common(CliCommand::ColorProfileFile(args)) => set_color_profile(
+            &keyboard,
+            &args,
+            opt.brightness,
+            opt.software_brightness,
+            opt.transition,
+        )?,
+        Command::Common(CliCommand::Animation(args)) => {
             let color = args.color.unwrap_or(rgb::RGB8::new(255, 255, 255).into());
 
             log::info!(
@@ -99,9 +231,146 @@ fn main() -> Result<()> {
                 color
             );
 
+            let params = AnimationParams {
+                mode: args.mode,
+                brightness: opt.brightness,
+                speed: args.speed,
+                color,
+                rainbow: args.rainbow,
+            };
+            match opt.transition {
+                Some(duration) => {
+                    transition(
+                        &keyboard,
+                        LightingState::Animation(params.clone()),
+                        duration,
+                        params.brightness.clone(),
+                    )?;
+                }
+                None => keyboard
+                    .set_led_animation(
+                        params.mode.clone(),
+                        params.brightness.clone(),
+                        params.speed.clone(),
+                        params.color.clone(),
+                        params.rainbow,
+                    )
+                    .context("Failed to set led animation")?,
+            }
+            let saved = SavedLighting::from(Scene::from(LightingState::Animation(params)));
+            state::record(keyboard.device_id(), &saved)?;
+        }
+        Command::Common(CliCommand::Script(args)) => {
+            let (name, source) = effects::load_script(&args.name)?;
+            let mut effect = ScriptEffect::new(&name, &source)?;
+            let saved = SavedLighting {
+                effect: Some(saved_effect(
+                    name,
+                    RpcEffectSource::Script(source),
+                    &args.playback,
+                )),
+                ..Default::default()
+            };
+            state::record(keyboard.device_id(), &saved)?;
+            play_effect(&keyboard, &mut effect, &args.playback)?;
+        }
+        Command::Common(CliCommand::AnimationFile(args)) => {
+            let (name, animation) = effects::load_animation(&args.file_path)?;
+            let mut player = KeyframePlayer::new(&name, &animation)?;
+            let saved = SavedLighting {
+                effect: Some(saved_effect(
+                    name,
+                    RpcEffectSource::Keyframes(animation),
+                    &args.playback,
+                )),
+                ..Default::default()
+            };
+            state::record(keyboard.device_id(), &saved)?;
+            play_effect(&keyboard, &mut player, &args.playback)?;
+        }
+        Command::Common(CliCommand::Scene(args)) => {
+            let scene = effects::load_scene(&args.file_path)?;
+            if let Some(duration) = opt.transition {
+                transition(
+                    &keyboard,
+                    scene.lighting_state(),
+                    duration,
+                    scene.brightness.clone(),
+                )?;
+            }
             keyboard
-                .set_led_animation(args.mode, opt.brightness, args.speed, color, args.rainbow)
-                .context("Failed to set led animation")?;
+                .apply_scene(&scene)
+                .context("Failed to apply scene")?;
+            state::record(keyboard.device_id(), &SavedLighting::from(scene))?;
+        }
+        Command::Common(CliCommand::Restore) => {
+            apply_saved(&keyboard, state::read_state(keyboard.device_id())?)?
+        }
+        Command::Common(CliCommand::Undo) => {
+            match state::step_history(keyboard.device_id(), History::undo)? {
+                Some(saved) => apply_saved(&keyboard, saved)?,
+                None => println!("Nothing to undo"),
+            }
+        }
+        Command::Common(CliCommand::Redo) => {
+            match state::step_history(keyboard.device_id(), History::redo)? {
+                Some(saved) => apply_saved(&keyboard, saved)?,
+                None => println!("Nothing to redo"),
+            }
+        }
+        Command::Common(CliCommand::History) => print!("{}", state::history(keyboard.device_id())?),
+        Command::Common(CliCommand::Profile(ProfileCommand::Save(args))) => {
+            let path = match args.file {
+                Some(file) => profiles::import(&args.name, &file)?,
+                None => match state::read_state(keyboard.device_id())?.scene {
+                    Some(Scene {
+                        mode: LightingMode::Custom,
+                        keys: Some(keys),
+                        ..
+                    }) => profiles::save(&args.name, &keys)?,
+                    _ => return Err(anyhow!("Last applied lighting has no custom colors")),
+                },
+            };
+            println!("Saved profile {} to {path:?}", args.name);
+        }
+        Command::Common(CliCommand::Profile(ProfileCommand::Load(args))) => {
+            let args = ColorProfileFileOptions {
+                keep_existing: args.keep_existing,
+                file_path: profiles::path(&args.name)?,
+                playback: args.playback,
+            };
+            set_color_profile(
+                &keyboard,
+                &args,
+                opt.brightness,
+                opt.software_brightness,
+                opt.transition,
+            )?;
+        }
+        Command::Common(CliCommand::Profile(ProfileCommand::List)) => {
+            for name in profiles::list()? {
+                println!("{name}");
+            }
+        }
+        Command::Common(CliCommand::Profile(ProfileCommand::Delete(args))) => {
+            profiles::delete(&args.name)?
+        }
+        Command::Common(CliCommand::Profile(ProfileCommand::Show(args))) => {
+            print!("{}", profiles::show(&args.name)?)
+        }
+        Command::Calibrate => calibrate::run_wizard(&mut keyboard, vendor_id, product_id)?,
+        Command::Raw(args) => {
+            let data = cherryrgb::hex::decode(args.data.concat()).context("Invalid hex data")?;
+            let response = keyboard.send_raw(args.payload_type, &data, args.transaction)?;
+            println!("{}", cherryrgb::hex::encode(&response.data));
+            match response.packet {
+                Some(packet) => println!(
+                    "{:?} Checksum valid: {}",
+                    packet.payload(),
+                    packet.verify_checksum().is_ok()
+                ),
+                None => println!("Failed to parse response"),
+            }
         }
     }
 
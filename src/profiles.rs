@@ -0,0 +1,100 @@
+use anyhow::{anyhow, Context, Error};
+use cherryrgb::{CustomKeyLeds, FileFormat, Profile};
+use std::path::{Path, PathBuf};
+
+/// Extensions of profile files, new profiles are saved as TOML
+const PROFILE_EXTENSIONS: [&str; 4] = ["toml", "json", "yaml", "yml"];
+
+/// Return the directory of the profile library
+pub fn profiles_dir() -> Result<PathBuf, Error> {
+    match dirs::config_dir() {
+        Some(path) => Ok(path.join("cherryrgb").join("profiles")),
+        None => Err(anyhow!("Could not get config directory")),
+    }
+}
+
+/// Names become file names, so they must not address other directories
+fn check_name(name: &str) -> Result<(), Error> {
+    if name.is_empty() || name.starts_with('.') || name.contains(['/', '\\']) {
+        return Err(anyhow!("Invalid profile name '{name}'"));
+    }
+    Ok(())
+}
+
+/// Return the file of an existing profile
+pub fn path(name: &str) -> Result<PathBuf, Error> {
+    check_name(name)?;
+    let dir = profiles_dir()?;
+    PROFILE_EXTENSIONS
+        .iter()
+        .map(|ext| dir.join(format!("{name}.{ext}")))
+        .find(|path| path.is_file())
+        .ok_or_else(|| anyhow!("Profile '{name}' not found in {dir:?}"))
+}
+
+/// Names of all profiles, sorted
+pub fn list() -> Result<Vec<String>, Error> {
+    let dir = profiles_dir()?;
+    if !dir.is_dir() {
+        return Ok(vec![]);
+    }
+    let mut names: Vec<String> = std::fs::read_dir(&dir)
+        .context(format!("reading {dir:?}"))?
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| {
+            matches!(path.extension().and_then(|ext| ext.to_str()),
+                Some(ext) if PROFILE_EXTENSIONS.contains(&ext))
+        })
+        .filter_map(|path| Some(path.file_stem()?.to_string_lossy().into_owned()))
+        .collect();
+    names.sort();
+    names.dedup();
+    Ok(names)
+}
+
+/// Write profile file, replacing a profile with the same name in any format
+fn write(name: &str, extension: &str, content: &str) -> Result<PathBuf, Error> {
+    check_name(name)?;
+    let dir = profiles_dir()?;
+    std::fs::create_dir_all(&dir)?;
+    if let Ok(existing) = path(name) {
+        std::fs::remove_file(existing)?;
+    }
+    let path = dir.join(format!("{name}.{extension}"));
+    std::fs::write(&path, content).context(format!("writing profile {path:?}"))?;
+    log::debug!("Saved profile {name} to {path:?}");
+    Ok(path)
+}
+
+/// Save custom colors as profile
+pub fn save(name: &str, key_leds: &CustomKeyLeds) -> Result<PathBuf, Error> {
+    let content = Profile::from_key_leds(key_leds).to_text(FileFormat::Toml)?;
+    write(name, "toml", &content)
+}
+
+/// Copy a profile file into the library, after checking that it can be read
+pub fn import(name: &str, file: &Path) -> Result<PathBuf, Error> {
+    let content = std::fs::read_to_string(file).context(format!("color profile {file:?}"))?;
+    let format = FileFormat::from_path(file);
+    Profile::parse(&content, format)
+        .and_then(|profile| profile.profile_keys())
+        .context(format!("reading color profile {file:?}"))?;
+    let extension = match format {
+        FileFormat::Json => "json",
+        FileFormat::Toml => "toml",
+        FileFormat::Yaml => "yaml",
+    };
+    write(name, extension, &content)
+}
+
+/// Delete profile
+pub fn delete(name: &str) -> Result<(), Error> {
+    let path = path(name)?;
+    std::fs::remove_file(&path).context(format!("deleting profile {path:?}"))
+}
+
+/// Return the content of the profile file
+pub fn show(name: &str) -> Result<String, Error> {
+    let path = path(name)?;
+    std::fs::read_to_string(&path).context(format!("reading profile {path:?}"))
+}
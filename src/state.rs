@@ -1,41 +1,104 @@
-use anyhow::{anyhow, Error};
-use cherryrgb::CustomKeyLeds;
-use std::{fs::File, io::BufReader, path::PathBuf};
+use crate::lockfile::lock;
+use anyhow::{anyhow, Context, Error};
+use cherryrgb::{CustomKeyLeds, History, SavedLighting};
+use std::path::{Path, PathBuf};
 
-const STATEFILE: &str = "cherryrgb_state.json";
-
-/// Return the state directory
+/// Return the state directory, $XDG_STATE_HOME/cherryrgb
 fn state_dir() -> Result<PathBuf, Error> {
-    match dirs::cache_dir() {
-        Some(path) => Ok(path),
-        None => Err(anyhow!("Could not get cache directory")),
+    match dirs::state_dir().or_else(dirs::data_local_dir) {
+        Some(path) => Ok(path.join("cherryrgb")),
+        None => Err(anyhow!("Could not get state directory")),
+    }
+}
+
+/// Return the state file of a keyboard, see `CherryKeyboard::device_id`
+fn state_file(device_id: &str) -> Result<PathBuf, Error> {
+    Ok(state_dir()?.join(SavedLighting::file_name(device_id)))
+}
+
+/// Return the undo history file of a keyboard, it shares the lock with the state file
+fn history_file(state_file: &Path) -> PathBuf {
+    state_file.with_extension("history.json")
+}
+
+fn read_history(state_file: &Path) -> Result<History, Error> {
+    let path = history_file(state_file);
+    if !path.exists() {
+        return Ok(History::default());
     }
+    Ok(History::load(&path)?)
 }
 
-/// Save custom colors to file
-pub fn save(leds: CustomKeyLeds) -> Result<(), Error> {
-    let dir = state_dir()?;
-    std::fs::create_dir_all(&dir)?;
-    let file = dir.join(STATEFILE);
-    log::debug!("Saving state to {file:?}");
-    serde_json::to_writer(&File::create(file)?, &leds)?;
+/// Save lighting of a keyboard to file.
+/// The file is replaced atomically, so readers never see a partially written state.
+// cherryrgb_cli adds to the history as well, see `record`
+#[allow(dead_code)]
+pub fn save(device_id: &str, lighting: &SavedLighting) -> Result<(), Error> {
+    let path = state_file(device_id)?;
+    let _lock = lock(&path, true).context(format!("locking {path:?}"))?;
+    log::debug!("Saving state to {path:?}");
+    lighting.save(&path)?;
     Ok(())
 }
 
-/// Read custom colors from file
-fn read_state() -> Result<CustomKeyLeds, Error> {
-    let path = state_dir()?.join(STATEFILE);
-    let file = File::open(path.clone())?;
-    let reader = BufReader::new(file);
-    let ret = serde_json::from_reader(reader)?;
+/// Save lighting of a keyboard to file and add it to the undo history
+// cherryrgb_ncli leaves the history to the service
+#[allow(dead_code)]
+pub fn record(device_id: &str, lighting: &SavedLighting) -> Result<(), Error> {
+    let path = state_file(device_id)?;
+    let _lock = lock(&path, true).context(format!("locking {path:?}"))?;
+    log::debug!("Saving state to {path:?}");
+    lighting.save(&path)?;
+    let mut history = read_history(&path)?;
+    history.push(lighting.clone());
+    history.save(&history_file(&path))?;
+    Ok(())
+}
+
+/// Step through the undo history of a keyboard (see `History::undo`, `History::redo`),
+/// the lighting stepped to becomes the state
+// cherryrgb_ncli uses the history of the service instead
+#[allow(dead_code)]
+pub fn step_history<F>(device_id: &str, step: F) -> Result<Option<SavedLighting>, Error>
+where
+    F: FnOnce(&mut History) -> Option<&SavedLighting>,
+{
+    let path = state_file(device_id)?;
+    let _lock = lock(&path, true).context(format!("locking {path:?}"))?;
+    let mut history = read_history(&path)?;
+    let lighting = match step(&mut history) {
+        Some(lighting) => lighting.clone(),
+        None => return Ok(None),
+    };
+    lighting.save(&path)?;
+    history.save(&history_file(&path))?;
+    Ok(Some(lighting))
+}
+
+/// Read the undo history of a keyboard
+#[allow(dead_code)]
+pub fn history(device_id: &str) -> Result<History, Error> {
+    let path = state_file(device_id)?;
+    let _lock = lock(&path, false).context(format!("locking {path:?}"))?;
+    read_history(&path)
+}
+
+/// Read last applied lighting of a keyboard from file
+pub fn read_state(device_id: &str) -> Result<SavedLighting, Error> {
+    let path = state_file(device_id)?;
+    if !path.exists() {
+        return Err(anyhow!("No state saved yet in {path:?}"));
+    }
+    let _lock = lock(&path, false).context(format!("locking {path:?}"))?;
+    let ret = SavedLighting::load(&path)?;
     log::debug!("Loaded state from {path:?}");
     Ok(ret)
 }
 
-/// Load custom colors from file or create a ne instance
-pub fn load() -> Result<CustomKeyLeds, Error> {
-    if let Ok(ret) = read_state() {
-        return Ok(ret);
+/// Load custom colors of a keyboard from file or create a new instance
+pub fn load(device_id: &str) -> Result<CustomKeyLeds, Error> {
+    if let Ok(ret) = read_state(device_id) {
+        return Ok(ret.keys());
     }
     let ret = CustomKeyLeds::new();
     Ok(ret)